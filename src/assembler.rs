@@ -9,42 +9,147 @@ use token::{Token, TokenKind};
 use parser::{Parser, ParserError};
 use instruction::{Instruction, InstructionError};
 use symbol_table::SymbolTable;
+use symbol_file::Import;
+use platform::Platform;
+use lint::{self, Warning};
 
 type Result<T> = std::result::Result<T, AssemblerError>;
 
 pub struct Assembler {
     symbols: SymbolTable,
+    instructions: Vec<Instruction>,
+    words: Vec<u16>,
+    source_lines: Vec<u32>,
+    directives: Vec<Token>,
+    platform: Platform,
 }
 
 impl Assembler {
+    /// An assembler for the standard Hack computer. Everything else goes by
+    /// the platform it's given.
+    #[cfg(test)]
     pub fn new() -> Assembler {
+        Assembler::with_platform(&Platform::hack())
+    }
+
+    /// An assembler for a platform's predefined symbols, variable range and
+    /// ROM size
+    pub fn with_platform(platform: &Platform) -> Assembler {
         Assembler {
-            symbols: SymbolTable::new(),
+            symbols: SymbolTable::with_platform(platform),
+            instructions: Vec::new(),
+            words: Vec::new(),
+            source_lines: Vec::new(),
+            directives: Vec::new(),
+            platform: platform.clone(),
         }
     }
 
+    pub fn platform(&self) -> &Platform {
+        &self.platform
+    }
+
+    /// Adds a predefined symbol for the assemblies that follow
+    pub fn define(&mut self, symbol: &str, address: u16) {
+        self.symbols.define(symbol, address);
+    }
+
+    /// Makes using a symbol that isn't a label, predefined, imported or
+    /// declared with `.var` an error rather than a new variable
+    pub fn set_strict(&mut self, strict: bool) {
+        self.symbols.set_strict(strict);
+    }
+
+    /// Adds symbols from another build, whose RAM variables won't be allocated
+    /// over. Fails on a name that's predefined or already imported.
+    pub fn import(&mut self, imports: &[Import]) -> std::result::Result<(), String> {
+        self.symbols.import(imports)
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// The instructions parsed during the last assembly
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// The machine code produced by the last assembly, indexed by ROM address
+    pub fn words(&self) -> &[u16] {
+        &self.words
+    }
+
+    /// The source line each word of machine code was assembled from
+    pub fn source_lines(&self) -> &[u32] {
+        &self.source_lines
+    }
+
+    /// Runs the lint pass over the last assembly
+    pub fn lint(&self) -> Vec<Warning> {
+        lint::lint(&self.instructions, &self.symbols)
+    }
+
+    /// The directives of the last assembly, which the instructions leave out
+    pub fn directives(&self) -> &[Token] {
+        &self.directives
+    }
+
+    /// The names declared with `.var` and the lines they're declared on
+    pub fn declarations(&self) -> Vec<(String, u32)> {
+        self.directives.iter().filter_map(|token| match token.kind {
+            TokenKind::Directive(ref text) => text.strip_prefix("var")
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+                .and_then(parse_declaration)
+                .map(|(name, _, _)| (name, token.line)),
+            _ => None,
+        }).collect()
+    }
+
     pub fn assemble_file(&mut self, filepath: &str) -> Result<()> {
         let filename = filepath.split("/").last().unwrap();
         let filename = filename.split(".").next().unwrap();
 
         let file = File::open(filepath)?;
-
-        let tokens = self.tokenize(file)?;
-        let instructions = self.parse(tokens)?;
-        let output_string = self.encode_binary(&instructions)?;
+        let output_string = self.assemble(file)?;
         self.write_file(filename, output_string)?;
 
         Ok(())
     }
 
-    fn tokenize(&mut self, file: File) -> Result<Vec<Token>> {
+    /// Assembles the source into the text of a .hack file
+    pub fn assemble<R: Read>(&mut self, reader: R) -> Result<String> {
+        self.instructions = self.load(reader)?;
+        self.encode_binary()
+    }
+
+    /// Parses the source into instructions, records the address of every
+    /// label and reserves RAM for the declared variables
+    pub fn load<R: Read>(&mut self, reader: R) -> Result<Vec<Instruction>> {
+        let tokens = self.tokenize(reader)?;
+        let instructions = self.parse(tokens)?;
+        self.resolve_labels(&instructions)?;
+        self.declare_variables()?;
+        Ok(instructions)
+    }
+
+    fn tokenize<R: Read>(&mut self, reader: R) -> Result<Vec<Token>> {
         let mut tokens = Vec::new();
-        let mut sl = Scanlines::new(file);
+        self.directives.clear();
+        let mut sl = Scanlines::new(reader);
         while let Some(line) = sl.next() {
             let mut line_tokens = Vec::new();
-            let mut line = line?;
+            let line = line?;
             for result in line {
                 let result = result?;
+                match result.kind {
+                    TokenKind::Comment(_) => continue,
+                    TokenKind::Directive(_) => {
+                        self.directives.push(result);
+                        continue;
+                    },
+                    _ => (),
+                }
                 line_tokens.push(result);
             }
             if !line_tokens.is_empty() && line_tokens[0].kind != TokenKind::NewLine {
@@ -66,20 +171,59 @@ impl Assembler {
         Ok(p.parse()?)
     }
 
-    fn encode_binary(&mut self, instructions: &Vec<Instruction>) -> Result<String> {
-        let mut out = String::new();
-
+    fn resolve_labels(&mut self, instructions: &[Instruction]) -> Result<()> {
         let mut rom_address: u16 = 0;
         for i in instructions {
             match i.symbol_string() {
+                Some(s) if self.symbols.is_imported(s) => {
+                    return Err(InstructionError::new(&format!("{} is already defined by an import", s), i.line()).into());
+                },
                 Some(s) => self.symbols.add_symbol(s, rom_address),
                 None => rom_address += 1,
             };
         }
+        Ok(())
+    }
 
-        for i in instructions {
-            if let Some(b) = i.binary_string(&mut self.symbols)? {
-                out.push_str(&format!("{}\n", b));
+    /// Reserves RAM for each `.var NAME`, `.var NAME[WORDS]` or
+    /// `.var NAME @ ADDRESS`, placing those at fixed addresses first so the
+    /// rest go around them
+    fn declare_variables(&mut self) -> Result<()> {
+        let mut declarations = Vec::new();
+        for token in &self.directives {
+            let text = match token.kind {
+                TokenKind::Directive(ref text) => text,
+                _ => continue,
+            };
+            let mut words = text.splitn(2, char::is_whitespace);
+            if words.next() != Some("var") {
+                continue;
+            }
+            let declaration = parse_declaration(words.next().unwrap_or(""))
+                .ok_or_else(|| ParserError::new(token.clone(), "Usage: .var NAME[WORDS] [@ ADDRESS]"))?;
+            declarations.push((token.line, declaration));
+        }
+        declarations.sort_by_key(|(_, (_, address, _))| address.is_none());
+        for (line, (name, address, words)) in declarations {
+            self.symbols.declare(&name, address, words).map_err(|e| InstructionError::new(&e, line))?;
+        }
+        Ok(())
+    }
+
+    fn encode_binary(&mut self) -> Result<String> {
+        let mut out = String::new();
+        self.words.clear();
+        self.source_lines.clear();
+
+        for i in &self.instructions {
+            if let Some(b) = i.binary(&mut self.symbols)? {
+                if self.words.len() == self.platform.rom_size {
+                    let description = format!("The program is longer than the {} words of ROM", self.platform.rom_size);
+                    return Err(InstructionError::new(&description, i.line()).into());
+                }
+                out.push_str(&format!("{:016b}\n", b));
+                self.words.push(b);
+                self.source_lines.push(i.line());
             }
         }
 
@@ -93,6 +237,21 @@ impl Assembler {
     }
 }
 
+/// Parses the name, fixed address and size of a `.var` declaration
+fn parse_declaration(text: &str) -> Option<(String, Option<u16>, u16)> {
+    let (name, address) = match text.split_once('@') {
+        Some((name, address)) => (name.trim(), Some(address.trim().parse::<u16>().ok()?)),
+        None => (text.trim(), None),
+    };
+    let (name, words) = match name.strip_suffix(']').and_then(|n| n.split_once('[')) {
+        Some((name, words)) => (name.trim(), words.trim().parse::<u16>().ok().filter(|&w| w > 0)?),
+        None => (name, 1),
+    };
+    let symbol = name.starts_with(|c: char| !c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "_.$:".contains(c));
+    if symbol { Some((name.to_string(), address, words)) } else { None }
+}
+
 #[derive(Debug)]
 pub enum AssemblerError {
     IoError(io::Error),
@@ -114,6 +273,18 @@ impl std::fmt::Display for AssemblerError {
 
 impl std::error::Error for AssemblerError {}
 
+impl AssemblerError {
+    /// The source line the error was found on, if it came from the source
+    pub fn line(&self) -> Option<u32> {
+        match self {
+            AssemblerError::IoError(_) => None,
+            AssemblerError::ScanError(err) => Some(err.line()),
+            AssemblerError::ParseError(err) => Some(err.line()),
+            AssemblerError::InstructionError(err) => Some(err.line()),
+        }
+    }
+}
+
 impl convert::From<io::Error> for AssemblerError {
     fn from(error: io::Error) -> Self {
         AssemblerError::IoError(error)
@@ -0,0 +1,357 @@
+use emulator::{self, Emulator, ROM_SIZE};
+
+/// The longest run of instructions decoded into one block, so a block never
+/// holds up a cycle limit for long
+const MAX_BLOCK_LEN: usize = 64;
+
+const DEST_A: u8 = 0x4;
+const DEST_D: u8 = 0x2;
+const DEST_M: u8 = 0x1;
+
+/// A C-instruction's comp bits, with y the A or M operand
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comp {
+    Zero,
+    One,
+    MinusOne,
+    D,
+    Y,
+    NotD,
+    NotY,
+    NegD,
+    NegY,
+    DPlusOne,
+    YPlusOne,
+    DMinusOne,
+    YMinusOne,
+    DPlusY,
+    DMinusY,
+    YMinusD,
+    DAndY,
+    DOrY,
+    /// Comp bits without a mnemonic, left to the ALU
+    Other(u16),
+}
+
+impl Comp {
+    fn decode(instruction: u16) -> Comp {
+        match instruction & 0xFC0 {
+            0xA80 => Comp::Zero,
+            0xFC0 => Comp::One,
+            0xE80 => Comp::MinusOne,
+            0x300 => Comp::D,
+            0xC00 => Comp::Y,
+            0x340 => Comp::NotD,
+            0xC40 => Comp::NotY,
+            0x3C0 => Comp::NegD,
+            0xCC0 => Comp::NegY,
+            0x7C0 => Comp::DPlusOne,
+            0xDC0 => Comp::YPlusOne,
+            0x380 => Comp::DMinusOne,
+            0xC80 => Comp::YMinusOne,
+            0x080 => Comp::DPlusY,
+            0x4C0 => Comp::DMinusY,
+            0x1C0 => Comp::YMinusD,
+            0x000 => Comp::DAndY,
+            0x540 => Comp::DOrY,
+            _ => Comp::Other(instruction),
+        }
+    }
+
+    #[inline(always)]
+    fn apply(self, d: u16, y: u16) -> u16 {
+        match self {
+            Comp::Zero => 0,
+            Comp::One => 1,
+            Comp::MinusOne => 0xFFFF,
+            Comp::D => d,
+            Comp::Y => y,
+            Comp::NotD => !d,
+            Comp::NotY => !y,
+            Comp::NegD => d.wrapping_neg(),
+            Comp::NegY => y.wrapping_neg(),
+            Comp::DPlusOne => d.wrapping_add(1),
+            Comp::YPlusOne => y.wrapping_add(1),
+            Comp::DMinusOne => d.wrapping_sub(1),
+            Comp::YMinusOne => y.wrapping_sub(1),
+            Comp::DPlusY => d.wrapping_add(y),
+            Comp::DMinusY => d.wrapping_sub(y),
+            Comp::YMinusD => y.wrapping_sub(d),
+            Comp::DAndY => d & y,
+            Comp::DOrY => d | y,
+            Comp::Other(instruction) => emulator::alu(d, y, instruction),
+        }
+    }
+}
+
+/// A decoded C-instruction, less its jump
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Compute {
+    comp: Comp,
+    /// Reads M rather than A
+    memory: bool,
+    dest: u8,
+}
+
+impl Compute {
+    fn decode(instruction: u16) -> Compute {
+        Compute {
+            comp: Comp::decode(instruction),
+            memory: instruction & 0x1000 != 0,
+            dest: ((instruction >> 3) & 0x7) as u8,
+        }
+    }
+
+    /// Executes the instruction, returning the ALU output
+    #[inline(always)]
+    fn execute(self, e: &mut Emulator) -> u16 {
+        let address = e.a;
+        let y = if self.memory { e.load(address) } else { address };
+        let out = self.comp.apply(e.d, y);
+        if self.dest & DEST_M != 0 {
+            e.store(address, out);
+        }
+        if self.dest & DEST_A != 0 {
+            e.a = out;
+        }
+        if self.dest & DEST_D != 0 {
+            e.d = out;
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    /// An A-instruction
+    Load(u16),
+    /// A C-instruction without a jump
+    Compute(Compute),
+    /// An A-instruction followed by a C-instruction using it, the most
+    /// common pair, like `@SP` `M=M+1`
+    LoadCompute(u16, Compute),
+}
+
+/// How a block ends
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Exit {
+    /// Falls through to the next address, which starts another block
+    Next(u16),
+    /// A C-instruction with a jump, falling through to the next address when
+    /// it doesn't jump
+    Jump { compute: Compute, condition: u16, next: u16 },
+    /// An unconditional jump to the address loaded right before it, like
+    /// `@LOOP` `0;JMP`
+    Goto(Compute, u16),
+}
+
+/// Straight-line instructions up to and including a jump
+#[derive(Debug, Clone, PartialEq)]
+struct Block {
+    ops: Vec<Op>,
+    exit: Exit,
+    /// Instructions in the block, counting the exit's jump
+    cycles: u64,
+    /// Starts at an instruction the program may halt on
+    halts: bool,
+}
+
+/// A program's basic blocks, decoded the first time execution reaches them,
+/// so that running doesn't decode every word each cycle
+pub struct Blocks {
+    /// The block starting at each ROM address, by index into `blocks`
+    index: Vec<u32>,
+    blocks: Vec<Block>,
+}
+
+const UNDECODED: u32 = u32::MAX;
+
+/// Determines if the instruction at an address is the jump of the
+/// `(END) @END 0;JMP` idiom, where the emulator may halt
+fn may_halt(e: &Emulator, address: u16) -> bool {
+    let previous = address.wrapping_sub(1);
+    e.instruction(address) & 0xE007 == 0xE007 && e.instruction(previous) == previous
+}
+
+impl Blocks {
+    pub fn new() -> Blocks {
+        Blocks { index: vec![UNDECODED; ROM_SIZE], blocks: Vec::new() }
+    }
+
+    /// Decodes the block starting at an address. A block stops short of an
+    /// instruction the program may halt on, so the emulator can check for it.
+    fn decode(e: &Emulator, start: u16) -> Block {
+        let mut ops = Vec::new();
+        let mut address = start;
+        let mut cycles = 0;
+        let exit = loop {
+            if cycles > 0 && may_halt(e, address) || cycles == MAX_BLOCK_LEN as u64 {
+                break Exit::Next(address);
+            }
+            let instruction = e.instruction(address);
+            cycles += 1;
+            let next = address.wrapping_add(1);
+
+            if instruction & 0x8000 == 0 {
+                ops.push(Op::Load(instruction));
+            } else if instruction & 0x7 == 0 {
+                let compute = Compute::decode(instruction);
+                match ops.last() {
+                    Some(&Op::Load(value)) => *ops.last_mut().unwrap() = Op::LoadCompute(value, compute),
+                    _ => ops.push(Op::Compute(compute)),
+                }
+            } else {
+                let compute = Compute::decode(instruction);
+                let condition = instruction & 0x7;
+                break match ops.last() {
+                    Some(&Op::Load(target)) if condition == 7 && compute.dest & DEST_A == 0 => {
+                        ops.pop();
+                        Exit::Goto(compute, target)
+                    },
+                    _ => Exit::Jump { compute, condition, next },
+                };
+            }
+            address = next;
+            // Past the end of ROM the PC keeps counting up, so a block can't
+            // run across it, nor past the end of a smaller board's ROM
+            if address as usize & (ROM_SIZE - 1) == 0 || address as usize & (ROM_SIZE - 1) >= e.rom_size() {
+                break Exit::Next(address);
+            }
+        };
+        Block { ops, exit, cycles, halts: may_halt(e, start) }
+    }
+
+    /// Runs the emulator until it halts or has run for `max_cycles` in
+    /// total, a block at a time. Near the limit it steps one instruction at
+    /// a time so it stops at exactly the same cycle as stepping would.
+    pub fn run(&mut self, e: &mut Emulator, max_cycles: u64) {
+        while e.cycles < max_cycles {
+            let slot = e.pc as usize & (ROM_SIZE - 1);
+            if e.pc as usize >= ROM_SIZE || slot >= e.rom_size() {
+                if e.halted() {
+                    return;
+                }
+                e.step();
+                continue;
+            }
+            if self.index[slot] == UNDECODED {
+                self.index[slot] = self.blocks.len() as u32;
+                self.blocks.push(Blocks::decode(e, e.pc));
+            }
+            let block = &self.blocks[self.index[slot] as usize];
+            if block.halts && e.halted() {
+                return;
+            }
+            if e.cycles + block.cycles > max_cycles {
+                while e.cycles < max_cycles && !e.halted() {
+                    e.step();
+                }
+                return;
+            }
+
+            // The count goes up before each instruction's memory access, as
+            // when stepping, so devices see the cycle they're read on
+            for op in &block.ops {
+                match *op {
+                    Op::Load(value) => {
+                        e.a = value;
+                        e.cycles += 1;
+                    },
+                    Op::Compute(compute) => {
+                        e.cycles += 1;
+                        compute.execute(e);
+                    },
+                    Op::LoadCompute(value, compute) => {
+                        e.a = value;
+                        e.cycles += 2;
+                        compute.execute(e);
+                    },
+                }
+            }
+            e.pc = match block.exit {
+                Exit::Next(next) => next,
+                Exit::Jump { compute, condition, next } => {
+                    let target = e.a;
+                    e.cycles += 1;
+                    let out = compute.execute(e);
+                    if emulator::jumps(condition, out) { target & 0x7FFF } else { next }
+                },
+                Exit::Goto(compute, target) => {
+                    e.a = target;
+                    e.cycles += 2;
+                    compute.execute(e);
+                    target & 0x7FFF
+                },
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use assembler::Assembler;
+    use emulator::Emulator;
+    use blocks::{Blocks, Exit, Op};
+    use device::Timer;
+
+    fn load(path: &str) -> Emulator {
+        let mut a = Assembler::new();
+        a.assemble(File::open(path).unwrap()).unwrap();
+        Emulator::new(a.words())
+    }
+
+    /// Runs a program by blocks and by single steps, which must agree
+    fn compare(mut blocks: Emulator, mut steps: Emulator, max_cycles: u64) -> Emulator {
+        Blocks::new().run(&mut blocks, max_cycles);
+        while steps.cycles < max_cycles && !steps.halted() {
+            steps.step();
+        }
+        assert_eq!((blocks.pc, blocks.a, blocks.d, blocks.cycles), (steps.pc, steps.a, steps.d, steps.cycles));
+        assert!(blocks.ram == steps.ram);
+        blocks
+    }
+
+    #[test]
+    fn decodes() {
+        let e = load("test/max/Max.asm");
+        let first = Blocks::decode(&e, 0);
+        assert_eq!(first.cycles, 6);
+        assert_eq!(first.ops.len(), 3);
+        assert!(matches!(first.ops[0], Op::LoadCompute(0, _)));
+        assert_eq!(first.ops[2], Op::Load(10));
+        assert!(matches!(first.exit, Exit::Jump { condition: 1, next: 6, .. }));
+        assert!(matches!(Blocks::decode(&e, 6).exit, Exit::Goto(_, 12)));
+        // Stops before the halting jump
+        assert_eq!(Blocks::decode(&e, 12).exit, Exit::Next(15));
+    }
+
+    #[test]
+    fn matches_stepping() {
+        for &(x, y) in &[(3, 7), (12, -4i16 as u16)] {
+            let mut e = load("test/max/Max.asm");
+            e.ram[0] = x;
+            e.ram[1] = y;
+            let mut f = load("test/max/Max.asm");
+            f.ram[0] = x;
+            f.ram[1] = y;
+            assert!(compare(e, f, u64::MAX).halted());
+        }
+        let e = compare(load("test/pong/Pong.asm"), load("test/pong/Pong.asm"), 1_000_003);
+        assert_eq!(e.cycles, 1_000_003);
+    }
+
+    #[test]
+    fn matches_stepping_with_timer() {
+        // Sums the timer's readings, which a single stale cycle count changes
+        let mut a = Assembler::new();
+        a.assemble("(LOOP)\n@24577\nD=M\n@R0\nM=D+M\n@LOOP\n0;JMP\n".as_bytes()).unwrap();
+        let timed = || {
+            let mut e = Emulator::new(a.words());
+            e.attach(Box::new(Timer::new(24577))).unwrap();
+            e
+        };
+        let e = compare(timed(), timed(), 10_000);
+        assert_eq!(e.ram[0], 7503);
+    }
+}
@@ -0,0 +1,147 @@
+use token::TokenKind;
+use instruction::Instruction;
+use symbol_table::SymbolTable;
+use lint::{self, AValue};
+
+/// Encoding of `D=A`, which follows the `@RETURN` of the call idiom
+const D_EQUALS_A: u16 = 0xEC10;
+
+/// Where a jumping instruction sends control
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// The jump address is known statically
+    Known(u16),
+    /// The jump address is computed at runtime
+    Computed,
+}
+
+/// The control flow out of a single instruction in ROM
+#[derive(Debug, Clone, PartialEq)]
+pub struct Node {
+    pub line: u32,
+    pub falls_through: bool,
+    pub jump: Option<Target>,
+}
+
+impl Node {
+    /// The ROM addresses control may continue at, `None` standing for a computed jump
+    pub fn successors(&self, address: u16) -> Vec<Option<u16>> {
+        let mut out = Vec::with_capacity(2);
+        if self.falls_through {
+            out.push(Some(address + 1));
+        }
+        match self.jump {
+            Some(Target::Known(t)) => out.push(Some(t)),
+            Some(Target::Computed) => out.push(None),
+            None => (),
+        }
+        out
+    }
+}
+
+/// The instructions from a label up to the next one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    pub name: String,
+    pub start: u16,
+    pub end: u16,
+    pub first_line: u32,
+    pub last_line: u32,
+}
+
+impl Region {
+    pub fn len(&self) -> u16 {
+        self.end - self.start
+    }
+
+    pub fn contains(&self, address: u16) -> bool {
+        self.start <= address && address < self.end
+    }
+}
+
+/// The control flow graph of a program, indexed by ROM address
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub regions: Vec<Region>,
+}
+
+impl Graph {
+    pub fn build(instructions: &[Instruction], symbols: &SymbolTable) -> Graph {
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut regions: Vec<Region> = Vec::new();
+
+        for (i, a) in instructions.iter().zip(lint::track_a(instructions, symbols)) {
+            let address = nodes.len() as u16;
+            match i {
+                Instruction::Label(_) => {
+                    close_region(&mut regions, address, &nodes);
+                    regions.push(Region {
+                        name: i.symbol_string().unwrap_or("").to_string(),
+                        start: address,
+                        end: address,
+                        first_line: i.line(),
+                        last_line: i.line(),
+                    });
+                    continue;
+                },
+                Instruction::AInstruction(_) => nodes.push(Node {
+                    line: i.line(),
+                    falls_through: true,
+                    jump: None,
+                }),
+                Instruction::CInstruction { dest: _, comp: _, jump } => nodes.push(Node {
+                    line: i.line(),
+                    falls_through: match jump {
+                        Some(j) => j.kind != TokenKind::Jump,
+                        None => true,
+                    },
+                    jump: jump.as_ref().map(|_| match a {
+                        AValue::Label(ref l) => match symbols.label_address(l) {
+                            Some(address) => Target::Known(address),
+                            None => Target::Computed,
+                        },
+                        AValue::Constant(n) if n <= u16::MAX as u32 => Target::Known(n as u16),
+                        _ => Target::Computed,
+                    }),
+                }),
+            }
+
+            // Code before the first label belongs to an unnamed region
+            if regions.is_empty() {
+                regions.push(Region {
+                    name: String::from("(start)"),
+                    start: 0,
+                    end: 0,
+                    first_line: i.line(),
+                    last_line: i.line(),
+                });
+            }
+        }
+        close_region(&mut regions, nodes.len() as u16, &nodes);
+
+        Graph { nodes, regions }
+    }
+}
+
+/// The labels that calls return to: those whose address is loaded right
+/// before `D=A`, sorted by address
+pub fn return_addresses(words: &[u16], symbols: &SymbolTable) -> Vec<u16> {
+    let labels = symbols.labels();
+    let mut addresses: Vec<u16> = words.windows(2)
+        .filter(|w| w[0] & 0x8000 == 0 && w[1] == D_EQUALS_A)
+        .map(|w| w[0])
+        .filter(|&a| labels.iter().any(|&(_, l)| l == a))
+        .collect();
+    addresses.sort_unstable();
+    addresses.dedup();
+    addresses
+}
+
+fn close_region(regions: &mut [Region], end: u16, nodes: &[Node]) {
+    if let Some(r) = regions.last_mut() {
+        r.end = end;
+        if r.end > r.start {
+            r.last_line = nodes[r.end as usize - 1].line;
+        }
+    }
+}
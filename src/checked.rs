@@ -0,0 +1,156 @@
+use std::fmt;
+
+use emulator::{Emulator, MemoryWrite, RAM_SIZE};
+
+/// A bug caught while running a program in checked mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrapKind {
+    /// Read RAM that nothing wrote first
+    UninitializedRead(u16),
+    /// Wrote the keyboard register, which only the keyboard sets
+    KeyboardWrite,
+    /// Read or wrote RAM past the keyboard register
+    OutOfRange(u16),
+    /// Jumped to an address with no instruction
+    JumpOutOfRom(u16),
+    /// Ran off the end of the program instead of halting with
+    /// `(END) @END 0;JMP`
+    Unterminated,
+}
+
+/// A trap and the ROM address of the instruction that caused it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Trap {
+    pub pc: u16,
+    pub kind: TrapKind,
+}
+
+/// Runs an emulator one instruction at a time, trapping on the bugs that
+/// would otherwise silently corrupt its state
+pub struct Checker {
+    program_len: u16,
+    written: Vec<bool>,
+    /// Device registers past the keyboard's
+    mapped: Vec<bool>,
+}
+
+impl Checker {
+    pub fn new(emulator: &Emulator, program_len: usize) -> Checker {
+        // Devices, like the blank screen and the keyboard, always hold a value
+        let mapped: Vec<bool> = (0..RAM_SIZE).map(|a| emulator.mapped(a as u16)).collect();
+        Checker { program_len: program_len as u16, written: mapped.clone(), mapped }
+    }
+
+    /// Marks RAM as initialized, for values set before the program runs
+    pub fn initialize(&mut self, address: u16) {
+        if let Some(w) = self.written.get_mut(address as usize) {
+            *w = true;
+        }
+    }
+
+    /// Executes the instruction at PC unless it would read or write RAM it
+    /// shouldn't. Traps that can only be seen afterwards, like jumping out of
+    /// ROM, leave the emulator past the faulting instruction.
+    pub fn step(&mut self, emulator: &mut Emulator) -> Result<Option<MemoryWrite>, Trap> {
+        let pc = emulator.pc;
+        let instruction = emulator.instruction(pc);
+        let trap = |kind| Err(Trap { pc, kind });
+
+        if instruction & 0x8000 != 0 {
+            let address = emulator.a;
+            let reads = instruction & 0x1000 != 0;
+            let writes = instruction & 0x8 != 0;
+            let keyboard = emulator.platform().keyboard;
+            if (reads || writes) && address > keyboard && !self.mapped.get(address as usize).is_some_and(|&m| m) {
+                return trap(TrapKind::OutOfRange(address));
+            }
+            if reads && !self.written[address as usize] {
+                return trap(TrapKind::UninitializedRead(address));
+            }
+            if writes && address == keyboard {
+                return trap(TrapKind::KeyboardWrite);
+            }
+        }
+
+        let a = emulator.a;
+        let write = emulator.step();
+        if let Some(w) = write {
+            self.written[w.address as usize] = true;
+        }
+        if emulator.pc >= self.program_len {
+            let jumped = instruction & 0x8007 > 0x8000 && emulator.pc == a & 0x7FFF;
+            return trap(if jumped { TrapKind::JumpOutOfRom(emulator.pc) } else { TrapKind::Unterminated });
+        }
+        Ok(write)
+    }
+}
+
+impl fmt::Display for TrapKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrapKind::UninitializedRead(address) => write!(f, "Read of RAM[{}] before anything wrote it", address),
+            TrapKind::KeyboardWrite => write!(f, "Write to the keyboard register"),
+            TrapKind::OutOfRange(address) => write!(f, "Access to RAM[{}] past the keyboard register", address),
+            TrapKind::JumpOutOfRom(address) => write!(f, "Jump to ROM[{}] past the end of the program", address),
+            TrapKind::Unterminated => write!(f, "Ran past the end of the program, which should end with (END) @END 0;JMP"),
+        }
+    }
+}
+
+/// A trap reported against the source line of its instruction
+#[derive(Debug)]
+pub struct RuntimeError {
+    description: String,
+    line: u32,
+}
+
+impl RuntimeError {
+    /// Maps a trap to its source line through the assembler's address map
+    pub fn new(trap: &Trap, source_lines: &[u32]) -> RuntimeError {
+        RuntimeError {
+            description: trap.kind.to_string(),
+            line: source_lines.get(trap.pc as usize).cloned().unwrap_or(0),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Runtime error: [Line {}] {} ", self.line, self.description)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+    use emulator::Emulator;
+    use checked::{Checker, RuntimeError, TrapKind};
+
+    /// Runs a program in checked mode until it traps or halts
+    fn check(source: &str) -> Option<(TrapKind, String)> {
+        let mut a = Assembler::new();
+        a.assemble(source.as_bytes()).unwrap();
+        let mut e = Emulator::new(a.words());
+        let mut checker = Checker::new(&e, a.words().len());
+        while !e.halted() {
+            if let Err(trap) = checker.step(&mut e) {
+                return Some((trap.kind, RuntimeError::new(&trap, a.source_lines()).to_string()));
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn traps() {
+        assert_eq!(check("@x\nM=1\nD=M\n@SCREEN\nM=M+1\n@KBD\nD=M\n(END)\n@END\n0;JMP\n"), None);
+        assert_eq!(check("@x\nM=1\n@y\nD=M\n(END)\n@END\n0;JMP\n"),
+            Some((TrapKind::UninitializedRead(17), String::from("Runtime error: [Line 4] Read of RAM[17] before anything wrote it "))));
+        assert_eq!(check("@KBD\nM=0\n").map(|t| t.0), Some(TrapKind::KeyboardWrite));
+        assert_eq!(check("@24577\nM=0\n").map(|t| t.0), Some(TrapKind::OutOfRange(24577)));
+        assert_eq!(check("@100\n0;JMP\n").map(|t| t.0), Some(TrapKind::JumpOutOfRom(100)));
+        assert_eq!(check("@x\nM=0\n"),
+            Some((TrapKind::Unterminated, String::from("Runtime error: [Line 2] Ran past the end of the program, which should end with (END) @END 0;JMP "))));
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt::Write;
+
+use assembler::Assembler;
+use cfg::{Graph, Region};
+use instruction;
+use emulator::Emulator;
+
+/// Which instructions of a program ran, and which ways its conditional jumps went
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    /// The `.asm` file the program was assembled from
+    pub path: String,
+    words: Vec<u16>,
+    source_lines: Vec<u32>,
+    regions: Vec<Region>,
+    hits: Vec<u64>,
+    /// How many times each jump was taken
+    jumps: Vec<u64>,
+}
+
+/// Both arms of a conditional jump
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub address: u16,
+    pub line: u32,
+    pub jumped: u64,
+    pub fell_through: u64,
+}
+
+impl Coverage {
+    pub fn new(path: &str, assembler: &Assembler) -> Coverage {
+        let words = assembler.words().to_vec();
+        let len = words.len();
+        Coverage {
+            path: path.to_string(),
+            words,
+            source_lines: assembler.source_lines().to_vec(),
+            regions: Graph::build(assembler.instructions(), assembler.symbols()).regions,
+            hits: vec![0; len],
+            jumps: vec![0; len],
+        }
+    }
+
+    /// Counts an executed instruction, given the PC it ran at and the PC after it
+    pub fn record(&mut self, pc: u16, next: u16) {
+        if let Some(h) = self.hits.get_mut(pc as usize) {
+            *h += 1;
+            if next != pc.wrapping_add(1) {
+                self.jumps[pc as usize] += 1;
+            }
+        }
+    }
+
+    /// Counts the jump a halted program is stuck on, which the emulator
+    /// stops before
+    pub fn finish(&mut self, emulator: &Emulator) {
+        if emulator.halted() {
+            self.record(emulator.pc, emulator.pc.wrapping_sub(1));
+        }
+    }
+
+    /// Adds the counts of another run of the same program
+    pub fn merge(&mut self, other: &Coverage) {
+        for (h, o) in self.hits.iter_mut().zip(&other.hits) {
+            *h += o;
+        }
+        for (j, o) in self.jumps.iter_mut().zip(&other.jumps) {
+            *j += o;
+        }
+    }
+
+    /// Determines if a run of another program can be merged into this one
+    pub fn same_program(&self, other: &Coverage) -> bool {
+        self.path == other.path && self.words == other.words
+    }
+
+    /// Every conditional jump in ROM order
+    pub fn branches(&self) -> Vec<Branch> {
+        self.words.iter().enumerate()
+            .filter(|&(_, &w)| w & 0x8000 != 0 && w & 7 != 0 && w & 7 != 7)
+            .map(|(a, _)| Branch {
+                address: a as u16,
+                line: self.source_lines[a],
+                jumped: self.jumps[a],
+                fell_through: self.hits[a] - self.jumps[a],
+            })
+            .collect()
+    }
+
+    /// The execution count of each source line with instructions, from the
+    /// instruction on it that ran most
+    fn lines(&self) -> Vec<(u32, u64)> {
+        let mut lines: Vec<(u32, u64)> = Vec::new();
+        for (&line, &hits) in self.source_lines.iter().zip(&self.hits) {
+            match lines.last_mut() {
+                Some(last) if last.0 == line => last.1 = last.1.max(hits),
+                _ => lines.push((line, hits)),
+            }
+        }
+        lines
+    }
+
+    /// An lcov tracefile record with the labels as functions
+    pub fn to_lcov(&self) -> String {
+        let mut out = format!("TN:\nSF:{}\n", self.path);
+        let regions: Vec<&Region> = self.regions.iter().filter(|r| r.len() > 0).collect();
+        for r in &regions {
+            let _ = writeln!(out, "FN:{},{}", r.first_line, r.name);
+        }
+        for r in &regions {
+            let _ = writeln!(out, "FNDA:{},{}", self.hits[r.start as usize], r.name);
+        }
+        let _ = writeln!(out, "FNF:{}", regions.len());
+        let _ = writeln!(out, "FNH:{}", regions.iter().filter(|r| self.hits[r.start as usize] > 0).count());
+
+        let branches = self.branches();
+        for b in &branches {
+            let ran = b.jumped + b.fell_through > 0;
+            for (arm, count) in [b.jumped, b.fell_through].iter().enumerate() {
+                let taken = if ran { count.to_string() } else { String::from("-") };
+                let _ = writeln!(out, "BRDA:{},{},{},{}", b.line, b.address, arm, taken);
+            }
+        }
+        let _ = writeln!(out, "BRF:{}", branches.len() * 2);
+        let _ = writeln!(out, "BRH:{}", branches.iter().map(|b| (b.jumped > 0) as usize + (b.fell_through > 0) as usize).sum::<usize>());
+
+        let lines = self.lines();
+        for &(line, hits) in &lines {
+            let _ = writeln!(out, "DA:{},{}", line, hits);
+        }
+        let _ = writeln!(out, "LF:{}", lines.len());
+        let _ = writeln!(out, "LH:{}", lines.iter().filter(|l| l.1 > 0).count());
+        out.push_str("end_of_record\n");
+        out
+    }
+
+    /// Lines and branch arms covered under each label, followed by every arm
+    /// that never ran
+    pub fn summary(&self) -> String {
+        let lines = self.lines();
+        let branches = self.branches();
+        let mut out = format!("{}:\n", self.path);
+        let row = |out: &mut String, name: &str, lines: &[&(u32, u64)], branches: &[&Branch]| {
+            let lines_hit = lines.iter().filter(|l| l.1 > 0).count();
+            let arms_hit: usize = branches.iter().map(|b| (b.jumped > 0) as usize + (b.fell_through > 0) as usize).sum();
+            let _ = writeln!(out, "  {:>7} {:>5}/{:<5} {:>5}/{:<5} {}",
+                percent(lines_hit, lines.len()), lines_hit, lines.len(), arms_hit, branches.len() * 2, name);
+        };
+
+        let _ = writeln!(out, "  {:>7} {:^11} {:^11} Label", "", "Lines", "Branches");
+        for r in self.regions.iter().filter(|r| r.len() > 0) {
+            let in_region = |line: u32| line >= r.first_line && line <= r.last_line;
+            let region_lines: Vec<&(u32, u64)> = lines.iter().filter(|l| in_region(l.0)).collect();
+            let region_branches: Vec<&Branch> = branches.iter().filter(|b| r.contains(b.address)).collect();
+            row(&mut out, &r.name, &region_lines, &region_branches);
+        }
+        row(&mut out, "(total)", &lines.iter().collect::<Vec<_>>(), &branches.iter().collect::<Vec<_>>());
+
+        for b in &branches {
+            let text = instruction::disassemble(self.words[b.address as usize], None);
+            if b.jumped == 0 {
+                let _ = writeln!(out, "  Line {}: {} never jumped", b.line, text);
+            }
+            if b.fell_through == 0 {
+                let _ = writeln!(out, "  Line {}: {} never fell through", b.line, text);
+            }
+        }
+        out
+    }
+}
+
+fn percent(hit: usize, total: usize) -> String {
+    if total == 0 {
+        return String::from("-");
+    }
+    format!("{:.1}%", hit as f64 * 100.0 / total as f64)
+}
+
+/// An lcov tracefile for every program covered
+pub fn to_lcov(coverages: &[Coverage]) -> String {
+    coverages.iter().map(|c| c.to_lcov()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use assembler::Assembler;
+    use emulator::Emulator;
+    use coverage::Coverage;
+
+    fn run(a: &Assembler, x: u16, y: u16) -> Coverage {
+        let mut coverage = Coverage::new("test/max/Max.asm", a);
+        let mut e = Emulator::new(a.words());
+        e.ram[0] = x;
+        e.ram[1] = y;
+        while !e.halted() {
+            let pc = e.pc;
+            e.step();
+            coverage.record(pc, e.pc);
+        }
+        coverage.finish(&e);
+        coverage
+    }
+
+    #[test]
+    fn max() {
+        let mut a = Assembler::new();
+        a.assemble(File::open("test/max/Max.asm").unwrap()).unwrap();
+        let mut coverage = run(&a, 3, 7);
+
+        let lcov = coverage.to_lcov();
+        assert!(lcov.starts_with("TN:\nSF:test/max/Max.asm\nFN:8,(start)\nFN:18,OUTPUT_FIRST\n"));
+        assert!(lcov.contains("FNDA:0,OUTPUT_FIRST\n"));
+        assert!(lcov.contains("BRDA:13,5,0,0\nBRDA:13,5,1,1\nBRF:2\nBRH:1\n"));
+        assert!(lcov.contains("DA:19,0\n"));
+        assert!(lcov.ends_with("LF:16\nLH:14\nend_of_record\n"));
+
+        let summary = coverage.summary();
+        assert!(summary.contains("  100.0%    10/10        1/2     (start)\n"));
+        assert!(summary.contains("    0.0%     0/2         0/0     OUTPUT_FIRST\n"));
+        assert!(summary.ends_with("  Line 13: D;JGT never jumped\n"));
+
+        let other = run(&a, 9, 7);
+        assert!(coverage.same_program(&other));
+        coverage.merge(&other);
+        assert!(coverage.to_lcov().contains("BRH:2\n"));
+        assert!(coverage.to_lcov().contains("LH:16\n"));
+    }
+}
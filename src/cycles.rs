@@ -0,0 +1,346 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use cfg::{Graph, Region, Target};
+
+/// An instruction count that may depend on how many times loops iterate.
+/// Each term is keyed by the iteration counts it is multiplied by, the empty
+/// key being the constant part.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cost {
+    terms: BTreeMap<Vec<String>, u64>,
+}
+
+impl Cost {
+    pub fn constant(n: u64) -> Cost {
+        let mut terms = BTreeMap::new();
+        terms.insert(Vec::new(), n);
+        Cost { terms }
+    }
+
+    fn add(&mut self, other: &Cost) {
+        for (k, v) in &other.terms {
+            *self.terms.entry(k.clone()).or_insert(0) += *v;
+        }
+    }
+
+    /// Multiplies every term by the iteration count of a loop
+    fn times(&self, var: &str) -> Cost {
+        let terms = self.terms.iter()
+            .filter(|&(_, v)| *v > 0)
+            .map(|(k, v)| {
+                let mut k = k.clone();
+                k.insert(0, var.to_string());
+                (k, *v)
+            })
+            .collect();
+        Cost { terms }
+    }
+}
+
+impl fmt::Display for Cost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        for (k, v) in &self.terms {
+            if k.is_empty() {
+                if *v > 0 {
+                    parts.push(v.to_string());
+                }
+            } else if *v == 1 {
+                parts.push(k.join("*"));
+            } else if *v > 0 {
+                parts.push(format!("{}*{}", v, k.join("*")));
+            }
+        }
+        if parts.is_empty() {
+            return write!(f, "0");
+        }
+        write!(f, "{}", parts.join(" + "))
+    }
+}
+
+/// The cost of a single pass through a label region
+pub struct RoutineCost {
+    pub name: String,
+    pub first_line: u32,
+    pub last_line: u32,
+    pub min: u64,
+    pub max: Cost,
+}
+
+/// A loop closed by one or more backward jumps to the same header
+pub struct Loop {
+    pub name: String,
+    pub header: u16,
+    pub latch: u16,
+    pub first_line: u32,
+    pub last_line: u32,
+    /// Instructions executed per iteration
+    pub min: u64,
+    pub max: Cost,
+    /// The loop has no way out besides its backward jump
+    pub infinite: bool,
+    parent: Option<usize>,
+}
+
+impl Loop {
+    /// The symbolic name for the number of times the backward jump is taken
+    pub fn variable(&self) -> String {
+        format!("n_{}", self.name)
+    }
+}
+
+pub struct Report {
+    pub routines: Vec<RoutineCost>,
+    pub loops: Vec<Loop>,
+}
+
+/// Estimates the number of instructions executed by each label region of the
+/// program. Backward jumps are treated as loops whose iteration counts can't
+/// be known statically, so they show up in the maximum as symbolic terms
+/// counting the extra passes through each loop body. Only loops that lie
+/// wholly within a region count towards it, so that its maximum covers the
+/// same instructions as its straight-line paths.
+pub fn estimate(graph: &Graph) -> Report {
+    let loops = find_loops(graph);
+
+    let routines = graph.regions.iter()
+        .filter(|r| r.len() > 0)
+        .map(|r| {
+            let (min, max) = region_paths(graph, r);
+            let mut max = Cost::constant(max);
+            let within = |l: &Loop| r.contains(l.header) && r.contains(l.latch);
+            for l in loops.iter().filter(|l| within(l)) {
+                let nested = match l.parent {
+                    Some(p) => within(&loops[p]),
+                    None => false,
+                };
+                if !nested {
+                    max.add(&l.max.times(&l.variable()));
+                }
+            }
+            RoutineCost {
+                name: r.name.clone(),
+                first_line: r.first_line,
+                last_line: r.last_line,
+                min,
+                max,
+            }
+        })
+        .collect();
+
+    Report { routines, loops }
+}
+
+/// The shortest and longest paths from the start of a region until control
+/// leaves it, stopping at backward jumps
+fn region_paths(graph: &Graph, region: &Region) -> (u64, u64) {
+    let len = region.len() as usize;
+    let mut min = vec![0u64; len];
+    let mut max = vec![0u64; len];
+
+    for address in (region.start..region.end).rev() {
+        let mut best: Option<(u64, u64)> = None;
+        for s in graph.nodes[address as usize].successors(address) {
+            let cost = match s {
+                Some(t) if region.contains(t) && t > address => {
+                    let i = (t - region.start) as usize;
+                    (min[i], max[i])
+                },
+                // Backward jumps are accounted for by the loop's terms
+                Some(t) if region.contains(t) => continue,
+                _ => (0, 0),
+            };
+            best = Some(match best {
+                Some((mn, mx)) => (mn.min(cost.0), mx.max(cost.1)),
+                None => cost,
+            });
+        }
+        let (mn, mx) = best.unwrap_or((0, 0));
+        let i = (address - region.start) as usize;
+        min[i] = mn + 1;
+        max[i] = mx + 1;
+    }
+
+    (min[0], max[0])
+}
+
+fn find_loops(graph: &Graph) -> Vec<Loop> {
+    // Each header is closed by its furthest backward jump
+    let mut latches: BTreeMap<u16, u16> = BTreeMap::new();
+    for (address, node) in graph.nodes.iter().enumerate() {
+        let address = address as u16;
+        if let Some(Target::Known(t)) = node.jump {
+            if t <= address {
+                let latch = latches.entry(t).or_insert(address);
+                *latch = address.max(*latch);
+            }
+        }
+    }
+
+    let mut loops: Vec<Loop> = Vec::new();
+    for (&header, &latch) in &latches {
+        let (min, max) = match body_paths(graph, header, latch) {
+            Some(paths) => paths,
+            None => continue,
+        };
+        let name = match graph.regions.iter().find(|r| r.start == header && r.len() > 0) {
+            Some(r) => r.name.clone(),
+            None => format!("loop@{}", header),
+        };
+        loops.push(Loop {
+            name,
+            header,
+            latch,
+            first_line: graph.nodes[header as usize].line,
+            last_line: graph.nodes[latch as usize].line,
+            min,
+            max: Cost::constant(max),
+            infinite: is_infinite(graph, header, latch),
+            parent: None,
+        });
+    }
+
+    // The parent of a loop is the innermost loop enclosing it
+    for i in 0..loops.len() {
+        loops[i].parent = (0..i).rev().find(|&p| {
+            loops[p].header < loops[i].header && loops[i].latch <= loops[p].latch
+        });
+    }
+
+    // Inner loops have later headers, so fold them into their parents first
+    for i in (0..loops.len()).rev() {
+        if let Some(p) = loops[i].parent {
+            let inner = loops[i].max.times(&loops[i].variable());
+            loops[p].max.add(&inner);
+        }
+    }
+
+    loops
+}
+
+/// The shortest and longest paths from a loop header to one of its backward
+/// jumps, not counting iterations of nested loops
+fn body_paths(graph: &Graph, header: u16, latch: u16) -> Option<(u64, u64)> {
+    let len = (latch - header) as usize + 1;
+    let mut reach: Vec<Option<(u64, u64)>> = vec![None; len];
+
+    for address in (header..=latch).rev() {
+        let node = &graph.nodes[address as usize];
+        let mut best: Option<(u64, u64)> = None;
+        let mut consider = |cost: (u64, u64)| {
+            best = Some(match best {
+                Some((mn, mx)) => (mn.min(cost.0), mx.max(cost.1)),
+                None => cost,
+            });
+        };
+
+        if node.jump == Some(Target::Known(header)) {
+            consider((0, 0));
+        }
+        for t in node.successors(address).into_iter().flatten() {
+            if t > address && t <= latch {
+                if let Some(cost) = reach[(t - header) as usize] {
+                    consider(cost);
+                }
+            }
+        }
+
+        reach[(address - header) as usize] = best.map(|(mn, mx)| (mn + 1, mx + 1));
+    }
+
+    reach[0]
+}
+
+fn is_infinite(graph: &Graph, header: u16, latch: u16) -> bool {
+    (header..=latch).all(|address| {
+        let node = &graph.nodes[address as usize];
+        node.successors(address).iter().all(|s| match *s {
+            Some(t) => header <= t && t <= latch,
+            None => false,
+        }) && !(address == latch && node.falls_through)
+    })
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.routines.iter().map(|r| r.name.len())
+            .chain(self.loops.iter().map(|l| l.name.len()))
+            .max()
+            .unwrap_or(0)
+            .max(7);
+
+        writeln!(f, "{:w$}  {:>13}  {:>8}  Max", "Routine", "Lines", "Min", w = width)?;
+        for r in &self.routines {
+            let lines = format!("{}-{}", r.first_line, r.last_line);
+            writeln!(f, "{:w$}  {:>13}  {:>8}  {}", r.name, lines, r.min, r.max, w = width)?;
+        }
+
+        if !self.loops.is_empty() {
+            writeln!(f)?;
+            writeln!(f, "{:w$}  {:>13}  {:>8}  Max (per iteration)", "Loop", "Lines", "Min", w = width)?;
+            for l in &self.loops {
+                let lines = format!("{}-{}", l.first_line, l.last_line);
+                write!(f, "{:w$}  {:>13}  {:>8}  {}", l.name, lines, l.min, l.max, w = width)?;
+                if l.infinite {
+                    write!(f, " (never exits)")?;
+                }
+                writeln!(f)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+    use cfg::Graph;
+    use cycles::{estimate, Report};
+
+    fn estimate_source(source: &str) -> Report {
+        let mut a = Assembler::new();
+        let instructions = a.load(source.as_bytes()).unwrap();
+        estimate(&Graph::build(&instructions, a.symbols()))
+    }
+
+    #[test]
+    fn branches() {
+        let report = estimate_source("@R0\nD=M\n@SKIP\nD;JGT\n@R1\nM=D\n(SKIP)\n@R2\nM=D\n");
+        assert_eq!(report.routines[0].name, "(start)");
+        assert_eq!(report.routines[0].min, 4);
+        assert_eq!(report.routines[0].max.to_string(), "6");
+        assert!(report.loops.is_empty());
+    }
+
+    #[test]
+    fn nested_loops() {
+        let report = estimate_source("\
+(OUTER)
+@i
+M=M-1
+(INNER)
+@j
+MD=M-1
+@INNER
+D;JGT
+@i
+D=M
+@OUTER
+D;JGT
+(END)
+@END
+0;JMP
+");
+        let names: Vec<&str> = report.loops.iter().map(|l| l.name.as_ref()).collect();
+        assert_eq!(names, vec!["OUTER", "INNER", "END"]);
+        assert_eq!(report.loops[0].max.to_string(), "10 + 4*n_INNER");
+        assert_eq!(report.loops[1].min, 4);
+        assert!(report.loops[2].infinite);
+        assert!(!report.loops[0].infinite);
+        // OUTER jumps back from INNER's region, so neither region counts it
+        assert_eq!(report.routines[0].max.to_string(), "2");
+        assert_eq!(report.routines[1].max.to_string(), "8 + 4*n_INNER");
+    }
+}
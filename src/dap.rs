@@ -0,0 +1,608 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use assembler::Assembler;
+use debugger::{Session, Stop, CYCLE_LIMIT};
+use json::Value;
+use platform::Platform;
+use transport;
+
+/// The Hack CPU runs a single thread of execution
+const THREAD_ID: u32 = 1;
+
+/// Instructions run between checks for a pause request
+const CHUNK: u64 = 100_000;
+
+// Variable references of the scopes shown for the only stack frame
+const REGISTERS: u64 = 1;
+const VARIABLES: u64 = 2;
+const PREDEFINED: u64 = 3;
+
+/// A debug adapter running Hack programs in the emulator, speaking the Debug
+/// Adapter Protocol over a pair of streams
+pub struct Adapter<W: Write> {
+    output: W,
+    seq: u32,
+    program: String,
+    session: Option<Session>,
+    stop_on_entry: bool,
+}
+
+impl<W: Write> Adapter<W> {
+    pub fn new(output: W) -> Adapter<W> {
+        Adapter {
+            output,
+            seq: 1,
+            program: String::new(),
+            session: None,
+            stop_on_entry: false,
+        }
+    }
+
+    /// Handles requests until the client disconnects or closes the input.
+    /// Requests are read on another thread, so a pause can arrive while the
+    /// program runs.
+    pub fn serve<R: BufRead + Send>(&mut self, input: &mut R) -> io::Result<()> {
+        let (sender, requests) = mpsc::channel();
+        thread::scope(|scope| {
+            scope.spawn(move || read_requests(input, sender));
+            self.handle(requests)
+        })
+    }
+
+    fn handle(&mut self, requests: Receiver<io::Result<Value>>) -> io::Result<()> {
+        let mut pending = VecDeque::new();
+        loop {
+            let message = match pending.pop_front() {
+                Some(message) => message,
+                None => match requests.recv() {
+                    Ok(message) => message?,
+                    Err(_) => break,
+                },
+            };
+            if message.get("type").as_str() != Some("request") {
+                continue;
+            }
+            let command = message.get("command").as_str().unwrap_or("").to_string();
+            let result = self.request(&command, message.get("arguments"));
+
+            let mut response = vec![
+                ("type", Value::string("response")),
+                ("request_seq", message.get("seq").clone()),
+                ("command", Value::string(&command)),
+            ];
+            match result {
+                Ok(body) => {
+                    response.push(("success", Value::Bool(true)));
+                    response.push(("body", body));
+                },
+                Err(error) => {
+                    response.push(("success", Value::Bool(false)));
+                    response.push(("message", Value::string(&error)));
+                },
+            }
+            self.send(response)?;
+
+            // Events that follow from a request go out after its response
+            match command.as_ref() {
+                "launch" if self.session.is_some() => self.event("initialized", Value::Null)?,
+                "configurationDone" => {
+                    if self.stop_on_entry {
+                        self.stopped("entry", None)?;
+                    } else {
+                        self.resume(&requests, &mut pending)?;
+                    }
+                },
+                "continue" => self.resume(&requests, &mut pending)?,
+                "next" => self.run(Session::next)?,
+                "stepIn" => self.run(Session::step)?,
+                "stepBack" => self.run(Session::reverse_step)?,
+                "reverseContinue" => self.run(Session::reverse_resume)?,
+                "pause" => self.stopped("pause", None)?,
+                "terminate" => self.event("terminated", Value::Null)?,
+                "disconnect" => break,
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, mut members: Vec<(&str, Value)>) -> io::Result<()> {
+        members.insert(0, ("seq", Value::number(self.seq)));
+        self.seq += 1;
+        transport::write_message(&mut self.output, &Value::object(members))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        self.send(vec![
+            ("type", Value::string("event")),
+            ("event", Value::string(event)),
+            ("body", body),
+        ])
+    }
+
+    fn stopped(&mut self, reason: &str, extra: Option<(&str, Value)>) -> io::Result<()> {
+        let mut body = vec![
+            ("reason", Value::string(reason)),
+            ("threadId", Value::number(THREAD_ID)),
+            ("allThreadsStopped", Value::Bool(true)),
+        ];
+        body.extend(extra);
+        self.event("stopped", Value::object(body))
+    }
+
+    /// Executes the program and reports where and why it stopped
+    fn run<F: FnOnce(&mut Session) -> Stop>(&mut self, f: F) -> io::Result<()> {
+        match self.session {
+            Some(ref mut session) => {
+                let stop = f(session);
+                self.report(stop)
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Runs the program a chunk at a time, setting aside the requests that
+    /// arrive in between. A pause stops it, and handling the pause reports
+    /// where; a disconnect or terminate stops it too.
+    fn resume(&mut self, requests: &Receiver<io::Result<Value>>, pending: &mut VecDeque<Value>) -> io::Result<()> {
+        let mut remaining = CYCLE_LIMIT;
+        loop {
+            let chunk = remaining.min(CHUNK);
+            let stop = match self.session {
+                Some(ref mut session) => session.resume(chunk),
+                None => return Ok(()),
+            };
+            remaining -= chunk;
+            match stop {
+                Stop::Limit(_) if remaining > 0 => (),
+                Stop::Limit(_) => return self.report(Stop::Limit(CYCLE_LIMIT)),
+                _ => return self.report(stop),
+            }
+            while let Ok(message) = requests.try_recv() {
+                let message = message?;
+                let command = message.get("command").as_str();
+                let stops = command == Some("pause") || command == Some("disconnect") || command == Some("terminate");
+                pending.push_back(message);
+                if stops {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn report(&mut self, stop: Stop) -> io::Result<()> {
+        match stop {
+            Stop::Step => self.stopped("step", None),
+            Stop::Breakpoint(id) => self.stopped("breakpoint", Some(
+                ("hitBreakpointIds", Value::Array(vec![Value::number(id as u32)])),
+            )),
+            Stop::Watch { .. } => self.stopped("data breakpoint", Some(("description", Value::string(&stop.to_string())))),
+            Stop::Halted => self.event("terminated", Value::Null),
+            Stop::Limit(_) | Stop::HistoryStart | Stop::Irreversible => self.stopped("pause", Some(("description", Value::string(&stop.to_string())))),
+        }
+    }
+
+    fn session(&mut self) -> Result<&mut Session, String> {
+        self.session.as_mut().ok_or_else(|| String::from("No program launched"))
+    }
+
+    fn request(&mut self, command: &str, arguments: &Value) -> Result<Value, String> {
+        match command {
+            "initialize" => Ok(Value::object(vec![
+                ("supportsConfigurationDoneRequest", Value::Bool(true)),
+                ("supportsSetVariable", Value::Bool(true)),
+                ("supportsEvaluateForHovers", Value::Bool(true)),
+                ("supportsReadMemoryRequest", Value::Bool(true)),
+                ("supportsStepBack", Value::Bool(true)),
+                ("supportsTerminateRequest", Value::Bool(true)),
+            ])),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "configurationDone" | "continue" | "next" | "stepIn" | "stepBack" | "reverseContinue" | "pause" | "terminate" | "disconnect" => {
+                self.session()?;
+                Ok(Value::object(vec![("allThreadsContinued", Value::Bool(true))]))
+            },
+            "threads" => Ok(Value::object(vec![
+                ("threads", Value::Array(vec![Value::object(vec![
+                    ("id", Value::number(THREAD_ID)),
+                    ("name", Value::string("Hack CPU")),
+                ])])),
+            ])),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(Value::object(vec![
+                ("scopes", Value::Array(vec![
+                    scope("Registers", REGISTERS),
+                    scope("Variables", VARIABLES),
+                    scope("Predefined", PREDEFINED),
+                ])),
+            ])),
+            "variables" => self.variables(arguments),
+            "setVariable" => self.set_variable(arguments),
+            "evaluate" => {
+                let session = self.session()?;
+                let location = session.parse_location(arguments.get("expression").as_str().unwrap_or(""))?;
+                Ok(Value::object(vec![
+                    ("result", Value::string(&session.show(location))),
+                    ("variablesReference", Value::number(0)),
+                ]))
+            },
+            "readMemory" => self.read_memory(arguments),
+            _ => Err(format!("Unsupported command {}", command)),
+        }
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let program = arguments.get("program").as_str().ok_or("Missing program")?;
+        let source = fs::read_to_string(program).map_err(|e| format!("{}: {}", program, e))?;
+        let platform = match arguments.get("platform").as_str() {
+            Some(path) => Platform::load(path)?,
+            None => Platform::hack(),
+        };
+        let mut assembler = Assembler::with_platform(&platform);
+        assembler.assemble(source.as_bytes()).map_err(|e| e.to_string())?;
+
+        self.program = program.to_string();
+        self.session = Some(Session::new(&assembler, &source));
+        self.stop_on_entry = arguments.get("stopOnEntry").as_bool().unwrap_or(false);
+        Ok(Value::Null)
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+        session.clear_breakpoints();
+
+        let mut breakpoints = Vec::new();
+        for b in arguments.get("breakpoints").as_array().unwrap_or(&[]) {
+            let line = b.get("line").as_u64().unwrap_or(0) as u32;
+            breakpoints.push(match session.address_for_line(line) {
+                Some(address) => {
+                    let id = session.add_breakpoint(address);
+                    Value::object(vec![
+                        ("id", Value::number(id as u32)),
+                        ("verified", Value::Bool(true)),
+                        ("line", Value::number(session.line_for(address).unwrap_or(line))),
+                    ])
+                },
+                None => Value::object(vec![
+                    ("verified", Value::Bool(false)),
+                    ("message", Value::string("No code at or after this line")),
+                ]),
+            });
+        }
+        Ok(Value::object(vec![("breakpoints", Value::Array(breakpoints))]))
+    }
+
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        let name = Path::new(&self.program).file_name().and_then(|n| n.to_str()).unwrap_or("").to_string();
+        let path = fs::canonicalize(&self.program).map(|p| p.to_string_lossy().into_owned()).unwrap_or_else(|_| self.program.clone());
+        let session = self.session()?;
+        let pc = session.emulator.pc;
+        let frame = Value::object(vec![
+            ("id", Value::number(0)),
+            ("name", Value::string(session.routine_for(pc).unwrap_or("(start)"))),
+            ("source", Value::object(vec![
+                ("name", Value::string(&name)),
+                ("path", Value::string(&path)),
+            ])),
+            ("line", Value::number(session.line_for(pc).unwrap_or(0))),
+            ("column", Value::number(1)),
+            ("instructionPointerReference", Value::string(&pc.to_string())),
+        ]);
+        Ok(Value::object(vec![
+            ("stackFrames", Value::Array(vec![frame])),
+            ("totalFrames", Value::number(1)),
+        ]))
+    }
+
+    fn variables(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+        let variables = match arguments.get("variablesReference").as_u64() {
+            Some(REGISTERS) => vec![
+                variable("A", session.emulator.a, None),
+                variable("D", session.emulator.d, None),
+                variable("PC", session.emulator.pc, None),
+            ],
+            Some(VARIABLES) => session.variables().into_iter()
+                .map(|(name, address)| variable(name, session.emulator.read(address), Some(address)))
+                .collect(),
+            Some(PREDEFINED) => session.predefined().into_iter()
+                .map(|(name, address)| variable(name, session.emulator.read(address), Some(address)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(Value::object(vec![("variables", Value::Array(variables))]))
+    }
+
+    fn set_variable(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+        let name = arguments.get("name").as_str().unwrap_or("");
+        let text = arguments.get("value").as_str().unwrap_or("").trim();
+        let value = match text.parse::<i16>() {
+            Ok(v) => v as u16,
+            Err(_) => session.evaluate(text)?,
+        };
+        let location = session.parse_location(name)?;
+        session.write(location, value);
+        Ok(Value::object(vec![("value", Value::string(&format_word(session.read(location))))]))
+    }
+
+    /// Reads RAM as bytes, two little-endian bytes per word, so a memory
+    /// reference of a word's address times two points at that word
+    fn read_memory(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+        let reference = arguments.get("memoryReference").as_str().unwrap_or("");
+        let start = parse_reference(reference)?
+            .checked_add(arguments.get("offset").as_u64().unwrap_or(0))
+            .ok_or_else(|| format!("Bad memory offset from {}", reference))?;
+        let count = arguments.get("count").as_u64().unwrap_or(0).min(2 * 0x8000);
+
+        let mut bytes = Vec::with_capacity(count as usize);
+        for byte in start..start.saturating_add(count).min(2 * 0x8000) {
+            let word = session.emulator.read((byte / 2) as u16);
+            bytes.push(if byte % 2 == 0 { word as u8 } else { (word >> 8) as u8 });
+        }
+        Ok(Value::object(vec![
+            ("address", Value::string(&format!("0x{:X}", start))),
+            ("data", Value::string(&base64(&bytes))),
+        ]))
+    }
+}
+
+/// Reads messages onto a channel until the input ends or fails, or the
+/// client disconnects
+fn read_requests<R: BufRead>(input: &mut R, sender: Sender<io::Result<Value>>) {
+    loop {
+        let message = match transport::read_message(input) {
+            Ok(Some(message)) => message,
+            Ok(None) => return,
+            Err(err) => {
+                let _ = sender.send(Err(err));
+                return;
+            },
+        };
+        let disconnect = message.get("command").as_str() == Some("disconnect");
+        if sender.send(Ok(message)).is_err() || disconnect {
+            return;
+        }
+    }
+}
+
+/// A memory reference for the RAM word at an address
+fn memory_reference(address: u16) -> String {
+    format!("0x{:X}", address as u32 * 2)
+}
+
+fn parse_reference(reference: &str) -> Result<u64, String> {
+    let parsed = match reference.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => reference.parse(),
+    };
+    parsed.map_err(|_| format!("Bad memory reference {}", reference))
+}
+
+fn scope(name: &str, reference: u64) -> Value {
+    Value::object(vec![
+        ("name", Value::string(name)),
+        ("variablesReference", Value::number(reference as u32)),
+        ("expensive", Value::Bool(false)),
+    ])
+}
+
+fn variable(name: &str, value: u16, address: Option<u16>) -> Value {
+    let mut members = vec![
+        ("name", Value::string(name)),
+        ("value", Value::string(&format_word(value))),
+        ("evaluateName", Value::string(name)),
+        ("variablesReference", Value::number(0)),
+    ];
+    if let Some(address) = address {
+        members.push(("memoryReference", Value::string(&memory_reference(address))));
+    }
+    Value::object(members)
+}
+
+/// A word as signed decimal with its hexadecimal bits
+fn format_word(value: u16) -> String {
+    format!("{} (0x{:04X})", value as i16, value)
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use json::Value;
+    use dap::{base64, Adapter};
+    use debugger::CYCLE_LIMIT;
+    use transport::{read_message, write_message};
+
+    fn request(seq: u32, command: &str, arguments: Value) -> Value {
+        Value::object(vec![
+            ("seq", Value::number(seq)),
+            ("type", Value::string("request")),
+            ("command", Value::string(command)),
+            ("arguments", arguments),
+        ])
+    }
+
+    #[test]
+    fn breakpoint_session() {
+        let messages = vec![
+            request(1, "initialize", Value::Null),
+            request(2, "launch", Value::object(vec![("program", Value::string("test/max/Max.asm"))])),
+            request(3, "setBreakpoints", Value::object(vec![
+                ("breakpoints", Value::Array(vec![Value::object(vec![("line", Value::number(21))])])),
+            ])),
+            request(4, "setVariable", Value::object(vec![
+                ("variablesReference", Value::number(3)),
+                ("name", Value::string("R0")),
+                ("value", Value::string("7")),
+            ])),
+            request(5, "configurationDone", Value::Null),
+            request(6, "stackTrace", Value::Null),
+            request(7, "variables", Value::object(vec![("variablesReference", Value::number(1))])),
+            request(8, "readMemory", Value::object(vec![
+                ("memoryReference", Value::string("0x0")),
+                ("count", Value::number(2)),
+            ])),
+            request(9, "disconnect", Value::Null),
+        ];
+        let mut input = Vec::new();
+        for m in &messages {
+            write_message(&mut input, m).unwrap();
+        }
+        let mut output = Vec::new();
+        Adapter::new(&mut output).serve(&mut Cursor::new(input)).unwrap();
+
+        let mut output = Cursor::new(output);
+        let mut responses = Vec::new();
+        while let Some(r) = read_message(&mut output).unwrap() {
+            responses.push(r);
+        }
+
+        assert_eq!(responses[2].get("event").as_str(), Some("initialized"));
+        let breakpoint = &responses[3].get("body").get("breakpoints").as_array().unwrap()[0];
+        assert_eq!(breakpoint.get("line").as_u64(), Some(22));
+
+        let stopped = responses.iter().find(|r| r.get("event").as_str() == Some("stopped")).unwrap();
+        assert_eq!(stopped.get("body").get("reason").as_str(), Some("breakpoint"));
+
+        let trace = responses.iter().find(|r| r.get("command").as_str() == Some("stackTrace")).unwrap();
+        let frame = &trace.get("body").get("stackFrames").as_array().unwrap()[0];
+        assert_eq!(frame.get("name").as_str(), Some("OUTPUT_D"));
+        assert_eq!(frame.get("line").as_u64(), Some(22));
+
+        let registers = responses.iter().find(|r| r.get("command").as_str() == Some("variables")).unwrap();
+        let d = &registers.get("body").get("variables").as_array().unwrap()[1];
+        assert_eq!(d.get("value").as_str(), Some("7 (0x0007)"));
+
+        let memory = responses.iter().find(|r| r.get("command").as_str() == Some("readMemory")).unwrap();
+        assert_eq!(memory.get("body").get("data").as_str(), Some("BwA="));
+    }
+
+    #[test]
+    fn pause() {
+        // Pong waits for keys forever, so only the pause stops it
+        let messages = vec![
+            request(1, "launch", Value::object(vec![("program", Value::string("test/pong/Pong.asm"))])),
+            request(2, "configurationDone", Value::Null),
+            request(3, "pause", Value::Null),
+            request(4, "disconnect", Value::Null),
+        ];
+        let mut input = Vec::new();
+        for m in &messages {
+            write_message(&mut input, m).unwrap();
+        }
+        let mut output = Vec::new();
+        let mut adapter = Adapter::new(&mut output);
+        adapter.serve(&mut Cursor::new(input)).unwrap();
+        assert!(adapter.session.unwrap().emulator.cycles < CYCLE_LIMIT);
+
+        let mut output = Cursor::new(output);
+        let mut stops = Vec::new();
+        while let Some(r) = read_message(&mut output).unwrap() {
+            if r.get("event").as_str() == Some("stopped") {
+                stops.push(r.get("body").get("reason").as_str().unwrap_or("").to_string());
+            }
+        }
+        assert_eq!(stops, ["pause"]);
+    }
+
+    #[test]
+    fn disconnect_while_running() {
+        let messages = vec![
+            request(1, "launch", Value::object(vec![("program", Value::string("test/pong/Pong.asm"))])),
+            request(2, "configurationDone", Value::Null),
+            request(3, "disconnect", Value::Null),
+        ];
+        let mut input = Vec::new();
+        for m in &messages {
+            write_message(&mut input, m).unwrap();
+        }
+        let mut output = Vec::new();
+        let mut adapter = Adapter::new(&mut output);
+        adapter.serve(&mut Cursor::new(input)).unwrap();
+        assert!(adapter.session.unwrap().emulator.cycles < CYCLE_LIMIT);
+    }
+
+    #[test]
+    fn terminated() {
+        let platform = env::temp_dir().join("dap-test-halt.platform");
+        fs::write(&platform, "rom_size = 64\n").unwrap();
+        let messages = vec![
+            request(1, "launch", Value::object(vec![
+                ("program", Value::string("test/max/Max.asm")),
+                ("platform", Value::string(platform.to_str().unwrap())),
+            ])),
+            request(2, "configurationDone", Value::Null),
+            request(3, "readMemory", Value::object(vec![
+                ("memoryReference", Value::string("0xFFFFFFFFFFFFFFFF")),
+                ("offset", Value::number(2)),
+                ("count", Value::number(2)),
+            ])),
+            request(4, "disconnect", Value::Null),
+        ];
+        let mut input = Vec::new();
+        for m in &messages {
+            write_message(&mut input, m).unwrap();
+        }
+        let mut output = Vec::new();
+        Adapter::new(&mut output).serve(&mut Cursor::new(input)).unwrap();
+        fs::remove_file(&platform).unwrap();
+
+        let mut output = Cursor::new(output);
+        let mut responses = Vec::new();
+        while let Some(r) = read_message(&mut output).unwrap() {
+            responses.push(r);
+        }
+        assert!(responses.iter().any(|r| r.get("event").as_str() == Some("terminated")));
+        assert!(!responses.iter().any(|r| r.get("event").as_str() == Some("stopped")));
+        let memory = responses.iter().find(|r| r.get("command").as_str() == Some("readMemory")).unwrap();
+        assert_eq!(memory.get("success").as_bool(), Some(false));
+    }
+
+    #[test]
+    fn platform() {
+        let path = env::temp_dir().join("dap-test.platform");
+        fs::write(&path, "keyboard = 28672\n").unwrap();
+        let mut input = Vec::new();
+        write_message(&mut input, &request(1, "launch", Value::object(vec![
+            ("program", Value::string("test/max/Max.asm")),
+            ("platform", Value::string(path.to_str().unwrap())),
+        ]))).unwrap();
+        let mut output = Vec::new();
+        let mut adapter = Adapter::new(&mut output);
+        adapter.serve(&mut Cursor::new(input)).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(adapter.session.unwrap().emulator.platform().keyboard, 28672);
+    }
+
+    #[test]
+    fn encodes_base64() {
+        assert_eq!(base64(b"Hack"), "SGFjaw==");
+        assert_eq!(base64(b"CPU"), "Q1BV");
+    }
+}
@@ -0,0 +1,704 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+
+use assembler::Assembler;
+use cfg;
+use emulator::{Delta, Emulator, MemoryWrite, RAM_SIZE};
+
+/// How many instructions `continue` runs before giving up on the program stopping
+pub const CYCLE_LIMIT: u64 = 100_000_000;
+
+/// How many steps can be undone, at 16 bytes each. Older ones are forgotten.
+pub const HISTORY_LIMIT: usize = 1_000_000;
+
+/// Something the debugger can read and write
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Location {
+    A,
+    D,
+    PC,
+    /// A range of RAM, from an address and a number of words
+    Ram(u16, u16),
+}
+
+/// Why execution stopped
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stop {
+    Step,
+    Breakpoint(usize),
+    Watch { id: usize, old: u16, new: u16 },
+    Halted,
+    /// Ran this many instructions without stopping
+    Limit(u64),
+    /// Reversing ran out of recorded steps
+    HistoryStart,
+    /// Reversing can't put back the state of the devices attached
+    Irreversible,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub address: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watch {
+    pub id: usize,
+    pub expression: String,
+    pub location: Location,
+    value: u16,
+}
+
+/// A step in the history that wrote RAM
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PastWrite {
+    pub cycle: u64,
+    pub pc: u16,
+    pub write: MemoryWrite,
+}
+
+/// A program loaded into the emulator along with everything needed to map it
+/// back to its source
+pub struct Session {
+    pub emulator: Emulator,
+    source: Vec<String>,
+    source_lines: Vec<u32>,
+    labels: Vec<(String, u16)>,
+    ram_symbols: Vec<(String, u16)>,
+    variables: Vec<String>,
+    return_addresses: Vec<u16>,
+    breakpoints: Vec<Breakpoint>,
+    watches: Vec<Watch>,
+    next_id: usize,
+    history: VecDeque<Delta>,
+}
+
+impl Session {
+    pub fn new(assembler: &Assembler, source: &str) -> Session {
+        let words = assembler.words();
+        let symbols = assembler.symbols();
+        let labels: Vec<(String, u16)> = symbols.labels().into_iter().map(|(k, v)| (k.to_string(), v)).collect();
+
+        Session {
+            emulator: Emulator::with_platform(words, assembler.platform()),
+            source: source.lines().map(|l| l.to_string()).collect(),
+            source_lines: assembler.source_lines().to_vec(),
+            labels,
+            ram_symbols: symbols.ram_symbols().into_iter().map(|(k, v)| (k.to_string(), v)).collect(),
+            variables: symbols.variables().to_vec(),
+            return_addresses: cfg::return_addresses(words, symbols),
+            breakpoints: Vec::new(),
+            watches: Vec::new(),
+            next_id: 1,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// The source line of the instruction at a ROM address
+    pub fn line_for(&self, address: u16) -> Option<u32> {
+        self.source_lines.get(address as usize).cloned()
+    }
+
+    /// The first ROM address generated by the given source line or any after it
+    pub fn address_for_line(&self, line: u32) -> Option<u16> {
+        self.source_lines.iter().position(|&l| l >= line).map(|a| a as u16)
+    }
+
+    pub fn source_text(&self, line: u32) -> &str {
+        self.source.get(line as usize - 1).map(|s| s.as_ref()).unwrap_or("")
+    }
+
+    pub fn label_address(&self, name: &str) -> Option<u16> {
+        self.labels.iter().find(|(k, _)| k == name).map(|&(_, v)| v)
+    }
+
+    /// The label at a ROM address, if any
+    pub fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels.iter().find(|&&(_, v)| v == address).map(|(k, _)| k.as_ref())
+    }
+
+    /// The label an address falls under, which names the routine it belongs to
+    pub fn routine_for(&self, address: u16) -> Option<&str> {
+        self.labels.iter().rev().find(|&&(_, v)| v <= address).map(|(k, _)| k.as_ref())
+    }
+
+    /// The program's variables and their RAM addresses, in allocation order
+    pub fn variables(&self) -> Vec<(&str, u16)> {
+        self.variables.iter().filter_map(|v| {
+            self.ram_symbols.iter().find(|(k, _)| k == v).map(|(k, a)| (k.as_ref(), *a))
+        }).collect()
+    }
+
+    /// The predefined RAM symbols, ordered by address
+    pub fn predefined(&self) -> Vec<(&str, u16)> {
+        self.ram_symbols.iter()
+            .filter(|(k, _)| !self.variables.contains(k))
+            .map(|(k, a)| (k.as_ref(), *a))
+            .collect()
+    }
+
+    /// Every predefined symbol and variable naming a RAM address
+    pub fn ram_names(&self, address: u16) -> Vec<&str> {
+        self.ram_symbols.iter().filter(|&&(_, v)| v == address).map(|(k, _)| k.as_ref()).collect()
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    /// Resolves a label name or a source line number to a ROM address
+    pub fn resolve_breakpoint(&self, spec: &str) -> Result<u16, String> {
+        if let Ok(line) = spec.parse::<u32>() {
+            return self.address_for_line(line).ok_or_else(|| format!("No code at or after line {}", line));
+        }
+        self.label_address(spec).ok_or_else(|| format!("No label {}", spec))
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.breakpoints.push(Breakpoint { id, address });
+        id
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|b| b.id != id);
+        self.breakpoints.len() != len
+    }
+
+    pub fn add_watch(&mut self, expression: &str) -> Result<usize, String> {
+        let location = self.parse_location(expression)?;
+        let id = self.next_id;
+        self.next_id += 1;
+        let value = self.read(location);
+        self.watches.push(Watch { id, expression: expression.to_string(), location, value });
+        Ok(id)
+    }
+
+    pub fn remove_watch(&mut self, id: usize) -> bool {
+        let len = self.watches.len();
+        self.watches.retain(|w| w.id != id);
+        self.watches.len() != len
+    }
+
+    /// Parses `A`, `D`, `PC`, `RAM[n]`, `RAM[a..b]` or a RAM symbol, where
+    /// addresses may be numbers or symbols with `+` and `-` offsets
+    pub fn parse_location(&self, expression: &str) -> Result<Location, String> {
+        let in_ram = |address: u16| match address as usize {
+            a if a < RAM_SIZE => Ok(address),
+            _ => Err(format!("RAM[{}] is past the end of RAM, which has {} words", address, RAM_SIZE)),
+        };
+
+        let e = expression.trim();
+        match e {
+            "A" => return Ok(Location::A),
+            "D" => return Ok(Location::D),
+            "PC" => return Ok(Location::PC),
+            "M" => return Ok(Location::Ram(in_ram(self.emulator.a)?, 1)),
+            _ => (),
+        }
+        if e.starts_with("RAM[") && e.ends_with(']') {
+            let inner = &e[4..e.len() - 1];
+            let mut range = inner.splitn(2, "..");
+            let start = in_ram(self.evaluate(range.next().unwrap_or(""))?)?;
+            return match range.next() {
+                Some(end) => {
+                    let end = in_ram(self.evaluate(end)?)?;
+                    if end < start {
+                        return Err(format!("Empty range {}", inner));
+                    }
+                    Ok(Location::Ram(start, end - start + 1))
+                },
+                None => Ok(Location::Ram(start, 1)),
+            };
+        }
+
+        Ok(Location::Ram(in_ram(self.evaluate(e)?)?, 1))
+    }
+
+    /// Evaluates a sum of numbers, registers and symbol addresses
+    pub fn evaluate(&self, expression: &str) -> Result<u16, String> {
+        let mut total: i32 = 0;
+        let mut sign = 1;
+        let mut term = String::new();
+        for c in expression.chars().chain(Some('+')) {
+            match c {
+                '+' | '-' => {
+                    let t = term.trim();
+                    if t.is_empty() {
+                        if c == '-' {
+                            sign = -sign;
+                        }
+                        continue;
+                    }
+                    total += sign * self.term(t)? as i32;
+                    term.clear();
+                    sign = if c == '-' { -1 } else { 1 };
+                },
+                _ => term.push(c),
+            }
+        }
+        Ok(total as u16)
+    }
+
+    fn term(&self, t: &str) -> Result<u16, String> {
+        if let Ok(n) = t.parse::<u16>() {
+            return Ok(n);
+        }
+        if let Some(hex) = t.strip_prefix("0x") {
+            return u16::from_str_radix(hex, 16).map_err(|_| format!("Bad number {}", t));
+        }
+        match t {
+            "A" => return Ok(self.emulator.a),
+            "D" => return Ok(self.emulator.d),
+            "PC" => return Ok(self.emulator.pc),
+            _ => (),
+        }
+        self.ram_symbols.iter().find(|(k, _)| k == t).map(|&(_, v)| v)
+            .or_else(|| self.label_address(t))
+            .ok_or_else(|| format!("No symbol {}", t))
+    }
+
+    /// The first word of a location
+    pub fn read(&self, location: Location) -> u16 {
+        match location {
+            Location::A => self.emulator.a,
+            Location::D => self.emulator.d,
+            Location::PC => self.emulator.pc,
+            Location::Ram(address, _) => self.emulator.read(address),
+        }
+    }
+
+    /// Sets every word of a location
+    pub fn write(&mut self, location: Location, value: u16) {
+        match location {
+            Location::A => self.emulator.a = value,
+            Location::D => self.emulator.d = value,
+            Location::PC => self.emulator.pc = value,
+            Location::Ram(address, len) => for i in 0..len {
+                self.emulator.write(address.wrapping_add(i), value);
+            },
+        }
+        self.refresh_watches();
+    }
+
+    fn refresh_watches(&mut self) {
+        for i in 0..self.watches.len() {
+            self.watches[i].value = self.read(self.watches[i].location);
+        }
+    }
+
+    /// Executes a single instruction
+    pub fn step(&mut self) -> Stop {
+        self.run_until(None, 1)
+    }
+
+    /// Steps over a call: a jump immediately followed by a return label runs
+    /// until execution comes back to that label
+    pub fn next(&mut self) -> Stop {
+        let pc = self.emulator.pc;
+        let instruction = self.emulator.instruction(pc);
+        let returns = pc.wrapping_add(1);
+        if instruction & 0x8007 > 0x8000 && self.return_addresses.contains(&returns) {
+            return self.run_until(Some(returns), CYCLE_LIMIT);
+        }
+        self.step()
+    }
+
+    /// Runs until a breakpoint, a watch changing, the program halting or the limit
+    pub fn resume(&mut self, limit: u64) -> Stop {
+        self.run_until(None, limit)
+    }
+
+    fn run_until(&mut self, target: Option<u16>, limit: u64) -> Stop {
+        for _ in 0..limit {
+            if self.emulator.halted() {
+                return Stop::Halted;
+            }
+            if self.emulator.has_hooked_devices() {
+                self.emulator.step();
+            } else {
+                if self.history.len() == HISTORY_LIMIT {
+                    self.history.pop_front();
+                }
+                self.history.push_back(self.emulator.step_recorded());
+            }
+
+            if let Some(stop) = self.check_stops() {
+                return stop;
+            }
+            if target == Some(self.emulator.pc) {
+                return Stop::Step;
+            }
+        }
+        if limit == 1 { Stop::Step } else { Stop::Limit(limit) }
+    }
+
+    /// Undoes a single instruction
+    pub fn reverse_step(&mut self) -> Stop {
+        self.reverse_until(1)
+    }
+
+    /// Runs backwards until a breakpoint, a watch changing or the start of
+    /// the history
+    pub fn reverse_resume(&mut self) -> Stop {
+        self.reverse_until(u64::MAX)
+    }
+
+    fn reverse_until(&mut self, limit: u64) -> Stop {
+        if self.emulator.has_hooked_devices() {
+            return Stop::Irreversible;
+        }
+        for _ in 0..limit {
+            let delta = match self.history.pop_back() {
+                Some(delta) => delta,
+                None => return Stop::HistoryStart,
+            };
+            self.emulator.undo(&delta);
+
+            if let Some(stop) = self.check_stops() {
+                return stop;
+            }
+        }
+        Stop::Step
+    }
+
+    /// Stops for a watch whose value changed or a breakpoint at PC
+    fn check_stops(&mut self) -> Option<Stop> {
+        for i in 0..self.watches.len() {
+            let new = self.read(self.watches[i].location);
+            let old = self.watches[i].value;
+            if new != old {
+                self.refresh_watches();
+                return Some(Stop::Watch { id: self.watches[i].id, old, new });
+            }
+        }
+        let pc = self.emulator.pc;
+        self.breakpoints.iter().find(|b| b.address == pc).map(|b| Stop::Breakpoint(b.id))
+    }
+
+    /// The most recent write to a RAM address still in the history
+    pub fn last_write(&self, address: u16) -> Option<PastWrite> {
+        let cycles = self.emulator.cycles;
+        self.history.iter().rev().enumerate()
+            .find(|(_, d)| d.write.is_some_and(|w| w.address == address & 0x7FFF))
+            .map(|(i, d)| PastWrite { cycle: cycles - i as u64 - 1, pc: d.pc, write: d.write.unwrap() })
+    }
+
+    /// Describes the instruction about to execute
+    pub fn current(&self) -> String {
+        self.describe(self.emulator.pc)
+    }
+
+    /// Describes the instruction at a ROM address with its source line
+    pub fn describe(&self, pc: u16) -> String {
+        match self.line_for(pc) {
+            Some(line) => format!("[PC {}] {}: {}", pc, line, self.source_text(line).trim()),
+            None => format!("[PC {}] outside the program", pc),
+        }
+    }
+
+    /// Formats a location's value, naming the symbols at each RAM address
+    pub fn show(&self, location: Location) -> String {
+        match location {
+            Location::A => format!("A = {}", self.value(self.emulator.a)),
+            Location::D => format!("D = {}", self.value(self.emulator.d)),
+            Location::PC => format!("PC = {}", self.emulator.pc),
+            Location::Ram(address, len) => {
+                let mut out = Vec::new();
+                for i in 0..len {
+                    let a = address.wrapping_add(i);
+                    let names = self.ram_names(a);
+                    let names = if names.is_empty() { String::new() } else { format!(" ({})", names.join(", ")) };
+                    out.push(format!("RAM[{}]{} = {}", a, names, self.value(self.emulator.read(a))));
+                }
+                out.join("\n")
+            },
+        }
+    }
+
+    /// A word as both signed and unsigned when they differ
+    fn value(&self, v: u16) -> String {
+        if (v as i16) < 0 {
+            format!("{} ({})", v as i16, v)
+        } else {
+            v.to_string()
+        }
+    }
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Step => Ok(()),
+            Stop::Breakpoint(id) => write!(f, "Breakpoint {}", id),
+            Stop::Watch { id, old, new } => write!(f, "Watch {}: {} -> {}", id, *old as i16, *new as i16),
+            Stop::Halted => write!(f, "Program halted"),
+            Stop::Limit(limit) => write!(f, "Stopped after {} cycles", limit),
+            Stop::HistoryStart => write!(f, "Reached the start of the recorded history"),
+            Stop::Irreversible => write!(f, "Can't run backwards with devices attached"),
+        }
+    }
+}
+
+const HELP: &str = "\
+break LABEL|LINE     stop before the instruction at a label or source line
+delete N             remove a breakpoint
+step [N]             execute one or N instructions
+next                 step, running over calls until they return
+continue             run until a breakpoint, a watch changes or the program halts
+reverse-step [N]     undo one or N instructions
+reverse-continue     run backwards until a breakpoint, a watch changes or the history ends
+last-write EXPR      show when a RAM word was last written
+print EXPR           show A, D, PC, RAM[n], RAM[a..b] or a symbol
+set EXPR VALUE       change a register or RAM
+watch EXPR           stop when a value changes
+unwatch N            remove a watch
+info registers|breakpoints|watches
+screen [FILE]        draw the screen, or save it as a .png or .pbm file
+quit
+";
+
+/// Reads debugger commands until `quit` or the end of input. An empty line
+/// repeats the previous command.
+pub fn repl<R: BufRead, W: Write>(session: &mut Session, input: &mut R, output: &mut W) -> io::Result<()> {
+    writeln!(output, "{}", session.current())?;
+    let mut previous = String::new();
+    let mut line = String::new();
+    loop {
+        write!(output, "(hdb) ")?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let command = if line.trim().is_empty() { previous.clone() } else { line.trim().to_string() };
+        if command.is_empty() {
+            continue;
+        }
+        previous = command.clone();
+
+        let mut words = command.splitn(2, char::is_whitespace);
+        let name = words.next().unwrap_or("");
+        let argument = words.next().unwrap_or("").trim();
+        match execute(session, name, argument) {
+            Ok(None) => return Ok(()),
+            Ok(Some(text)) => {
+                if !text.is_empty() {
+                    writeln!(output, "{}", text)?;
+                }
+            },
+            Err(err) => writeln!(output, "{}", err)?,
+        }
+        writeln!(output, "{}", session.current())?;
+    }
+}
+
+/// Runs a single command, returning its output or `None` to quit
+fn execute(session: &mut Session, name: &str, argument: &str) -> Result<Option<String>, String> {
+    let text = match name {
+        "b" | "break" => {
+            let address = session.resolve_breakpoint(argument)?;
+            let id = session.add_breakpoint(address);
+            format!("Breakpoint {} at {}", id, describe_address(session, address))
+        },
+        "d" | "delete" => {
+            let id = argument.parse().map_err(|_| format!("Bad breakpoint {}", argument))?;
+            if !session.remove_breakpoint(id) {
+                return Err(format!("No breakpoint {}", id));
+            }
+            String::new()
+        },
+        "s" | "step" => {
+            let count: u64 = if argument.is_empty() { 1 } else {
+                argument.parse().map_err(|_| format!("Bad count {}", argument))?
+            };
+            let mut stop = Stop::Step;
+            for _ in 0..count {
+                stop = session.step();
+                if stop != Stop::Step {
+                    break;
+                }
+            }
+            stop.to_string()
+        },
+        "n" | "next" => session.next().to_string(),
+        "c" | "continue" => session.resume(CYCLE_LIMIT).to_string(),
+        "rs" | "reverse-step" => {
+            let count: u64 = if argument.is_empty() { 1 } else {
+                argument.parse().map_err(|_| format!("Bad count {}", argument))?
+            };
+            let mut stop = Stop::Step;
+            for _ in 0..count {
+                stop = session.reverse_step();
+                if stop != Stop::Step {
+                    break;
+                }
+            }
+            stop.to_string()
+        },
+        "rc" | "reverse-continue" => session.reverse_resume().to_string(),
+        "lw" | "last-write" => {
+            let address = match session.parse_location(argument)? {
+                Location::Ram(address, 1) => address,
+                _ => return Err(String::from("Usage: last-write RAM_ADDRESS")),
+            };
+            let names = session.ram_names(address);
+            let names = if names.is_empty() { String::new() } else { format!(" ({})", names.join(", ")) };
+            match session.last_write(address) {
+                Some(p) => format!("RAM[{}]{} was last written at cycle {}: {} -> {}\n{}",
+                    address, names, p.cycle, p.write.old as i16, p.write.new as i16, session.describe(p.pc)),
+                None => format!("RAM[{}]{} wasn't written in the recorded history", address, names),
+            }
+        },
+        "p" | "print" => session.show(session.parse_location(argument)?),
+        "set" => {
+            let mut parts = argument.rsplitn(2, char::is_whitespace);
+            let value = parts.next().unwrap_or("");
+            let location = session.parse_location(parts.next().ok_or("Usage: set EXPR VALUE")?)?;
+            let value = match value.parse::<i16>() {
+                Ok(v) => v as u16,
+                Err(_) => session.evaluate(value)?,
+            };
+            session.write(location, value);
+            session.show(location)
+        },
+        "w" | "watch" => {
+            let id = session.add_watch(argument)?;
+            format!("Watch {}: {}", id, argument)
+        },
+        "unwatch" => {
+            let id = argument.parse().map_err(|_| format!("Bad watch {}", argument))?;
+            if !session.remove_watch(id) {
+                return Err(format!("No watch {}", id));
+            }
+            String::new()
+        },
+        "i" | "info" => match argument {
+            "registers" | "r" => [Location::A, Location::D, Location::PC].iter()
+                .map(|&l| session.show(l))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            "breakpoints" | "b" => session.breakpoints().iter()
+                .map(|b| format!("{}: {}", b.id, describe_address(session, b.address)))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            "watches" | "w" => session.watches().iter()
+                .map(|w| format!("{}: {} = {}", w.id, w.expression, session.read(w.location) as i16))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            _ => return Err(String::from("Usage: info registers|breakpoints|watches")),
+        },
+        "screen" => {
+            if argument.is_empty() {
+                session.emulator.platform().screen.to_ascii(&session.emulator.ram).trim_end().to_string()
+            } else {
+                session.emulator.platform().screen.save(argument, &session.emulator.ram).map_err(|e| e.to_string())?;
+                format!("Saved {}", argument)
+            }
+        },
+        "h" | "help" => HELP.trim_end().to_string(),
+        "q" | "quit" => return Ok(None),
+        _ => return Err(format!("Unknown command {}, try help", name)),
+    };
+    Ok(Some(text))
+}
+
+fn describe_address(session: &Session, address: u16) -> String {
+    let line = session.line_for(address).map(|l| format!(", line {}", l)).unwrap_or_default();
+    match session.label_at(address) {
+        Some(label) => format!("ROM {} ({}){}", address, label, line),
+        None => format!("ROM {}{}", address, line),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use assembler::Assembler;
+    use debugger::{repl, Location, Session, Stop};
+    use device::Timer;
+
+    fn session(source: &str) -> Session {
+        let mut a = Assembler::new();
+        a.assemble(source.as_bytes()).unwrap();
+        Session::new(&a, source)
+    }
+
+    #[test]
+    fn script() {
+        let source = fs::read_to_string("test/max/Max.asm").unwrap();
+        let mut s = session(&source);
+        let script = "set R0 3\nset R1 9\nbreak OUTPUT_D\ncontinue\nprint D\nwatch R2\ncontinue\n\nprint RAM[0..2]\nquit\n";
+        let mut out = Vec::new();
+        repl(&mut s, &mut script.as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("Breakpoint 1 at ROM 12 (OUTPUT_D), line 22"));
+        assert!(out.contains("Breakpoint 1\n[PC 12] 22: @R2"));
+        assert!(out.contains("D = 9\n"));
+        assert!(out.contains("Watch 2: 0 -> 9\n[PC 14] 25: @INFINITE_LOOP"));
+        assert!(out.contains("Program halted"));
+        assert!(out.contains("RAM[2] (ARG, R2) = 9"));
+
+        assert_eq!(s.parse_location("RAM[0..65535]").unwrap_err(), "RAM[65535] is past the end of RAM, which has 32768 words");
+        assert!(s.parse_location("RAM[32760..32770]").is_err());
+        assert!(s.parse_location("RAM[32760..32767]").is_ok());
+
+        s.emulator.a = 0x8000;
+        assert_eq!(s.parse_location("M").unwrap_err(), "RAM[32768] is past the end of RAM, which has 32768 words");
+        s.emulator.a = 5;
+        assert_eq!(s.parse_location("M").unwrap(), Location::Ram(5, 1));
+        assert_eq!(session("@0\nD=A\n").resume(3).to_string(), "Stopped after 3 cycles");
+    }
+
+    #[test]
+    fn step_over_call() {
+        let source = "@RET\nD=A\n@R15\nM=D\n@DOUBLE\n0;JMP\n(RET)\n@END\n(END)\n@END\n0;JMP\n\
+                      (DOUBLE)\nD=D+A\n@R15\nA=M\n0;JMP\n";
+        let mut s = session(source);
+        for _ in 0..5 {
+            assert_eq!(s.step(), Stop::Step);
+        }
+        assert_eq!(s.next(), Stop::Step);
+        assert_eq!(s.emulator.pc, 6);
+        assert_eq!(s.emulator.d, 15);
+    }
+
+    #[test]
+    fn reverse() {
+        let source = fs::read_to_string("test/max/Max.asm").unwrap();
+        let mut s = session(&source);
+        let script = "set R0 3\nset R1 9\ncontinue\nlast-write R2\nbreak OUTPUT_D\nreverse-continue\nprint R2\n\
+                      reverse-step 2\nreverse-continue\nlast-write R2\nquit\n";
+        let mut out = Vec::new();
+        repl(&mut s, &mut script.as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("RAM[2] (ARG, R2) was last written at cycle 11: 0 -> 9\n[PC 13] 23: M=D "));
+        assert!(out.contains("Breakpoint 1\n[PC 12] 22: @R2"));
+        assert!(out.contains("RAM[2] (ARG, R2) = 0"));
+        assert!(out.contains("(hdb) [PC 8] 16: @OUTPUT_D"));
+        assert!(out.contains("Reached the start of the recorded history\n[PC 0]"));
+        assert!(out.contains("RAM[2] (ARG, R2) wasn't written in the recorded history"));
+        assert_eq!((s.emulator.cycles, s.emulator.d), (0, 0));
+
+        // Undoing can't wind a timer back
+        s.emulator.attach(Box::new(Timer::new(0x6001))).unwrap();
+        assert_eq!(s.step(), Stop::Step);
+        assert_eq!(s.reverse_step(), Stop::Irreversible);
+        assert_eq!(s.emulator.cycles, 1);
+    }
+}
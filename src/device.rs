@@ -0,0 +1,251 @@
+use std::io::{self, Write};
+
+use emulator::RAM_SIZE;
+use platform::Platform;
+
+/// A memory-mapped peripheral. Its words live in RAM like any other, so
+/// views of RAM like the debugger's see them, and the emulator lets the
+/// device see and answer the CPU's reads and writes in its range.
+pub trait Device {
+    /// The predefined symbol for its first word
+    fn name(&self) -> &str;
+
+    /// The first address it's mapped at
+    fn address(&self) -> u16;
+
+    /// How many words it's mapped over
+    fn words(&self) -> u16 {
+        1
+    }
+
+    /// Determines if the emulator has to consult it at all, which devices
+    /// that are nothing but RAM don't
+    fn hooked(&self) -> bool {
+        true
+    }
+
+    /// The value the CPU reads at an address in range, given what RAM holds
+    /// there and the cycle it's read on
+    fn read(&mut self, _address: u16, stored: u16, _cycles: u64) -> u16 {
+        stored
+    }
+
+    /// Sees a value the CPU wrote to an address in range, after RAM stored it
+    fn write(&mut self, _address: u16, _value: u16, _cycles: u64) {}
+}
+
+/// The screen, a bit per pixel, which the CPU draws on by writing RAM
+pub struct Screen {
+    address: u16,
+    words: u16,
+}
+
+impl Screen {
+    pub fn new(address: u16, words: u16) -> Screen {
+        Screen { address, words }
+    }
+}
+
+impl Device for Screen {
+    fn name(&self) -> &str {
+        "SCREEN"
+    }
+
+    fn address(&self) -> u16 {
+        self.address
+    }
+
+    fn words(&self) -> u16 {
+        self.words
+    }
+
+    fn hooked(&self) -> bool {
+        false
+    }
+}
+
+/// The keyboard register, holding the code of the key being pressed, which
+/// whatever drives the keyboard writes
+pub struct Keyboard {
+    address: u16,
+}
+
+impl Keyboard {
+    pub fn new(address: u16) -> Keyboard {
+        Keyboard { address }
+    }
+}
+
+impl Device for Keyboard {
+    fn name(&self) -> &str {
+        "KBD"
+    }
+
+    fn address(&self) -> u16 {
+        self.address
+    }
+
+    fn hooked(&self) -> bool {
+        false
+    }
+}
+
+/// Cycles per tick of the timer
+pub const TIMER_PERIOD: u64 = 1000;
+
+/// Counts thousands of cycles since it was last written, wrapping at 16 bits
+pub struct Timer {
+    address: u16,
+    start: u64,
+}
+
+impl Timer {
+    pub fn new(address: u16) -> Timer {
+        Timer { address, start: 0 }
+    }
+}
+
+impl Device for Timer {
+    fn name(&self) -> &str {
+        "TIMER"
+    }
+
+    fn address(&self) -> u16 {
+        self.address
+    }
+
+    fn read(&mut self, _address: u16, _stored: u16, cycles: u64) -> u16 {
+        (cycles.saturating_sub(self.start) / TIMER_PERIOD) as u16
+    }
+
+    fn write(&mut self, _address: u16, _value: u16, cycles: u64) {
+        self.start = cycles;
+    }
+}
+
+/// Reads as a new pseudo-random number each time, from a xorshift generator
+/// that writing seeds
+pub struct Random {
+    address: u16,
+    state: u16,
+}
+
+impl Random {
+    pub fn new(address: u16) -> Random {
+        Random { address, state: 1 }
+    }
+}
+
+impl Device for Random {
+    fn name(&self) -> &str {
+        "RANDOM"
+    }
+
+    fn address(&self) -> u16 {
+        self.address
+    }
+
+    fn read(&mut self, _address: u16, _stored: u16, _cycles: u64) -> u16 {
+        self.state ^= self.state << 7;
+        self.state ^= self.state >> 9;
+        self.state ^= self.state << 8;
+        self.state
+    }
+
+    fn write(&mut self, _address: u16, value: u16, _cycles: u64) {
+        // Zero would stick at zero forever
+        self.state = if value == 0 { 1 } else { value };
+    }
+}
+
+/// Writes the low byte of each word written to it to an output, always
+/// reading as ready, 0
+pub struct Serial<W: Write> {
+    address: u16,
+    output: W,
+}
+
+impl<W: Write> Serial<W> {
+    pub fn new(address: u16, output: W) -> Serial<W> {
+        Serial { address, output }
+    }
+}
+
+impl<W: Write> Device for Serial<W> {
+    fn name(&self) -> &str {
+        "SERIAL"
+    }
+
+    fn address(&self) -> u16 {
+        self.address
+    }
+
+    fn read(&mut self, _address: u16, _stored: u16, _cycles: u64) -> u16 {
+        0
+    }
+
+    fn write(&mut self, _address: u16, value: u16, _cycles: u64) {
+        let _ = self.output.write_all(&[value as u8]);
+        let _ = self.output.flush();
+    }
+}
+
+/// Creates a device from `KIND` or `KIND@ADDRESS`, where the kind is
+/// `timer`, `random` or `serial`, which by default follow the platform's
+/// keyboard
+pub fn parse(spec: &str, platform: &Platform) -> Result<Box<dyn Device>, String> {
+    let (kind, address) = match spec.split_once('@') {
+        Some((kind, address)) => (kind, Some(address.parse::<u16>().map_err(|_| format!("Bad device address {}", address))?)),
+        None => (spec, None),
+    };
+    let device: Box<dyn Device> = match kind {
+        "timer" => Box::new(Timer::new(address.unwrap_or(platform.keyboard + 1))),
+        "random" => Box::new(Random::new(address.unwrap_or(platform.keyboard + 2))),
+        "serial" => Box::new(Serial::new(address.unwrap_or(platform.keyboard + 3), io::stdout())),
+        _ => return Err(format!("Unknown device {}", kind)),
+    };
+    if device.address() as usize + device.words() as usize > RAM_SIZE {
+        return Err(format!("{} at {} is past the end of RAM, which has {} words", kind, device.address(), RAM_SIZE));
+    }
+    Ok(device)
+}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+    use emulator::Emulator;
+    use device::{parse, Device, Random, Serial, Timer};
+    use platform::Platform;
+
+    #[test]
+    fn devices() {
+        let source = "@TIMER\nD=M\n@R0\nM=D\n@RANDOM\nD=M\n@R1\nM=D\n@RANDOM\nD=M\n@R2\nM=D\n(END)\n@END\n0;JMP\n";
+        let mut a = Assembler::new();
+        a.define("TIMER", 24577);
+        a.define("RANDOM", 24578);
+        a.assemble(source.as_bytes()).unwrap();
+
+        let mut e = Emulator::new(a.words());
+        e.attach(Box::new(Timer::new(24577))).unwrap();
+        e.attach(Box::new(Random::new(24578))).unwrap();
+        e.cycles = 5500;
+        e.run(u64::MAX);
+        assert_eq!(e.ram[0], 5);
+        assert!(e.ram[1] != 0 && e.ram[2] != 0 && e.ram[1] != e.ram[2]);
+        // Reading RAM rather than through the device gets what's stored there
+        assert_eq!(e.read(24578), 0);
+        assert!(e.mapped(24577) && e.mapped(0x4000) && e.mapped(0x6000) && !e.mapped(24579));
+
+        let mut serial = Serial::new(24579, Vec::new());
+        serial.write(24579, 0x168, 0);
+        serial.write(24579, 'i' as u16, 0);
+        assert_eq!(serial.output, b"hi");
+
+        let hack = Platform::hack();
+        assert_eq!(parse("timer@40000", &hack).err().unwrap(), "timer at 40000 is past the end of RAM, which has 32768 words");
+        assert_eq!(parse("random@32767", &hack).unwrap().address(), 32767);
+        assert_eq!(parse("timer", &hack).unwrap().address(), 24577);
+        let board = Platform::parse("keyboard = 28672\n").unwrap();
+        assert_eq!(parse("serial", &board).unwrap().address(), 28675);
+    }
+}
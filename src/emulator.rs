@@ -0,0 +1,344 @@
+use blocks::Blocks;
+use device::Device;
+use platform::Platform;
+
+/// Words of instruction memory
+pub const ROM_SIZE: usize = 0x8000;
+
+/// Words of data memory, including the memory mapped screen and keyboard
+pub const RAM_SIZE: usize = 0x8000;
+
+pub const SCREEN: u16 = 0x4000;
+pub const KBD: u16 = 0x6000;
+
+/// Devices an emulator can have attached, numbered by a 16 bit hook
+pub const MAX_DEVICES: usize = u16::MAX as usize;
+
+/// Masks an address to the 15 bits the Hack address bus carries
+const ADDRESS_MASK: u16 = 0x7FFF;
+
+/// A RAM write made by a single instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryWrite {
+    pub address: u16,
+    /// The value the write replaced
+    pub old: u16,
+    pub new: u16,
+}
+
+/// What a single step changed, which is enough to undo it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Delta {
+    /// The registers before the step
+    pub pc: u16,
+    pub a: u16,
+    pub d: u16,
+    pub write: Option<MemoryWrite>,
+}
+
+/// Runs Hack machine code the way the CPU does, one instruction per cycle
+pub struct Emulator {
+    rom: Vec<u16>,
+    pub ram: Vec<u16>,
+    pub a: u16,
+    pub d: u16,
+    pub pc: u16,
+    pub cycles: u64,
+    /// Decoded on the first run
+    blocks: Option<Box<Blocks>>,
+    devices: Vec<Box<dyn Device>>,
+    /// The device hooked at each RAM address, as its index plus one, or 0
+    hooks: Vec<u16>,
+    platform: Platform,
+}
+
+impl Emulator {
+    /// An emulator of the standard Hack computer. Everything else goes by the
+    /// platform it's given.
+    #[cfg(test)]
+    pub fn new(program: &[u16]) -> Emulator {
+        Emulator::with_platform(program, &Platform::hack())
+    }
+
+    /// An emulator with a platform's ROM size and devices
+    pub fn with_platform(program: &[u16], platform: &Platform) -> Emulator {
+        let mut rom = vec![0; platform.rom_size];
+        let len = program.len().min(platform.rom_size);
+        rom[..len].copy_from_slice(&program[..len]);
+        let mut e = Emulator {
+            rom,
+            ram: vec![0; RAM_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+            blocks: None,
+            devices: Vec::new(),
+            hooks: vec![0; RAM_SIZE],
+            platform: platform.clone(),
+        };
+        // Parsing the platform kept its devices within the limit
+        for device in platform.devices() {
+            e.attach(device).expect("Too many platform devices");
+        }
+        e
+    }
+
+    /// The word of ROM at an address, 0 past the end of the board's ROM
+    pub fn instruction(&self, address: u16) -> u16 {
+        self.rom.get((address & ADDRESS_MASK) as usize).cloned().unwrap_or(0)
+    }
+
+    /// Words of ROM on the board, which may be fewer than the PC can address
+    pub fn rom_size(&self) -> usize {
+        self.rom.len()
+    }
+
+    /// Maps a device into RAM, over any device already at its addresses.
+    /// Fails once MAX_DEVICES are attached.
+    pub fn attach(&mut self, device: Box<dyn Device>) -> Result<(), String> {
+        if self.devices.len() == MAX_DEVICES {
+            return Err(format!("Can't attach more than {} devices", MAX_DEVICES));
+        }
+        if device.hooked() {
+            let start = device.address() as usize;
+            let end = (start + device.words() as usize).min(RAM_SIZE);
+            for hook in &mut self.hooks[start..end] {
+                *hook = self.devices.len() as u16 + 1;
+            }
+        }
+        self.devices.push(device);
+        Ok(())
+    }
+
+    /// The platform the emulator was created for
+    pub fn platform(&self) -> &Platform {
+        &self.platform
+    }
+
+    /// Determines if any device has to see the CPU's reads and writes, which
+    /// makes its state part of the machine's
+    pub fn has_hooked_devices(&self) -> bool {
+        self.devices.iter().any(|d| d.hooked())
+    }
+
+    /// Determines if a device is mapped at an address
+    pub fn mapped(&self, address: u16) -> bool {
+        self.devices.iter().any(|d| address >= d.address() && ((address - d.address()) as usize) < d.words() as usize)
+    }
+
+    /// What RAM holds, without the side effects of a device reading it
+    pub fn read(&self, address: u16) -> u16 {
+        self.ram[(address & ADDRESS_MASK) as usize]
+    }
+
+    /// Sets RAM, without a device seeing the write, like driving the
+    /// keyboard from outside
+    pub fn write(&mut self, address: u16, value: u16) {
+        self.ram[(address & ADDRESS_MASK) as usize] = value;
+    }
+
+    /// Reads RAM the way the CPU does, through any device hooked there
+    #[inline(always)]
+    pub fn load(&mut self, address: u16) -> u16 {
+        let address = (address & ADDRESS_MASK) as usize;
+        match self.hooks[address] {
+            0 => self.ram[address],
+            hook => self.devices[hook as usize - 1].read(address as u16, self.ram[address], self.cycles),
+        }
+    }
+
+    /// Writes RAM the way the CPU does, letting any device hooked there see it
+    #[inline(always)]
+    pub fn store(&mut self, address: u16, value: u16) {
+        let address = (address & ADDRESS_MASK) as usize;
+        self.ram[address] = value;
+        if self.hooks[address] != 0 {
+            let cycles = self.cycles;
+            self.devices[self.hooks[address] as usize - 1].write(address as u16, value, cycles);
+        }
+    }
+
+    /// Executes the instruction at PC, returning the RAM write it made
+    pub fn step(&mut self) -> Option<MemoryWrite> {
+        let instruction = self.instruction(self.pc);
+        self.cycles += 1;
+
+        if instruction & 0x8000 == 0 {
+            self.a = instruction;
+            self.pc = self.pc.wrapping_add(1);
+            return None;
+        }
+
+        // M and the jump target both come from A as it was before this instruction
+        let address = self.a;
+        let y = if instruction & 0x1000 != 0 { self.load(address) } else { address };
+        let out = alu(self.d, y, instruction);
+
+        let mut write = None;
+        if instruction & 0x8 != 0 {
+            write = Some(MemoryWrite { address: address & ADDRESS_MASK, old: self.read(address), new: out });
+            self.store(address, out);
+        }
+        if instruction & 0x20 != 0 {
+            self.a = out;
+        }
+        if instruction & 0x10 != 0 {
+            self.d = out;
+        }
+
+        if jumps(instruction, out) {
+            self.pc = address & ADDRESS_MASK;
+        } else {
+            self.pc = self.pc.wrapping_add(1);
+        }
+        write
+    }
+
+    /// Executes the instruction at PC, returning how to undo it
+    pub fn step_recorded(&mut self) -> Delta {
+        let (pc, a, d) = (self.pc, self.a, self.d);
+        let write = self.step();
+        Delta { pc, a, d, write }
+    }
+
+    /// Reverts the most recent step, given its delta
+    pub fn undo(&mut self, delta: &Delta) {
+        self.pc = delta.pc;
+        self.a = delta.a;
+        self.d = delta.d;
+        if let Some(w) = delta.write {
+            self.write(w.address, w.old);
+        }
+        self.cycles -= 1;
+    }
+
+    /// Runs until the program halts or `max_cycles` have run in total, the
+    /// same as stepping but much faster, by executing pre-decoded basic blocks
+    pub fn run(&mut self, max_cycles: u64) {
+        let mut blocks = self.blocks.take().unwrap_or_else(|| Box::new(Blocks::new()));
+        blocks.run(self, max_cycles);
+        self.blocks = Some(blocks);
+    }
+
+    /// Determines if the program has reached the `(END) @END 0;JMP` idiom,
+    /// an unconditional jump back to the A-instruction right before it, or
+    /// the PC has left the board's ROM, where there's nothing to run
+    pub fn halted(&self) -> bool {
+        if (self.pc & ADDRESS_MASK) as usize >= self.rom.len() {
+            return true;
+        }
+        let instruction = self.instruction(self.pc);
+        let previous = self.pc.wrapping_sub(1);
+        instruction & 0xE007 == 0xE007
+            && self.a == previous
+            && self.instruction(previous) == previous
+    }
+}
+
+/// Reads the words of a `.hack` file, one 16 digit binary number per line
+pub fn parse_hack(text: &str) -> Result<Vec<u16>, String> {
+    let mut words = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match u16::from_str_radix(line, 2) {
+            Ok(word) if line.len() == 16 => words.push(word),
+            _ => return Err(format!("Invalid instruction on line {}: {}", i + 1, line)),
+        }
+    }
+    Ok(words)
+}
+
+/// Computes the C-instruction's comp bits over D and the A or M operand
+pub fn alu(x: u16, y: u16, instruction: u16) -> u16 {
+    let x = if instruction & 0x800 != 0 { 0 } else { x };
+    let x = if instruction & 0x400 != 0 { !x } else { x };
+    let y = if instruction & 0x200 != 0 { 0 } else { y };
+    let y = if instruction & 0x100 != 0 { !y } else { y };
+    let out = if instruction & 0x80 != 0 { x.wrapping_add(y) } else { x & y };
+    if instruction & 0x40 != 0 { !out } else { out }
+}
+
+/// Determines if a C-instruction's jump bits hold for the ALU output
+pub fn jumps(instruction: u16, out: u16) -> bool {
+    let out = out as i16;
+    (instruction & 0x4 != 0 && out < 0)
+        || (instruction & 0x2 != 0 && out == 0)
+        || (instruction & 0x1 != 0 && out > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use assembler::Assembler;
+    use device::Timer;
+    use emulator::{Emulator, MAX_DEVICES};
+
+    fn load(path: &str) -> Emulator {
+        let mut a = Assembler::new();
+        a.assemble(File::open(path).unwrap()).unwrap();
+        Emulator::new(a.words())
+    }
+
+    #[test]
+    fn max() {
+        for &(x, y) in &[(3, 7), (12, -4i16 as u16)] {
+            let mut e = load("test/max/Max.asm");
+            e.ram[0] = x;
+            e.ram[1] = y;
+            while !e.halted() {
+                e.step();
+            }
+            assert_eq!(e.ram[2] as i16, (x as i16).max(y as i16));
+        }
+    }
+
+    #[test]
+    fn many_devices() {
+        // Past 255 devices, each address still reaches its own timer
+        let mut e = Emulator::new(&[]);
+        for address in 100..400 {
+            e.attach(Box::new(Timer::new(address))).unwrap();
+        }
+        e.cycles = 1_000_000;
+        e.store(399, 0);
+        assert_eq!(e.load(399), 0);
+        assert!((100..399).all(|address| e.load(address) != 0));
+
+        while e.devices.len() < MAX_DEVICES {
+            e.attach(Box::new(Timer::new(100))).unwrap();
+        }
+        assert!(e.attach(Box::new(Timer::new(100))).is_err());
+    }
+
+    #[test]
+    fn rect() {
+        let mut e = load("test/rect/Rect.asm");
+        e.ram[0] = 4;
+        while !e.halted() {
+            e.step();
+        }
+        let rows: Vec<u16> = (0..5).map(|r| e.ram[0x4000 + r * 32]).collect();
+        assert_eq!(rows, vec![0xFFFF, 0xFFFF, 0xFFFF, 0xFFFF, 0]);
+    }
+
+    #[test]
+    fn undo() {
+        let mut e = load("test/rect/Rect.asm");
+        e.ram[0] = 4;
+        let mut deltas = Vec::new();
+        while !e.halted() {
+            deltas.push(e.step_recorded());
+        }
+        for delta in deltas.iter().rev() {
+            e.undo(delta);
+        }
+        let mut fresh = load("test/rect/Rect.asm");
+        fresh.ram[0] = 4;
+        assert_eq!((e.pc, e.a, e.d, e.cycles), (0, 0, 0, 0));
+        assert!(e.ram == fresh.ram);
+    }
+}
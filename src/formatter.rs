@@ -0,0 +1,244 @@
+use token::{Token, TokenKind};
+use scanner::Scanner;
+use parser::Parser;
+use instruction::{self, Instruction, Expression};
+use assembler::AssemblerError;
+
+type Result<T> = std::result::Result<T, AssemblerError>;
+
+/// Indentation of everything but labels
+const INDENT: &str = "    ";
+
+/// Spaces between the longest instruction of a block and its comments
+const COMMENT_GAP: usize = 2;
+
+/// A line of source in canonical form
+enum Line {
+    Blank,
+    Comment(String),
+    Code { text: String, label: bool, comment: Option<String> },
+}
+
+/// Rewrites Hack assembly in the canonical style: labels flush left,
+/// instructions indented, destinations in AMD order, commutative operands
+/// with D first and constants last, and trailing comments aligned within each
+/// block of consecutive lines. Formatting formatted source changes nothing.
+pub fn format_source(source: &str) -> Result<String> {
+    let mut lines: Vec<Line> = Vec::new();
+    for (i, text) in source.lines().enumerate() {
+        lines.extend(format_line(text, i as u32 + 1)?);
+    }
+
+    // Collapse runs of blank lines and drop them from the ends of the file
+    let mut collapsed: Vec<Line> = Vec::with_capacity(lines.len());
+    for line in lines {
+        if let Line::Blank = line {
+            match collapsed.last() {
+                None | Some(Line::Blank) => continue,
+                _ => (),
+            }
+        }
+        collapsed.push(line);
+    }
+    if let Some(Line::Blank) = collapsed.last() {
+        collapsed.pop();
+    }
+
+    let mut out = String::new();
+    let mut block_start = 0;
+    while block_start < collapsed.len() {
+        let block_end = (block_start..collapsed.len())
+            .find(|&i| matches!(collapsed[i], Line::Blank))
+            .unwrap_or(collapsed.len());
+        write_block(&mut out, &collapsed[block_start..block_end]);
+        if block_end < collapsed.len() {
+            out.push('\n');
+        }
+        block_start = block_end + 1;
+    }
+    Ok(out)
+}
+
+fn format_line(text: &str, line: u32) -> Result<Vec<Line>> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut comment = None;
+    let mut directive = None;
+    for result in Scanner::new(text, line) {
+        let token = result?;
+        match token.kind {
+            TokenKind::Comment(c) => comment = Some(c),
+            TokenKind::NewLine => (),
+            TokenKind::Directive(_) => directive = Some(token.kind.to_string()),
+            _ => tokens.push(token),
+        }
+    }
+
+    // Directives sit flush left like labels
+    if let Some(text) = directive {
+        return Ok(vec![Line::Code { text, label: true, comment }]);
+    }
+
+    if tokens.is_empty() {
+        return Ok(vec![match comment {
+            Some(c) => Line::Comment(c),
+            None => Line::Blank,
+        }]);
+    }
+
+    tokens.push(Token::new(TokenKind::NewLine, line));
+    tokens.push(Token::new(TokenKind::EOF, line));
+    let instructions = Parser::new(tokens).parse()?;
+
+    let mut out: Vec<Line> = instructions.iter().map(|i| Line::Code {
+        text: canonical(i),
+        label: matches!(i, Instruction::Label(_)),
+        comment: None,
+    }).collect();
+    if let Some(Line::Code { comment: ref mut c, .. }) = out.first_mut() {
+        *c = comment;
+    }
+    Ok(out)
+}
+
+/// Writes the instruction in canonical form, without indentation
+fn canonical(i: &Instruction) -> String {
+    match i {
+        Instruction::Label(t) | Instruction::AInstruction(t) => t.kind.to_string(),
+        Instruction::CInstruction { dest, comp, jump } => {
+            let mut s = instruction::dest_string(dest);
+            if !s.is_empty() {
+                s.push('=');
+            }
+            // Reordering must not make a comp the assembler rejects valid
+            match i.comp_code() {
+                Some(_) => s.push_str(&canonical_comp(comp)),
+                None => s.push_str(&comp.to_string()),
+            }
+            if let Some(j) = jump {
+                s.push_str(&format!(";{}", j.kind));
+            }
+            s
+        },
+    }
+}
+
+/// Orders the operands of commutative expressions with D first and constants last
+fn canonical_comp(comp: &Expression) -> String {
+    if let Expression::Binary { left, operator, right } = comp {
+        let commutative = matches!(operator.kind, TokenKind::Plus | TokenKind::And | TokenKind::Or);
+        let swap = match (&left.kind, &right.kind) {
+            (TokenKind::DRegister, _) => false,
+            (_, TokenKind::DRegister) => true,
+            (TokenKind::Number(_), _) => true,
+            _ => false,
+        };
+        if commutative && swap {
+            return format!("{}{}{}", right.kind, operator.kind, left.kind);
+        }
+    }
+    comp.to_string()
+}
+
+fn write_block(out: &mut String, block: &[Line]) {
+    let width = |line: &Line| match line {
+        Line::Code { text, label, comment: Some(_) } => text.len() + if *label { 0 } else { INDENT.len() },
+        _ => 0,
+    };
+    let column = block.iter().map(width).max().unwrap_or(0) + COMMENT_GAP;
+
+    for (i, line) in block.iter().enumerate() {
+        match line {
+            Line::Blank => (),
+            Line::Comment(c) => {
+                // Comments sit at the indentation of the code they describe
+                let indented = block[i..].iter().find_map(|l| match l {
+                    Line::Code { label, .. } => Some(!label),
+                    _ => None,
+                }).unwrap_or(false);
+                if indented {
+                    out.push_str(INDENT);
+                }
+                out.push_str(&format!("//{}", c));
+            },
+            Line::Code { text, label, comment } => {
+                let mut code = String::new();
+                if !label {
+                    code.push_str(INDENT);
+                }
+                code.push_str(text);
+                match comment {
+                    Some(c) => out.push_str(&format!("{:w$}//{}", code, c, w = column)),
+                    None => out.push_str(&code),
+                }
+            },
+        }
+        out.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+    use formatter::format_source;
+
+    const SOURCE: &str = "\
+// Computes R2 = max(R0, R1)
+
+
+   @R0
+   D=M              // D = first number
+   @R1
+  D=D-M   // D = first number - second number
+   // jump when the first is greater
+   @OUTPUT_FIRST
+   D;JGT
+(OUTPUT_FIRST)
+DM=M+D // add
+  D=1+D
+(END)   // done
+   @END
+   0;JMP
+
+";
+
+    const FORMATTED: &str = "\
+// Computes R2 = max(R0, R1)
+
+    @R0
+    D=M     // D = first number
+    @R1
+    D=D-M   // D = first number - second number
+    // jump when the first is greater
+    @OUTPUT_FIRST
+    D;JGT
+(OUTPUT_FIRST)
+    MD=D+M  // add
+    D=D+1
+(END)       // done
+    @END
+    0;JMP
+";
+
+    #[test]
+    fn canonical_style() {
+        assert_eq!(format_source(SOURCE).unwrap(), FORMATTED);
+        // What the assembler rejects stays rejected
+        assert_eq!(format_source("D=0+A\n").unwrap(), "    D=0+A\n");
+
+        // Swapping operands doesn't change the code
+        let source = "D=A+D\nAM=M+D\nD=1+M\nM=A&D\nD=M|D\n";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(formatted, "    D=D+A\n    AM=D+M\n    D=M+1\n    M=D&A\n    D=D|M\n");
+        let assemble = |text: &str| {
+            let mut a = Assembler::new();
+            a.assemble(text.as_bytes()).unwrap();
+            a.words().to_vec()
+        };
+        assert_eq!(assemble(source), assemble(&formatted));
+    }
+
+    #[test]
+    fn idempotent() {
+        assert_eq!(format_source(FORMATTED).unwrap(), FORMATTED);
+    }
+}
@@ -0,0 +1,394 @@
+use std::io;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+
+use emulator::{Emulator, RAM_SIZE};
+
+/// Instructions executed between checks for an interrupt from the debugger
+const INTERRUPT_INTERVAL: u64 = 0x10000;
+
+/// The bytes of RAM, two to a word
+const RAM_BYTES: u64 = 2 * RAM_SIZE as u64;
+
+/// SIGTRAP, reported whenever the target stops
+const STOP_REPLY: &str = "S05";
+
+/// Describes A, D and PC as three 16 bit registers
+const TARGET_XML: &str = "<?xml version=\"1.0\"?>\
+<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+<target version=\"1.0\">\
+<feature name=\"org.nand2tetris.hack\">\
+<reg name=\"a\" bitsize=\"16\" type=\"uint16\" regnum=\"0\"/>\
+<reg name=\"d\" bitsize=\"16\" type=\"int16\" regnum=\"1\"/>\
+<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\" regnum=\"2\"/>\
+</feature>\
+</target>";
+
+/// A GDB remote serial protocol stub controlling the emulator. RAM is byte
+/// addressed with two little-endian bytes per word, while PC and breakpoints
+/// are ROM word addresses.
+pub struct Stub {
+    emulator: Emulator,
+    breakpoints: Vec<u16>,
+}
+
+impl Stub {
+    pub fn new(emulator: Emulator) -> Stub {
+        Stub {
+            emulator,
+            breakpoints: Vec::new(),
+        }
+    }
+
+    /// Accepts a single debugger connection and serves it until it detaches
+    pub fn listen(&mut self, port: u16) -> io::Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!("Listening on {}", listener.local_addr()?);
+        let (stream, address) = listener.accept()?;
+        println!("Debugger connected from {}", address);
+        self.serve(stream)
+    }
+
+    fn serve(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        let mut reader = io::BufReader::new(stream.try_clone()?);
+        while let Some((packet, intact)) = read_packet(&mut reader)? {
+            // A damaged packet is refused so the debugger sends it again
+            if !intact {
+                stream.write_all(b"-")?;
+                continue;
+            }
+            stream.write_all(b"+")?;
+            let reply = match packet.as_bytes().first() {
+                Some(b'c') | Some(b's') => {
+                    if !self.resume_at(&packet[1..]) {
+                        write_packet(&mut stream, "E01")?;
+                        continue;
+                    }
+                    let step = packet.starts_with('s');
+                    // Poll for the Ctrl-C byte the debugger sends to interrupt a continue
+                    stream.set_nonblocking(true)?;
+                    let poll = &mut stream;
+                    self.resume(step, &mut || {
+                        let mut byte = [0u8];
+                        matches!(poll.read(&mut byte), Ok(1) if byte[0] == 0x03)
+                    });
+                    stream.set_nonblocking(false)?;
+                    STOP_REPLY.to_string()
+                },
+                Some(b'k') => return Ok(()),
+                _ => self.handle(&packet),
+            };
+            write_packet(&mut stream, &reply)?;
+            if packet == "D" {
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers every packet that doesn't run the program
+    pub fn handle(&mut self, packet: &str) -> String {
+        let (command, rest) = packet.split_at(packet.len().min(1));
+        match command {
+            "?" => STOP_REPLY.to_string(),
+            "g" => self.registers().iter().map(|&r| hex_word(r)).collect(),
+            "G" => {
+                let words: Vec<u16> = (0..3).filter_map(|i| rest.get(i * 4..i * 4 + 4).and_then(parse_word)).collect();
+                if words.len() != 3 {
+                    return String::from("E01");
+                }
+                self.set_register(0, words[0]);
+                self.set_register(1, words[1]);
+                self.set_register(2, words[2]);
+                String::from("OK")
+            },
+            "p" => match parse_hex(rest).and_then(|n| self.registers().get(n as usize).cloned()) {
+                Some(value) => hex_word(value),
+                None => String::from("E01"),
+            },
+            "P" => {
+                let mut parts = rest.splitn(2, '=');
+                match (parts.next().and_then(parse_hex), parts.next().and_then(parse_word)) {
+                    (Some(n), Some(value)) if n < 3 => {
+                        self.set_register(n, value);
+                        String::from("OK")
+                    },
+                    _ => String::from("E01"),
+                }
+            },
+            // A read running past the end of RAM stops there
+            "m" => match parse_range(rest) {
+                Some((address, end)) if address < RAM_BYTES || address == end => (address..end.min(RAM_BYTES))
+                    .map(|byte| format!("{:02x}", self.read_byte(byte)))
+                    .collect(),
+                _ => String::from("E01"),
+            },
+            "M" => {
+                let mut parts = rest.splitn(2, ':');
+                let range = parts.next().and_then(parse_range);
+                let data = parts.next().unwrap_or("");
+                match range {
+                    Some((address, end)) if end <= RAM_BYTES && data.is_ascii() && data.len() as u64 == 2 * (end - address) => {
+                        let bytes: Option<Vec<u8>> = data.as_bytes().chunks(2)
+                            .map(|pair| std::str::from_utf8(pair).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()))
+                            .collect();
+                        match bytes {
+                            Some(bytes) => {
+                                for (byte, value) in (address..end).zip(bytes) {
+                                    self.write_byte(byte, value);
+                                }
+                                String::from("OK")
+                            },
+                            None => String::from("E01"),
+                        }
+                    },
+                    _ => String::from("E01"),
+                }
+            },
+            "Z" | "z" => {
+                let mut parts = rest.split(',');
+                let kind = parts.next();
+                let address = parts.next().and_then(parse_hex);
+                match (kind, address) {
+                    (Some("0"), Some(address)) | (Some("1"), Some(address)) => match self.rom_address(address) {
+                        Some(address) => {
+                            self.breakpoints.retain(|&b| b != address);
+                            if command == "Z" {
+                                self.breakpoints.push(address);
+                            }
+                            String::from("OK")
+                        },
+                        None => String::from("E01"),
+                    },
+                    _ => String::new(),
+                }
+            },
+            "H" => String::from("OK"),
+            "D" => String::from("OK"),
+            "q" => self.query(packet),
+            _ => String::new(),
+        }
+    }
+
+    fn query(&self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return String::from("PacketSize=4000;qXfer:features:read+");
+        }
+        if let Some(annex) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_range(annex) {
+                Some((offset, length)) => {
+                    let start = (offset as usize).min(TARGET_XML.len());
+                    let end = (start + length as usize).min(TARGET_XML.len());
+                    let prefix = if end == TARGET_XML.len() { "l" } else { "m" };
+                    format!("{}{}", prefix, &TARGET_XML[start..end])
+                },
+                None => String::from("E01"),
+            };
+        }
+        match packet {
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            _ => String::new(),
+        }
+    }
+
+    /// Runs a single instruction, or until a breakpoint, the program halting
+    /// or the debugger interrupting
+    pub fn resume(&mut self, step: bool, interrupted: &mut dyn FnMut() -> bool) {
+        loop {
+            if self.emulator.halted() {
+                return;
+            }
+            self.emulator.step();
+            if step || self.breakpoints.contains(&self.emulator.pc) {
+                return;
+            }
+            if self.emulator.cycles.is_multiple_of(INTERRUPT_INTERVAL) && interrupted() {
+                return;
+            }
+        }
+    }
+
+    /// Moves PC to the address a `c` or `s` packet gives, if any. Returns
+    /// false when the address isn't in ROM.
+    fn resume_at(&mut self, address: &str) -> bool {
+        if address.is_empty() {
+            return true;
+        }
+        match parse_hex(address).and_then(|a| self.rom_address(a)) {
+            Some(address) => {
+                self.emulator.pc = address;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn rom_address(&self, address: u64) -> Option<u16> {
+        if address < self.emulator.rom_size() as u64 { Some(address as u16) } else { None }
+    }
+
+    fn registers(&self) -> [u16; 3] {
+        [self.emulator.a, self.emulator.d, self.emulator.pc]
+    }
+
+    fn set_register(&mut self, n: u64, value: u16) {
+        match n {
+            0 => self.emulator.a = value,
+            1 => self.emulator.d = value,
+            _ => self.emulator.pc = value,
+        }
+    }
+
+    fn read_byte(&self, byte: u64) -> u8 {
+        if byte >= RAM_BYTES {
+            return 0;
+        }
+        let word = self.emulator.read((byte / 2) as u16);
+        if byte.is_multiple_of(2) { word as u8 } else { (word >> 8) as u8 }
+    }
+
+    fn write_byte(&mut self, byte: u64, value: u8) {
+        if byte >= RAM_BYTES {
+            return;
+        }
+        let address = (byte / 2) as u16;
+        let word = self.emulator.read(address);
+        let word = if byte.is_multiple_of(2) {
+            (word & 0xFF00) | value as u16
+        } else {
+            (word & 0x00FF) | (value as u16) << 8
+        };
+        self.emulator.write(address, word);
+    }
+}
+
+/// Reads the next `$data#checksum` packet, skipping acknowledgements, along
+/// with whether its checksum matches
+fn read_packet<R: BufRead>(reader: &mut R) -> io::Result<Option<(String, bool)>> {
+    let mut byte = [0u8];
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        if byte[0] == b'$' {
+            break;
+        }
+    }
+    let mut data = Vec::new();
+    reader.read_until(b'#', &mut data)?;
+    data.pop();
+    let mut sent = [0u8; 2];
+    reader.read_exact(&mut sent)?;
+    let intact = std::str::from_utf8(&sent).ok()
+        .and_then(|hex| u8::from_str_radix(hex, 16).ok()) == Some(checksum(&data));
+    Ok(Some((String::from_utf8_lossy(&data).into_owned(), intact)))
+}
+
+fn write_packet<W: Write>(writer: &mut W, data: &str) -> io::Result<()> {
+    write!(writer, "${}#{:02x}", data, checksum(data.as_bytes()))?;
+    writer.flush()
+}
+
+/// The sum of a packet's bytes modulo 256
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s, 16).ok()
+}
+
+/// Parses `address,length` in hex into the first byte and the one past the last
+fn parse_range(s: &str) -> Option<(u64, u64)> {
+    let mut parts = s.splitn(2, ',');
+    let address = parts.next().and_then(parse_hex)?;
+    Some((address, address.checked_add(parts.next().and_then(parse_hex)?)?))
+}
+
+/// A word as the target sends it, low byte first
+fn hex_word(value: u16) -> String {
+    format!("{:02x}{:02x}", value & 0xFF, value >> 8)
+}
+
+fn parse_word(s: &str) -> Option<u16> {
+    if s.len() != 4 {
+        return None;
+    }
+    let low = u16::from_str_radix(&s[0..2], 16).ok()?;
+    let high = u16::from_str_radix(&s[2..4], 16).ok()?;
+    Some(high << 8 | low)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use assembler::Assembler;
+    use emulator::Emulator;
+    use std::io::Cursor;
+    use gdb::{read_packet, write_packet, Stub};
+
+    fn stub() -> Stub {
+        let mut a = Assembler::new();
+        a.assemble(File::open("test/max/Max.asm").unwrap()).unwrap();
+        Stub::new(Emulator::new(a.words()))
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let mut s = stub();
+        assert_eq!(s.handle("M0,4:05000300"), "OK");
+        assert_eq!(s.handle("m0,6"), "050003000000");
+        assert_eq!(s.handle("P1=3412"), "OK");
+        assert_eq!(s.handle("g"), "000034120000");
+        assert_eq!(s.handle("p2"), "0000");
+        assert!(s.handle("qXfer:features:read:target.xml:0,1000").starts_with("l<?xml"));
+    }
+
+    #[test]
+    fn memory_bounds() {
+        let mut s = stub();
+        assert_eq!(s.handle("mfffe,4"), "0000");
+        assert_eq!(s.handle("m10000,2"), "E01");
+        assert_eq!(s.handle("m1,ffffffffffffffff"), "E01");
+        assert_eq!(s.handle("M0,2:050"), "E01");
+        assert_eq!(s.handle("M0,2:05é"), "E01");
+        assert_eq!(s.handle("M0,2:05zz"), "E01");
+        assert_eq!(s.handle("Mffff,2:0500"), "E01");
+        assert_eq!(s.handle("m0,2"), "0000");
+    }
+
+    #[test]
+    fn breakpoints() {
+        let mut s = stub();
+        s.handle("M0,4:05000300");
+        assert_eq!(s.handle("Z0,c,1"), "OK");
+        s.resume(false, &mut || false);
+        assert_eq!(s.handle("p2"), "0c00");
+        assert_eq!(s.handle("z0,c,1"), "OK");
+        s.resume(false, &mut || false);
+        assert_eq!(s.handle("m4,2"), "0500");
+        s.resume(true, &mut || false);
+        assert_eq!(s.handle("p2"), "0f00");
+
+        assert_eq!(s.handle("Z0,10000,1"), "E01");
+        assert_eq!(s.handle("z0,8000,1"), "E01");
+        assert!(!s.resume_at("10000c"));
+        assert!(s.resume_at("c"));
+        assert_eq!(s.handle("p2"), "0c00");
+    }
+
+    #[test]
+    fn framing() {
+        let mut out = Vec::new();
+        write_packet(&mut out, "OK").unwrap();
+        assert_eq!(out, b"$OK#9a");
+
+        let mut input = Cursor::new(&b"+$OK#9a$OK#00"[..]);
+        assert_eq!(read_packet(&mut input).unwrap(), Some((String::from("OK"), true)));
+        assert_eq!(read_packet(&mut input).unwrap(), Some((String::from("OK"), false)));
+        assert_eq!(read_packet(&mut input).unwrap(), None);
+    }
+}
@@ -21,18 +21,19 @@ impl Instruction {
         }
     }
 
-    pub fn binary_string(&self, symbols: &mut SymbolTable) -> Result<Option<String>> {
-        if let Some(b) = self.binary(symbols)? {
-            return Ok(Some(format!("{:016b}", b)))
+    /// The source line the instruction was written on
+    pub fn line(&self) -> u32 {
+        match self {
+            Instruction::Label(t) | Instruction::AInstruction(t) => t.line,
+            Instruction::CInstruction { dest: _, comp, jump: _ } => comp.line(),
         }
-        Ok(None)
     }
 
     pub fn binary(&self, symbols: &mut SymbolTable) -> Result<Option<u16>> {
         use instruction::Instruction::*;
         match self {
             Label(_) => Ok(None),
-            AInstruction(t) => Ok(Some(self.a_binary(&t, symbols)?)),
+            AInstruction(t) => Ok(Some(self.a_binary(t, symbols)?)),
             CInstruction { dest, comp, jump } => Ok(Some(self.c_binary(dest, comp, jump)?)),
         }
     }
@@ -40,32 +41,46 @@ impl Instruction {
     fn a_binary(&self, token: &Token, symbols: &mut SymbolTable) -> Result<u16> {
         match token.kind {
             TokenKind::Address(n) => {
-                if n > std::u16::MAX as u32 {
+                if n > u16::MAX as u32 {
                     return Err(self.error("Address value greater than 16-bit address width", token.line))
                 }
                 Ok(n as u16)
             },
-            TokenKind::Symbol(ref s) => Ok(symbols.address_for(&s).clone()),
+            TokenKind::Symbol(ref s) => symbols.address_for(s).ok_or_else(|| {
+                if symbols.is_strict() {
+                    return self.error(&format!("Undeclared symbol {}", s), token.line);
+                }
+                let (first, last) = symbols.variable_range();
+                self.error(&format!("No RAM left for variable {}, which go from {} to {}", s, first, last), token.line)
+            }),
             _ => Err(self.error("Token cannot be encoded as a instruction", token.line))
         }
     }
 
+    /// The a-bit and comp bits of a C-instruction, if it has valid ones
+    pub fn comp_code(&self) -> Option<u16> {
+        match self {
+            Instruction::CInstruction { comp, .. } => Some(self.opcode(comp) | self.comp_bits(comp).ok()?),
+            _ => None,
+        }
+    }
+
     fn c_binary(&self, dest: &Vec<Token>, comp: &Expression, jump: &Option<Token>) -> Result<u16> {
         let mut code: u16 = 0xE000;
-        code |= self.opcode(&comp);
-        code |= self.comp_bits(&comp)?;
-        code |= self.dest_bits(&dest)?;
-        code |= self.jump_bits(&jump)?;
+        code |= self.opcode(comp);
+        code |= self.comp_bits(comp)?;
+        code |= self.dest_bits(dest)?;
+        code |= self.jump_bits(jump)?;
         Ok(code)
     }
 
     fn opcode(&self, comp: &Expression) -> u16 {
         match comp {
             Expression::Binary{ left, operator: _, right } => {
-                self.memory_code(&left) | self.memory_code(&right)
+                self.memory_code(left) | self.memory_code(right)
             },
-            Expression::Unary{ operator: _, right } => self.memory_code(&right),
-            Expression::Literal(t) => self.memory_code(&t)
+            Expression::Unary{ operator: _, right } => self.memory_code(right),
+            Expression::Literal(t) => self.memory_code(t)
         }
     }
 
@@ -81,10 +96,11 @@ impl Instruction {
         match comp {
             Expression::Binary{ left, operator, right } => {
                 match operator.kind {
+                    // The commutative operators take their operands either way round
                     Plus => match (&left.kind, &right.kind) {
-                        (DRegister, Number(1)) => Ok(0x7C0),
-                        (ARegister, Number(1)) | (Memory, Number(1)) => Ok(0xDC0),
-                        (DRegister, ARegister) | (DRegister, Memory) => Ok(0x80),
+                        (DRegister, Number(1)) | (Number(1), DRegister) => Ok(0x7C0),
+                        (ARegister, Number(1)) | (Memory, Number(1)) | (Number(1), ARegister) | (Number(1), Memory) => Ok(0xDC0),
+                        (DRegister, ARegister) | (DRegister, Memory) | (ARegister, DRegister) | (Memory, DRegister) => Ok(0x80),
                         _ => Err(self.error("Invalid + binary expression", left.line)),
                     },
                     Minus => match (&left.kind, &right.kind) {
@@ -95,11 +111,11 @@ impl Instruction {
                         _ => Err(self.error("Invalid - binary expression", left.line)),
                     },
                     And => match (&left.kind, &right.kind) {
-                        (DRegister, ARegister) | (DRegister, Memory) => Ok(0x0),
+                        (DRegister, ARegister) | (DRegister, Memory) | (ARegister, DRegister) | (Memory, DRegister) => Ok(0x0),
                         _ => Err(self.error("Invalid & binary expression", left.line)),
                     },
                     Or => match (&left.kind, &right.kind) {
-                        (DRegister, ARegister) | (DRegister, Memory) => Ok(0x540),
+                        (DRegister, ARegister) | (DRegister, Memory) | (ARegister, DRegister) | (Memory, DRegister) => Ok(0x540),
                         _ => Err(self.error("Invalid | binary expression", left.line)),
                     },
                     _ => Err(self.error("Invalid binary expression", operator.line)),
@@ -172,6 +188,75 @@ impl Instruction {
     }
 }
 
+/// Every computation a C-instruction can perform, as written in source
+pub const COMP_MNEMONICS: [&str; 28] = [
+    "0", "1", "-1", "D", "A", "!D", "!A", "-D", "-A", "D+1", "A+1", "D-1", "A-1", "D+A",
+    "D-A", "A-D", "D&A", "D|A", "M", "!M", "-M", "M+1", "M-1", "D+M", "D-M", "M-D", "D&M", "D|M",
+];
+
+pub const JUMP_MNEMONICS: [&str; 7] = ["JGT", "JEQ", "JGE", "JLT", "JNE", "JLE", "JMP"];
+
+/// The comp bits of each computation over A, which reads M instead when the a-bit is set
+const COMP_CODES: [(u16, &str); 18] = [
+    (0xA80, "0"), (0xFC0, "1"), (0xE80, "-1"), (0x300, "D"), (0xC00, "A"), (0x340, "!D"),
+    (0xC40, "!A"), (0x3C0, "-D"), (0xCC0, "-A"), (0x7C0, "D+1"), (0xDC0, "A+1"), (0x380, "D-1"),
+    (0xC80, "A-1"), (0x080, "D+A"), (0x4C0, "D-A"), (0x1C0, "A-D"), (0x000, "D&A"), (0x540, "D|A"),
+];
+
+/// The mnemonic of a C-instruction's comp bits, written with A for the A or M
+/// operand, if it has one
+pub fn comp_mnemonic(word: u16) -> Option<&'static str> {
+    COMP_CODES.iter().find(|&&(bits, _)| bits == word & 0xFC0).map(|&(_, mnemonic)| mnemonic)
+}
+
+/// The comp of a C-instruction as the assembler spells it, with M for the
+/// operand when the a-bit is set
+pub fn comp_string(word: u16) -> String {
+    match comp_mnemonic(word) {
+        Some(mnemonic) if word & 0x1000 != 0 => mnemonic.replace('A', "M"),
+        Some(mnemonic) => mnemonic.to_string(),
+        // The ALU computes something for every combination, even undocumented ones
+        None => format!("comp({:07b})", (word >> 6) & 0x7F),
+    }
+}
+
+/// Writes a machine instruction as assembly, naming the value of an
+/// A-instruction with the symbol it was assembled from
+pub fn disassemble(word: u16, symbol: Option<&str>) -> String {
+    if word & 0x8000 == 0 {
+        return match symbol {
+            Some(s) => format!("@{}", s),
+            None => format!("@{}", word),
+        };
+    }
+
+    let comp = comp_string(word);
+    let dest: String = [(0x20, 'A'), (0x8, 'M'), (0x10, 'D')].iter()
+        .filter(|&&(bit, _)| word & bit != 0)
+        .map(|&(_, c)| c)
+        .collect();
+
+    let mut s = String::new();
+    if !dest.is_empty() {
+        s.push_str(&dest);
+        s.push('=');
+    }
+    s.push_str(&comp);
+    if word & 0x7 != 0 {
+        s.push(';');
+        s.push_str(JUMP_MNEMONICS[(word & 0x7) as usize - 1]);
+    }
+    s
+}
+
+/// Writes the destination registers in the canonical AMD order
+pub fn dest_string(dest: &[Token]) -> String {
+    [TokenKind::ARegister, TokenKind::Memory, TokenKind::DRegister].iter()
+        .filter(|k| dest.iter().any(|t| t.kind == **k))
+        .map(|k| k.to_string())
+        .collect()
+}
+
 #[derive(Debug)]
 pub struct InstructionError {
     description: String,
@@ -179,12 +264,16 @@ pub struct InstructionError {
 }
 
 impl InstructionError {
-    fn new(description: &str, line: u32) -> InstructionError {
+    pub fn new(description: &str, line: u32) -> InstructionError {
         InstructionError {
             description: String::from(description),
-            line: line,
+            line,
         }
     }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
 }
 
 impl std::fmt::Display for InstructionError {
@@ -200,4 +289,34 @@ pub enum Expression {
     Binary { left: Token, operator: Token, right: Token },
     Unary { operator: Token, right: Token },
     Literal(Token),
+}
+
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expression::Binary { left, operator, right } => write!(f, "{}{}{}", left.kind, operator.kind, right.kind),
+            Expression::Unary { operator, right } => write!(f, "{}{}", operator.kind, right.kind),
+            Expression::Literal(t) => write!(f, "{}", t.kind),
+        }
+    }
+}
+
+impl Expression {
+    pub fn line(&self) -> u32 {
+        match self {
+            Expression::Binary { left, operator: _, right: _ } => left.line,
+            Expression::Unary { operator, right: _ } => operator.line,
+            Expression::Literal(t) => t.line,
+        }
+    }
+
+    /// Determines if evaluating the expression reads the M register
+    pub fn reads_memory(&self) -> bool {
+        let is_memory = |t: &Token| t.kind == TokenKind::Memory;
+        match self {
+            Expression::Binary { left, operator: _, right } => is_memory(left) || is_memory(right),
+            Expression::Unary { operator: _, right } => is_memory(right),
+            Expression::Literal(t) => is_memory(t),
+        }
+    }
 }
\ No newline at end of file
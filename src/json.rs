@@ -0,0 +1,350 @@
+use std::fmt;
+
+/// A JSON document. Object members keep the order they were inserted in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn object(members: Vec<(&str, Value)>) -> Value {
+        Value::Object(members.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    pub fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    pub fn number<N: Into<f64>>(n: N) -> Value {
+        Value::Number(n.into())
+    }
+
+    pub fn parse(text: &str) -> Result<Value, ParseError> {
+        let mut p = Parser { chars: text.chars().collect(), cursor: 0, depth: 0 };
+        let value = p.value()?;
+        p.skip_whitespace();
+        if p.cursor < p.chars.len() {
+            return Err(p.error("Unexpected trailing characters"));
+        }
+        Ok(value)
+    }
+
+    /// Looks up an object member, or `Null` when there isn't one
+    pub fn get(&self, key: &str) -> &Value {
+        match self {
+            Value::Object(members) => members.iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v)
+                .unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+static NULL: Value = Value::Null;
+
+/// How deeply arrays and objects may nest, so hostile input can't overflow the stack
+const MAX_DEPTH: usize = 128;
+
+struct Parser {
+    chars: Vec<char>,
+    cursor: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn value(&mut self) -> Result<Value, ParseError> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if c == '{' || c == '[' => {
+                if self.depth == MAX_DEPTH {
+                    return Err(self.error("Too deeply nested"));
+                }
+                self.depth += 1;
+                let value = if c == '{' { self.object() } else { self.array() };
+                self.depth -= 1;
+                value
+            }
+            Some('"') => Ok(Value::String(self.string()?)),
+            Some('t') => self.keyword("true", Value::Bool(true)),
+            Some('f') => self.keyword("false", Value::Bool(false)),
+            Some('n') => self.keyword("null", Value::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            _ => Err(self.error("Expected a value")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, ParseError> {
+        self.cursor += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.cursor += 1;
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("Expected a member name"));
+            }
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Object(members)),
+                _ => return Err(self.error("Expected , or } in object")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.cursor += 1;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.cursor += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(items)),
+                _ => return Err(self.error("Expected , or ] in array")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.cursor += 1;
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.next() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => {
+                        let mut code = self.hex()?;
+                        // Characters outside the basic plane come as surrogate pairs
+                        if (0xD800..0xDC00).contains(&code) && self.peek() == Some('\\') {
+                            self.cursor += 1;
+                            self.expect('u')?;
+                            let low = self.hex()?;
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                        }
+                        s.push(std::char::from_u32(code).unwrap_or('\u{FFFD}'));
+                    },
+                    _ => return Err(self.error("Invalid escape sequence")),
+                },
+                Some(c) => s.push(c),
+                None => return Err(self.error("Unterminated string")),
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32, ParseError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.next().and_then(|c| c.to_digit(16));
+            match digit {
+                Some(d) => code = code * 16 + d,
+                None => return Err(self.error("Invalid unicode escape")),
+            }
+        }
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.cursor;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' || c == 'e' || c == 'E' {
+                self.cursor += 1;
+            } else {
+                break;
+            }
+        }
+        let text: String = self.chars[start..self.cursor].iter().collect();
+        text.parse::<f64>()
+            .map(Value::Number)
+            .map_err(|_| self.error("Invalid number"))
+    }
+
+    fn keyword(&mut self, word: &str, value: Value) -> Result<Value, ParseError> {
+        for c in word.chars() {
+            if self.next() != Some(c) {
+                return Err(self.error("Invalid literal"));
+            }
+        }
+        Ok(value)
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), ParseError> {
+        if self.next() != Some(c) {
+            return Err(self.error(&format!("Expected {}", c)));
+        }
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_whitespace()) {
+            self.cursor += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.cursor).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.cursor += 1;
+        c
+    }
+
+    fn error(&self, description: &str) -> ParseError {
+        ParseError {
+            description: String::from(description),
+            offset: self.cursor,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    description: String,
+    offset: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "JSON error: [Offset {}] {} ", self.offset, self.description)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => {
+                if n.fract() == 0.0 && n.abs() < 1e15 {
+                    write!(f, "{}", *n as i64)
+                } else {
+                    write!(f, "{}", n)
+                }
+            },
+            Value::String(s) => write_string(f, s),
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            },
+            Value::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use json::Value;
+
+    #[test]
+    fn serialize() {
+        let v = Value::object(vec![
+            ("name", Value::string("say \"hi\"\n")),
+            ("sizes", Value::Array(vec![Value::number(1), Value::number(2.5)])),
+        ]);
+        assert_eq!(v.to_string(), r#"{"name":"say \"hi\"\n","sizes":[1,2.5]}"#);
+    }
+
+    #[test]
+    fn parse() {
+        let v = Value::parse(r#" {"id": 3, "ok": true, "none": null, "list": [-1.5e2, "a\u00e9\n"], "o": {}} "#).unwrap();
+        assert_eq!(v.get("id").as_u64(), Some(3));
+        assert_eq!(v.get("ok").as_bool(), Some(true));
+        assert_eq!(v.get("none"), &Value::Null);
+        assert_eq!(v.get("missing"), &Value::Null);
+        assert_eq!(v.get("list").as_array().unwrap(), &[Value::number(-150), Value::string("a\u{e9}\n")][..]);
+        assert_eq!(Value::parse(&v.to_string()).unwrap(), v);
+        assert!(Value::parse("[1, 2").is_err());
+        assert!(Value::parse(&"[".repeat(100_000)).is_err());
+        assert!(Value::parse(&format!("{}{}", "[".repeat(100), "]".repeat(100))).is_ok());
+    }
+}
@@ -0,0 +1,206 @@
+use std::fmt;
+
+use emulator::Emulator;
+
+type Result<T> = std::result::Result<T, KeyScriptError>;
+
+// Hack key codes for keys that aren't printable characters
+pub const NEWLINE: u16 = 128;
+pub const BACKSPACE: u16 = 129;
+pub const LEFT: u16 = 130;
+pub const UP: u16 = 131;
+pub const RIGHT: u16 = 132;
+pub const DOWN: u16 = 133;
+pub const HOME: u16 = 134;
+pub const END: u16 = 135;
+pub const PAGE_UP: u16 = 136;
+pub const PAGE_DOWN: u16 = 137;
+pub const INSERT: u16 = 138;
+pub const DELETE: u16 = 139;
+pub const ESCAPE: u16 = 140;
+pub const F1: u16 = 141;
+
+/// Names accepted in keyboard scripts, and used when recording them
+const NAMES: [(&str, u16); 15] = [
+    ("NONE", 0),
+    ("SPACE", 32),
+    ("NEWLINE", NEWLINE),
+    ("BACKSPACE", BACKSPACE),
+    ("LEFT", LEFT),
+    ("UP", UP),
+    ("RIGHT", RIGHT),
+    ("DOWN", DOWN),
+    ("HOME", HOME),
+    ("END", END),
+    ("PAGE_UP", PAGE_UP),
+    ("PAGE_DOWN", PAGE_DOWN),
+    ("INSERT", INSERT),
+    ("DELETE", DELETE),
+    ("ESCAPE", ESCAPE),
+];
+
+/// The value KBD takes from a cycle on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    pub cycle: u64,
+    pub code: u16,
+}
+
+/// A keyboard script: lines of a cycle count and the key held from then on,
+/// as a key code, a name like `LEFT` or `NONE`, or a single character.
+/// `//` starts a comment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Script {
+    events: Vec<Event>,
+    next: usize,
+}
+
+impl Script {
+    pub fn new() -> Script {
+        Script::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Script> {
+        let mut script = Script::new();
+        for (i, line) in text.lines().enumerate() {
+            let line_num = i as u32 + 1;
+            let line = line.split("//").next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let cycle = words.next().and_then(|c| c.parse::<u64>().ok())
+                .ok_or_else(|| KeyScriptError::new(line_num, "Expected a cycle count"))?;
+            let key = words.next().ok_or_else(|| KeyScriptError::new(line_num, "Expected a key"))?;
+            let code = parse_key(key).ok_or_else(|| KeyScriptError::new(line_num, &format!("Unknown key {}", key)))?;
+            if words.next().is_some() {
+                return Err(KeyScriptError::new(line_num, "Expected one key per line"));
+            }
+            if script.events.last().is_some_and(|e| e.cycle > cycle) {
+                return Err(KeyScriptError::new(line_num, "Cycle counts must not decrease"));
+            }
+            script.events.push(Event { cycle, code });
+        }
+        Ok(script)
+    }
+
+    /// Adds an event when it changes the key held
+    pub fn record(&mut self, cycle: u64, code: u16) {
+        let held = self.events.last().map(|e| e.code).unwrap_or(0);
+        if held != code {
+            self.events.push(Event { cycle, code });
+        }
+    }
+
+    /// Sets KBD from every event due by the emulator's cycle count. Call it
+    /// before each step.
+    pub fn apply(&mut self, emulator: &mut Emulator) {
+        while let Some(event) = self.events.get(self.next) {
+            if event.cycle > emulator.cycles {
+                break;
+            }
+            emulator.write(emulator.platform().keyboard, event.code);
+            self.next += 1;
+        }
+    }
+}
+
+fn parse_key(key: &str) -> Option<u16> {
+    if let Ok(code) = key.parse::<u16>() {
+        return Some(code);
+    }
+    if let Some(&(_, code)) = NAMES.iter().find(|&&(name, _)| name.eq_ignore_ascii_case(key)) {
+        return Some(code);
+    }
+    if let Some(n) = key.strip_prefix('F').or_else(|| key.strip_prefix('f')).and_then(|n| n.parse::<u16>().ok()) {
+        if (1..=12).contains(&n) {
+            return Some(F1 + n - 1);
+        }
+    }
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_graphic() => Some(c as u16),
+        _ => None,
+    }
+}
+
+fn key_name(code: u16) -> String {
+    if let Some(&(name, _)) = NAMES.iter().find(|&&(_, c)| c == code) {
+        return name.to_string();
+    }
+    match code {
+        c if (F1..F1 + 12).contains(&c) => format!("F{}", c - F1 + 1),
+        // Digits would read as key codes
+        c if c < 0x7F && (c as u8).is_ascii_graphic() && !(c as u8).is_ascii_digit() => (c as u8 as char).to_string(),
+        c => c.to_string(),
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for e in &self.events {
+            writeln!(f, "{} {}", e.cycle, key_name(e.code))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct KeyScriptError {
+    description: String,
+    line: u32,
+}
+
+impl KeyScriptError {
+    fn new(line: u32, description: &str) -> KeyScriptError {
+        KeyScriptError {
+            description: description.to_string(),
+            line,
+        }
+    }
+}
+
+impl fmt::Display for KeyScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Keyboard script error: [Line {}] {} ", self.line, self.description)
+    }
+}
+
+impl std::error::Error for KeyScriptError {}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+    use emulator::Emulator;
+    use keys::Script;
+
+    #[test]
+    fn round_trip() {
+        let script = Script::parse("// serve\n0 SPACE\n10 none\n25 left  // move\n40 a\n41 7\n50 F3\n").unwrap();
+        assert_eq!(script.events.iter().map(|e| e.code).collect::<Vec<u16>>(), vec![32, 0, 130, 97, 7, 143]);
+        let text = script.to_string();
+        assert_eq!(text, "0 SPACE\n10 NONE\n25 LEFT\n40 a\n41 7\n50 F3\n");
+        assert_eq!(Script::parse(&text).unwrap(), script);
+        assert!(Script::parse("10 LEFT\n5 NONE\n").is_err());
+    }
+
+    #[test]
+    fn replay() {
+        // Sums KBD into R1 every 6 cycles
+        let mut a = Assembler::new();
+        a.assemble("(LOOP)\n@KBD\nD=M\n@R1\nM=D+M\n@LOOP\n0;JMP\n".as_bytes()).unwrap();
+
+        let mut recording = Script::new();
+        recording.record(12, 3);
+        recording.record(20, 3);
+        recording.record(30, 0);
+
+        let mut e = Emulator::new(a.words());
+        let mut script = Script::parse(&recording.to_string()).unwrap();
+        while e.cycles < 60 {
+            script.apply(&mut e);
+            e.step();
+        }
+        assert_eq!(e.ram[1], 3 * 3);
+    }
+}
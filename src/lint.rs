@@ -0,0 +1,160 @@
+use token::{Token, TokenKind};
+use instruction::Instruction;
+use symbol_table::SymbolTable;
+
+/// What the A register was last loaded with at a point in the program
+#[derive(Debug, Clone, PartialEq)]
+pub enum AValue {
+    Label(String),
+    Variable(String),
+    Constant(u32),
+    Unknown,
+}
+
+/// Determines what A was last loaded with when each instruction executes
+pub fn track_a(instructions: &[Instruction], symbols: &SymbolTable) -> Vec<AValue> {
+    let mut values = Vec::with_capacity(instructions.len());
+    let mut a = AValue::Unknown;
+
+    for i in instructions {
+        // Any jump may land on a label, so nothing is known about A there
+        if let Instruction::Label(_) = i {
+            a = AValue::Unknown;
+        }
+        values.push(a.clone());
+
+        match i {
+            Instruction::Label(_) => (),
+            Instruction::AInstruction(t) => a = loaded_value(t, symbols),
+            Instruction::CInstruction { dest, comp: _, jump } => {
+                if dest.iter().any(|t| t.kind == TokenKind::ARegister) {
+                    a = AValue::Unknown;
+                }
+                if let Some(TokenKind::Jump) = jump.as_ref().map(|j| &j.kind) {
+                    a = AValue::Unknown;
+                }
+            },
+        }
+    }
+
+    values
+}
+
+/// Tracks the A register through the program, warning about the common ways
+/// a C-instruction misuses it: jumping to a RAM address, addressing RAM with
+/// a ROM address, or using the same A for both memory and a jump.
+pub fn lint(instructions: &[Instruction], symbols: &SymbolTable) -> Vec<Warning> {
+    let mut warnings = Vec::new();
+
+    for (i, a) in instructions.iter().zip(track_a(instructions, symbols)) {
+        if let Instruction::CInstruction { dest, comp, jump } = i {
+            let uses_memory = comp.reads_memory() || dest.iter().any(|t| t.kind == TokenKind::Memory);
+
+            if uses_memory {
+                if let AValue::Label(ref l) = a {
+                    warnings.push(Warning::new(&format!("M accessed while A holds the ROM label {}", l), i.line()));
+                }
+            }
+
+            if let Some(j) = jump {
+                match a {
+                    AValue::Variable(ref v) => {
+                        warnings.push(Warning::new(&format!("Jump through A loaded from the variable {} instead of a label", v), j.line));
+                    },
+                    AValue::Constant(n) => {
+                        warnings.push(Warning::new(&format!("Jump through A loaded from the constant {} instead of a label", n), j.line));
+                    },
+                    _ => (),
+                }
+
+                if uses_memory {
+                    warnings.push(Warning::new("Instruction uses M and jumps, so A addresses both RAM and ROM", j.line));
+                }
+            }
+        }
+    }
+
+    warnings
+}
+
+fn loaded_value(token: &Token, symbols: &SymbolTable) -> AValue {
+    match token.kind {
+        TokenKind::Address(n) => AValue::Constant(n),
+        TokenKind::Symbol(ref s) if symbols.is_label(s) => AValue::Label(s.clone()),
+        TokenKind::Symbol(ref s) => AValue::Variable(s.clone()),
+        _ => AValue::Unknown,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Warning {
+    description: String,
+    line: u32,
+}
+
+impl Warning {
+    fn new(description: &str, line: u32) -> Warning {
+        Warning {
+            description: String::from(description),
+            line,
+        }
+    }
+}
+
+impl Warning {
+    pub fn line(&self) -> u32 {
+        self.line
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Warning: [Line {}] {} ", self.line, self.description)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+    use lint::{lint, Warning};
+
+    fn lint_source(source: &str) -> Vec<Warning> {
+        let mut a = Assembler::new();
+        let instructions = a.load(source.as_bytes()).unwrap();
+        lint(&instructions, a.symbols())
+    }
+
+    #[test]
+    fn jump_through_variable() {
+        let warnings = lint_source("@counter\nD;JGT\n");
+        assert_eq!(warnings, vec![
+            Warning::new("Jump through A loaded from the variable counter instead of a label", 2),
+        ]);
+    }
+
+    #[test]
+    fn memory_through_label() {
+        let warnings = lint_source("(LOOP)\n@LOOP\nM=D\n@LOOP\n0;JMP\n");
+        assert_eq!(warnings, vec![
+            Warning::new("M accessed while A holds the ROM label LOOP", 3),
+        ]);
+    }
+
+    #[test]
+    fn memory_and_jump() {
+        let warnings = lint_source("(LOOP)\n@LOOP\nM;JGT\n");
+        assert_eq!(warnings, vec![
+            Warning::new("M accessed while A holds the ROM label LOOP", 3),
+            Warning::new("Instruction uses M and jumps, so A addresses both RAM and ROM", 3),
+        ]);
+    }
+
+    #[test]
+    fn computed_jump() {
+        assert!(lint_source("@R15\nA=M\n0;JMP\n").is_empty());
+    }
+}
@@ -0,0 +1,517 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+
+use token::TokenKind;
+use instruction::{Instruction, COMP_MNEMONICS, JUMP_MNEMONICS};
+use assembler::{Assembler, AssemblerError};
+use json::Value;
+use platform::Platform;
+use transport;
+
+// Constants defined by the Language Server Protocol
+const SEVERITY_ERROR: u32 = 1;
+const SEVERITY_WARNING: u32 = 2;
+const COMPLETION_VARIABLE: u32 = 6;
+const COMPLETION_KEYWORD: u32 = 14;
+const COMPLETION_CONSTANT: u32 = 21;
+const PARSE_ERROR: i32 = -32700;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const TEXT_DOCUMENT_SYNC_FULL: u32 = 1;
+
+/// How many broken lines are left out to analyze the rest of a document
+const RECOVERY_ATTEMPTS: usize = 20;
+
+/// The result of assembling an open document
+struct Analysis {
+    assembler: Assembler,
+    error: Option<AssemblerError>,
+}
+
+impl Analysis {
+    /// Assembles the document, reporting its first error. Navigation keeps
+    /// working while it's being edited, as the assembler's symbols and
+    /// instructions come from the document with each broken line blanked.
+    fn new(text: &str, platform: &Platform) -> Analysis {
+        let mut lines: Vec<&str> = text.lines().collect();
+        let mut error = None;
+        for _ in 0..RECOVERY_ATTEMPTS {
+            let mut assembler = Assembler::with_platform(platform);
+            let err = match assembler.assemble(lines.join("\n").as_bytes()) {
+                Ok(_) => return Analysis { assembler, error },
+                Err(err) => err,
+            };
+            let line = err.line().map(|l| l as usize).filter(|&l| l > 0 && l <= lines.len() && !lines[l - 1].is_empty());
+            error = error.or(Some(err));
+            match line {
+                Some(l) => lines[l - 1] = "",
+                None => break,
+            }
+        }
+        Analysis { assembler: Assembler::with_platform(platform), error }
+    }
+}
+
+/// What the server can do, answered to `initialize`
+fn capabilities() -> Value {
+    Value::object(vec![
+        ("capabilities", Value::object(vec![
+            ("textDocumentSync", Value::number(TEXT_DOCUMENT_SYNC_FULL)),
+            ("definitionProvider", Value::Bool(true)),
+            ("referencesProvider", Value::Bool(true)),
+            ("hoverProvider", Value::Bool(true)),
+            ("completionProvider", Value::object(vec![
+                ("triggerCharacters", Value::Array(vec![
+                    Value::string("@"),
+                    Value::string("="),
+                    Value::string(";"),
+                ])),
+            ])),
+        ])),
+        ("serverInfo", Value::object(vec![("name", Value::string("hackassembler"))])),
+    ])
+}
+
+fn error_response(id: Value, code: i32, message: &str) -> Value {
+    Value::object(vec![
+        ("jsonrpc", Value::string("2.0")),
+        ("id", id),
+        ("error", Value::object(vec![
+            ("code", Value::number(code)),
+            ("message", Value::string(message)),
+        ])),
+    ])
+}
+
+/// A language server for Hack assembly speaking JSON-RPC over a pair of streams
+pub struct Server<W: Write> {
+    output: W,
+    documents: HashMap<String, String>,
+    platform: Platform,
+}
+
+impl<W: Write> Server<W> {
+    pub fn new(output: W) -> Server<W> {
+        Server {
+            output,
+            documents: HashMap::new(),
+            platform: Platform::hack(),
+        }
+    }
+
+    /// Handles messages until the client sends `exit` or closes the input
+    pub fn serve<R: BufRead>(&mut self, input: &mut R) -> io::Result<()> {
+        loop {
+            let message = match transport::read_message(input) {
+                Ok(Some(message)) => message,
+                Ok(None) => break,
+                // The whole body was read, so the next message is still in step
+                Err(ref err) if err.kind() == io::ErrorKind::InvalidData => {
+                    let response = error_response(Value::Null, PARSE_ERROR, &err.to_string());
+                    transport::write_message(&mut self.output, &response)?;
+                    continue;
+                },
+                Err(err) => return Err(err),
+            };
+            let method = message.get("method").as_str().unwrap_or("");
+            if method == "exit" {
+                break;
+            }
+
+            let params = message.get("params");
+            match message.get("id") {
+                Value::Null => self.notification(method, params)?,
+                id => {
+                    let response = match self.request(method, params) {
+                        Ok(result) => Value::object(vec![
+                            ("jsonrpc", Value::string("2.0")),
+                            ("id", id.clone()),
+                            ("result", result),
+                        ]),
+                        Err((code, message)) => error_response(id.clone(), code, &message),
+                    };
+                    transport::write_message(&mut self.output, &response)?;
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> io::Result<()> {
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("").to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").as_str().unwrap_or("");
+                self.documents.insert(uri.clone(), text.to_string());
+                self.publish_diagnostics(&uri)
+            },
+            "textDocument/didChange" => {
+                // Only full document sync is offered, so the last change is the whole text
+                let changes = params.get("contentChanges").as_array().unwrap_or(&[]);
+                if let Some(text) = changes.last().and_then(|c| c.get("text").as_str()) {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+                self.publish_diagnostics(&uri)
+            },
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                Ok(())
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i32, String)> {
+        match method {
+            "initialize" => {
+                // A platform profile given by the client applies to every document
+                if let Some(path) = params.get("initializationOptions").get("platform").as_str() {
+                    self.platform = Platform::load(path).map_err(|e| (INVALID_PARAMS, e))?;
+                }
+                Ok(capabilities())
+            },
+            "shutdown" => Ok(Value::Null),
+            "textDocument/definition" => Ok(self.definition(params)),
+            "textDocument/references" => Ok(self.references(params)),
+            "textDocument/hover" => Ok(self.hover(params)),
+            "textDocument/completion" => Ok(self.completion(params)),
+            _ => Err((METHOD_NOT_FOUND, format!("Unsupported method {}", method))),
+        }
+    }
+
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let text = match self.documents.get(uri) {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+        let analysis = Analysis::new(text, &self.platform);
+
+        let mut diagnostics = Vec::new();
+        if let Some(ref err) = analysis.error {
+            let line = err.line().unwrap_or(1);
+            diagnostics.push(diagnostic(text, line, SEVERITY_ERROR, &err.to_string()));
+        }
+        for warning in analysis.assembler.lint() {
+            diagnostics.push(diagnostic(text, warning.line(), SEVERITY_WARNING, warning.description()));
+        }
+
+        let notification = Value::object(vec![
+            ("jsonrpc", Value::string("2.0")),
+            ("method", Value::string("textDocument/publishDiagnostics")),
+            ("params", Value::object(vec![
+                ("uri", Value::string(uri)),
+                ("diagnostics", Value::Array(diagnostics)),
+            ])),
+        ]);
+        transport::write_message(&mut self.output, &notification)
+    }
+
+    /// The document, its analysis and the symbol under the cursor of a request
+    fn lookup<'a>(&'a self, params: &'a Value) -> Option<(&'a str, &'a str, Analysis, String)> {
+        let uri = params.get("textDocument").get("uri").as_str()?;
+        let text = self.documents.get(uri)?;
+        let line = params.get("position").get("line").as_u64()? as usize;
+        let character = params.get("position").get("character").as_u64()? as usize;
+        let word = word_at(text.lines().nth(line)?, character)?;
+        Some((uri, text, Analysis::new(text, &self.platform), word))
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let (uri, text, analysis, word) = match self.lookup(params) {
+            Some(l) => l,
+            None => return Value::Null,
+        };
+        match label_definition(analysis.assembler.instructions(), &word) {
+            Some(line) => location(uri, text, line, "(", &word),
+            None => Value::Null,
+        }
+    }
+
+    fn references(&self, params: &Value) -> Value {
+        let (uri, text, analysis, word) = match self.lookup(params) {
+            Some(l) => l,
+            None => return Value::Null,
+        };
+        let instructions = analysis.assembler.instructions();
+
+        let mut locations = Vec::new();
+        if params.get("context").get("includeDeclaration").as_bool().unwrap_or(false) {
+            if let Some(line) = label_definition(instructions, &word) {
+                locations.push(location(uri, text, line, "(", &word));
+            }
+        }
+        for i in instructions {
+            if let Instruction::AInstruction(t) = i {
+                if t.kind == TokenKind::Symbol(word.clone()) {
+                    locations.push(location(uri, text, t.line, "@", &word));
+                }
+            }
+        }
+        Value::Array(locations)
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let (_, _, analysis, word) = match self.lookup(params) {
+            Some(l) => l,
+            None => return Value::Null,
+        };
+        let symbols = analysis.assembler.symbols();
+        let description = match symbols.get(&word) {
+            Some(address) if symbols.is_label(&word) => format!("label at ROM {}", address),
+            Some(address) if symbols.variables().contains(&word) => format!("variable at RAM {}", address),
+            Some(address) => format!("predefined symbol for RAM {}", address),
+            None => return Value::Null,
+        };
+        Value::object(vec![
+            ("contents", Value::object(vec![
+                ("kind", Value::string("markdown")),
+                ("value", Value::string(&format!("**{}**: {}", word, description))),
+            ])),
+        ])
+    }
+
+    fn completion(&self, params: &Value) -> Value {
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+        let text = match self.documents.get(uri) {
+            Some(text) => text,
+            None => return Value::Array(Vec::new()),
+        };
+        let line = params.get("position").get("line").as_u64().unwrap_or(0) as usize;
+        let character = params.get("position").get("character").as_u64().unwrap_or(0) as usize;
+        let prefix: String = text.lines().nth(line).unwrap_or("").chars().take(character).collect();
+
+        let item = |label: &str, kind: u32, detail: String| Value::object(vec![
+            ("label", Value::string(label)),
+            ("kind", Value::number(kind)),
+            ("detail", Value::string(&detail)),
+        ]);
+
+        let mut items = Vec::new();
+        if prefix.contains('@') {
+            let analysis = Analysis::new(text, &self.platform);
+            let symbols = analysis.assembler.symbols();
+            for (name, address) in symbols.labels() {
+                items.push(item(name, COMPLETION_CONSTANT, format!("ROM {}", address)));
+            }
+            for (name, address) in symbols.ram_symbols() {
+                let kind = if symbols.variables().iter().any(|v| v == name) { COMPLETION_VARIABLE } else { COMPLETION_CONSTANT };
+                items.push(item(name, kind, format!("RAM {}", address)));
+            }
+        } else if prefix.contains(';') {
+            for j in JUMP_MNEMONICS.iter() {
+                items.push(item(j, COMPLETION_KEYWORD, String::from("jump")));
+            }
+        } else {
+            for c in COMP_MNEMONICS.iter() {
+                items.push(item(c, COMPLETION_KEYWORD, String::from("computation")));
+            }
+        }
+        Value::Array(items)
+    }
+}
+
+/// Determines if the character can be part of a symbol
+fn is_symbol_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.' || c == '$' || c == ':'
+}
+
+/// The symbol touching the character position in a line, if any
+fn word_at(line: &str, character: usize) -> Option<String> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut start = character.min(chars.len());
+    while start > 0 && is_symbol_char(chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = start;
+    while end < chars.len() && is_symbol_char(chars[end]) {
+        end += 1;
+    }
+    if start == end {
+        return None;
+    }
+    Some(chars[start..end].iter().collect())
+}
+
+fn label_definition(instructions: &[Instruction], name: &str) -> Option<u32> {
+    instructions.iter()
+        .find(|i| i.symbol_string() == Some(name))
+        .map(|i| i.line())
+}
+
+fn range(line: u32, start: usize, end: usize) -> Value {
+    let position = |character: usize| Value::object(vec![
+        ("line", Value::number(line.saturating_sub(1))),
+        ("character", Value::number(character as u32)),
+    ]);
+    Value::object(vec![("start", position(start)), ("end", position(end))])
+}
+
+/// The location of a symbol written after a prefix on a line
+fn location(uri: &str, text: &str, line: u32, prefix: &str, name: &str) -> Value {
+    let source = text.lines().nth(line as usize - 1).unwrap_or("");
+    let start = source.find(&format!("{}{}", prefix, name))
+        .map(|i| source[..i].chars().count() + 1)
+        .unwrap_or(0);
+    Value::object(vec![
+        ("uri", Value::string(uri)),
+        ("range", range(line, start, start + name.chars().count())),
+    ])
+}
+
+/// A diagnostic covering a whole line
+fn diagnostic(text: &str, line: u32, severity: u32, message: &str) -> Value {
+    let length = text.lines().nth(line as usize - 1).map(|l| l.chars().count()).unwrap_or(0);
+    Value::object(vec![
+        ("range", range(line, 0, length)),
+        ("severity", Value::number(severity)),
+        ("source", Value::string("hackassembler")),
+        ("message", Value::string(message.trim_end())),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+    use json::Value;
+    use lsp::Server;
+    use transport::{read_message, write_message};
+
+    const SOURCE: &str = "(LOOP)\n@ball_x\nM=M+1\n@LOOP\n0;JMP\n";
+
+    fn exchange(messages: Vec<Value>) -> Vec<Value> {
+        let mut input = Vec::new();
+        for m in &messages {
+            write_message(&mut input, m).unwrap();
+        }
+        let mut output = Vec::new();
+        Server::new(&mut output).serve(&mut Cursor::new(input)).unwrap();
+
+        let mut output = Cursor::new(output);
+        let mut responses = Vec::new();
+        while let Some(r) = read_message(&mut output).unwrap() {
+            responses.push(r);
+        }
+        responses
+    }
+
+    fn open(text: &str) -> Value {
+        Value::object(vec![
+            ("jsonrpc", Value::string("2.0")),
+            ("method", Value::string("textDocument/didOpen")),
+            ("params", Value::object(vec![
+                ("textDocument", Value::object(vec![
+                    ("uri", Value::string("file:///a.asm")),
+                    ("text", Value::string(text)),
+                ])),
+            ])),
+        ])
+    }
+
+    fn request(id: u32, method: &str, line: u32, character: u32) -> Value {
+        Value::object(vec![
+            ("jsonrpc", Value::string("2.0")),
+            ("id", Value::number(id)),
+            ("method", Value::string(method)),
+            ("params", Value::object(vec![
+                ("textDocument", Value::object(vec![("uri", Value::string("file:///a.asm"))])),
+                ("position", Value::object(vec![
+                    ("line", Value::number(line)),
+                    ("character", Value::number(character)),
+                ])),
+            ])),
+        ])
+    }
+
+    #[test]
+    fn diagnostics() {
+        let responses = exchange(vec![open("@1\nD=D*A\n")]);
+        let diagnostics = responses[0].get("params").get("diagnostics").as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].get("range").get("start").get("line").as_u64(), Some(1));
+        assert_eq!(diagnostics[0].get("severity").as_u64(), Some(1));
+    }
+
+    #[test]
+    fn hover_while_broken() {
+        let responses = exchange(vec![
+            open("(LOOP)\n@ball_x\nD=D*A\n@LOOP\n0;JMP\n"),
+            request(1, "textDocument/hover", 1, 3),
+            request(2, "textDocument/definition", 3, 2),
+        ]);
+        let diagnostics = responses[0].get("params").get("diagnostics").as_array().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        let hover = responses[1].get("result").get("contents").get("value");
+        assert_eq!(hover.as_str(), Some("**ball_x**: variable at RAM 16"));
+        assert_eq!(responses[2].get("result").get("range").get("start").get("line").as_u64(), Some(0));
+    }
+
+    #[test]
+    fn malformed() {
+        let mut input = b"Content-Length: 9\r\n\r\n{\"id\": 1,".to_vec();
+        write_message(&mut input, &request(2, "textDocument/hover", 0, 0)).unwrap();
+        let mut output = Vec::new();
+        Server::new(&mut output).serve(&mut Cursor::new(input)).unwrap();
+
+        let mut output = Cursor::new(output);
+        let error = read_message(&mut output).unwrap().unwrap();
+        assert_eq!(error.get("error").get("code"), &Value::number(-32700));
+        assert_eq!(error.get("id"), &Value::Null);
+        assert_eq!(read_message(&mut output).unwrap().unwrap().get("id").as_u64(), Some(2));
+    }
+
+    #[test]
+    fn navigation() {
+        let responses = exchange(vec![
+            open(SOURCE),
+            request(1, "textDocument/definition", 3, 2),
+            request(2, "textDocument/references", 0, 2),
+            request(3, "textDocument/hover", 1, 3),
+            request(4, "textDocument/completion", 3, 1),
+        ]);
+
+        let definition = responses[1].get("result");
+        assert_eq!(definition.get("range").get("start").get("line").as_u64(), Some(0));
+        assert_eq!(definition.get("range").get("start").get("character").as_u64(), Some(1));
+
+        let references = responses[2].get("result").as_array().unwrap();
+        assert_eq!(references.len(), 1);
+        assert_eq!(references[0].get("range").get("start").get("line").as_u64(), Some(3));
+
+        let hover = responses[3].get("result").get("contents").get("value");
+        assert_eq!(hover.as_str(), Some("**ball_x**: variable at RAM 16"));
+
+        let completions = responses[4].get("result").as_array().unwrap();
+        assert!(completions.iter().any(|c| c.get("label").as_str() == Some("LOOP")));
+        assert!(completions.iter().any(|c| c.get("label").as_str() == Some("SCREEN")));
+    }
+
+    #[test]
+    fn platform() {
+        let path = env::temp_dir().join("lsp-test.platform");
+        fs::write(&path, "symbol.LED = 21249\n").unwrap();
+        let initialize = |path: &str| Value::object(vec![
+            ("jsonrpc", Value::string("2.0")),
+            ("id", Value::number(1)),
+            ("method", Value::string("initialize")),
+            ("params", Value::object(vec![
+                ("initializationOptions", Value::object(vec![("platform", Value::string(path))])),
+            ])),
+        ]);
+        let responses = exchange(vec![
+            initialize(path.to_str().unwrap()),
+            open("@LED\nM=1\n"),
+            request(2, "textDocument/hover", 0, 2),
+        ]);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(responses[0].get("result").get("capabilities").get("hoverProvider").as_bool(), Some(true));
+        let hover = responses[2].get("result").get("contents").get("value");
+        assert_eq!(hover.as_str(), Some("**LED**: predefined symbol for RAM 21249"));
+
+        let responses = exchange(vec![initialize("missing.platform")]);
+        assert_eq!(responses[0].get("error").get("code"), &Value::number(-32602));
+    }
+}
@@ -1,7 +1,16 @@
+#![allow(clippy::enum_variant_names, clippy::upper_case_acronyms)]
+
 use std::env;
-use std::time::{SystemTime};
+use std::io;
+use std::fs;
+use std::process;
+use std::time::{Duration, Instant, SystemTime};
 
-use assembler::Assembler;
+use assembler::{Assembler, AssemblerError};
+use cfg::Graph;
+use platform::Platform;
+use stats::Stats;
+use xref::CrossReference;
 
 mod instruction;
 mod parser;
@@ -9,29 +18,979 @@ mod token;
 mod scanner;
 mod scanlines;
 mod symbol_table;
+mod symbol_file;
 mod assembler;
+mod lint;
+mod cfg;
+mod cycles;
+mod json;
+mod stats;
+mod xref;
+mod formatter;
+mod transport;
+mod lsp;
+mod emulator;
+mod blocks;
+mod device;
+mod platform;
+mod debugger;
+mod dap;
+mod gdb;
+mod tst;
+mod screen;
+mod play;
+mod keys;
+mod trace;
+mod checked;
+mod profile;
+mod coverage;
+mod unit;
+mod recompiler;
+
+/// Options for assembling a single file
+struct Options {
+    filepath: String,
+    lint: bool,
+    cycles: bool,
+    stats: bool,
+    stats_json: Option<String>,
+    xref: bool,
+    xref_html: Option<String>,
+    /// Predefined symbols added to the standard ones
+    define: Vec<(String, u16)>,
+    /// Symbol files to import
+    symbols: Vec<String>,
+    export_symbols: Option<String>,
+    /// Undeclared symbols are errors rather than variables
+    strict: bool,
+    platform: Option<String>,
+}
+
+fn parse_options(args: &[String]) -> Option<Options> {
+    let mut filepath = None;
+    let mut options = Options {
+        filepath: String::new(),
+        lint: false,
+        cycles: false,
+        stats: false,
+        stats_json: None,
+        xref: false,
+        xref_html: None,
+        define: Vec::new(),
+        symbols: Vec::new(),
+        export_symbols: None,
+        strict: false,
+        platform: None,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--lint" => options.lint = true,
+            "--cycles" => options.cycles = true,
+            "--stats" => options.stats = true,
+            "--stats-json" => options.stats_json = Some(args.next()?.clone()),
+            "--xref" => options.xref = true,
+            "--xref-html" => options.xref_html = Some(args.next()?.clone()),
+            "--define" => options.define.push(parse_define(args.next()?)?),
+            "--symbols" => options.symbols.push(args.next()?.clone()),
+            "--export-symbols" => options.export_symbols = Some(args.next()?.clone()),
+            "--strict" => options.strict = true,
+            "--platform" => options.platform = Some(args.next()?.clone()),
+            _ if arg.starts_with("--") => return None,
+            _ if filepath.is_none() => filepath = Some(arg.clone()),
+            _ => return None,
+        }
+    }
+
+    options.filepath = filepath?;
+    Some(options)
+}
+
+/// Options for running a program in the emulator
+struct RunOptions {
+    filepath: String,
+    cycles: u64,
+    keys: Option<String>,
+    screen: Option<String>,
+    ascii: bool,
+    trace: Option<String>,
+    vcd: Option<String>,
+    checked: bool,
+    /// RAM set before running, by symbol or address
+    set: Vec<(String, u16)>,
+    profile: bool,
+    listing: Option<String>,
+    flamegraph: Option<String>,
+    coverage: Option<String>,
+    /// Devices to attach, as `KIND` or `KIND@ADDRESS`
+    devices: Vec<String>,
+    define: Vec<(String, u16)>,
+    symbols: Vec<String>,
+    strict: bool,
+    platform: Option<String>,
+}
+
+impl RunOptions {
+    fn profiling(&self) -> bool {
+        self.profile || self.listing.is_some() || self.flamegraph.is_some()
+    }
+
+    /// Determines if the run needs the program's `.asm` source
+    fn needs_source(&self) -> bool {
+        self.profiling() || self.coverage.is_some()
+    }
+}
+
+/// Parses a predefined symbol given as `NAME=ADDRESS`
+fn parse_define(arg: &str) -> Option<(String, u16)> {
+    let (name, address) = arg.split_once('=')?;
+    Some((name.to_string(), address.parse().ok()?))
+}
+
+fn parse_run_options(args: &[String]) -> Option<RunOptions> {
+    let mut filepath = None;
+    let mut options = RunOptions {
+        filepath: String::new(),
+        cycles: debugger::CYCLE_LIMIT,
+        keys: None,
+        screen: None,
+        ascii: false,
+        trace: None,
+        vcd: None,
+        checked: false,
+        set: Vec::new(),
+        profile: false,
+        listing: None,
+        flamegraph: None,
+        coverage: None,
+        devices: Vec::new(),
+        define: Vec::new(),
+        symbols: Vec::new(),
+        strict: false,
+        platform: None,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--cycles" => options.cycles = args.next()?.parse().ok()?,
+            "--keys" => options.keys = Some(args.next()?.clone()),
+            "--screen" => options.screen = Some(args.next()?.clone()),
+            "--ascii" => options.ascii = true,
+            "--trace" => options.trace = Some(args.next()?.clone()),
+            "--vcd" => options.vcd = Some(args.next()?.clone()),
+            "--checked" => options.checked = true,
+            "--profile" => options.profile = true,
+            "--listing" => options.listing = Some(args.next()?.clone()),
+            "--flamegraph" => options.flamegraph = Some(args.next()?.clone()),
+            "--coverage" => options.coverage = Some(args.next()?.clone()),
+            "--device" => options.devices.push(args.next()?.clone()),
+            "--define" => options.define.push(parse_define(args.next()?)?),
+            "--symbols" => options.symbols.push(args.next()?.clone()),
+            "--strict" => options.strict = true,
+            "--platform" => options.platform = Some(args.next()?.clone()),
+            "--set" => {
+                let (name, value) = args.next()?.split_once('=')?;
+                let value = value.parse::<i16>().map(|v| v as u16).or_else(|_| value.parse::<u16>()).ok()?;
+                options.set.push((name.to_string(), value));
+            },
+            _ if arg.starts_with("--") => return None,
+            _ if filepath.is_none() => filepath = Some(arg.clone()),
+            _ => return None,
+        }
+    }
+
+    options.filepath = filepath?;
+    Some(options)
+}
+
+/// Options for playing a program in the terminal
+struct PlayOptions {
+    filepath: String,
+    keys: Option<String>,
+    record: Option<String>,
+    platform: Option<String>,
+    options: play::Options,
+}
+
+fn parse_play_options(args: &[String]) -> Option<PlayOptions> {
+    let mut filepath = None;
+    let mut keys = None;
+    let mut options = play::Options {
+        rate: 1_000_000,
+        style: play::Style::Braille,
+        scale: 1,
+        hold: Duration::from_millis(150),
+        replay: None,
+    };
+    let mut record = None;
+    let mut platform = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--rate" => options.rate = args.next()?.parse().ok()?,
+            "--blocks" => options.style = play::Style::HalfBlock,
+            "--scale" => options.scale = args.next()?.parse().ok()?,
+            "--hold" => options.hold = Duration::from_millis(args.next()?.parse().ok()?),
+            "--keys" => keys = Some(args.next()?.clone()),
+            "--record" => record = Some(args.next()?.clone()),
+            "--platform" => platform = Some(args.next()?.clone()),
+            _ if arg.starts_with("--") => return None,
+            _ if filepath.is_none() => filepath = Some(arg.clone()),
+            _ => return None,
+        }
+    }
+
+    Some(PlayOptions { filepath: filepath?, keys, record, platform, options })
+}
+
+/// Options for the subcommands whose only flags, besides `--platform`, each
+/// take a value
+struct ToolOptions {
+    files: Vec<String>,
+    platform: Option<String>,
+    /// The subcommand's own flags and their values
+    values: Vec<(String, String)>,
+}
+
+impl ToolOptions {
+    /// The value of a flag, the last one given if it's repeated
+    fn value(&self, flag: &str) -> Option<&str> {
+        self.values.iter().rev().find(|(f, _)| f == flag).map(|(_, v)| v.as_ref())
+    }
+
+    /// The single input file
+    fn filepath(&self) -> &str {
+        &self.files[0]
+    }
+}
+
+/// Parses the options of a subcommand that takes the given flags, requiring
+/// exactly one input file when `single` is set
+fn parse_tool_options(args: &[String], flags: &[&str], single: bool) -> Option<ToolOptions> {
+    let mut options = ToolOptions {
+        files: Vec::new(),
+        platform: None,
+        values: Vec::new(),
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_ref() {
+            "--platform" => options.platform = Some(args.next()?.clone()),
+            flag if flags.contains(&flag) => options.values.push((arg.clone(), args.next()?.clone())),
+            _ if arg.starts_with("--") => return None,
+            _ => options.files.push(arg.clone()),
+        }
+    }
+
+    if single && options.files.len() != 1 {
+        return None;
+    }
+    Some(options)
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    if args.len() != 2 {
-        println!("Usage: hackassembler [asm_file]");
-    } else {
-        let now = SystemTime::now();
+    match args.first().map(|a| a.as_ref()) {
+        Some("fmt") => {
+            if !format_files(&args[1..]) {
+                process::exit(1);
+            }
+        },
+        Some("lsp") => {
+            let stdin = io::stdin();
+            if let Err(err) = lsp::Server::new(io::stdout()).serve(&mut stdin.lock()) {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        },
+        Some("dap") => {
+            if let Err(err) = dap::Adapter::new(io::stdout()).serve(&mut io::BufReader::new(io::stdin())) {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        },
+        Some("gdb") => match parse_tool_options(&args[1..], &["--port"], true) {
+            Some(options) => {
+                if !gdb_server(&options) {
+                    process::exit(1);
+                }
+            },
+            None => usage(),
+        },
+        Some("run") => match parse_run_options(&args[1..]) {
+            Some(options) => {
+                if !run(&options) {
+                    process::exit(1);
+                }
+            },
+            None => usage(),
+        },
+        Some("play") => match parse_play_options(&args[1..]) {
+            Some(options) => {
+                if !play_program(options) {
+                    process::exit(1);
+                }
+            },
+            None => usage(),
+        },
+        Some("tst") => match parse_tool_options(&args[1..], &["--coverage"], false) {
+            Some(options) => {
+                if !run_scripts(&options) {
+                    process::exit(1);
+                }
+            },
+            None => usage(),
+        },
+        Some("test") => match parse_tool_options(&args[1..], &[], false) {
+            Some(options) => {
+                if !run_tests(&options) {
+                    process::exit(1);
+                }
+            },
+            None => usage(),
+        },
+        Some("bench") => match parse_tool_options(&args[1..], &["--cycles"], true) {
+            Some(options) => {
+                if !bench(&options) {
+                    process::exit(1);
+                }
+            },
+            None => usage(),
+        },
+        Some("recompile") => match parse_tool_options(&args[1..], &["--output"], true) {
+            Some(options) => {
+                if !recompile(&options) {
+                    process::exit(1);
+                }
+            },
+            None => usage(),
+        },
+        Some("debug") => match parse_tool_options(&args[1..], &[], true) {
+            Some(options) => {
+                if !debug(&options) {
+                    process::exit(1);
+                }
+            },
+            None => usage(),
+        },
+        _ => match parse_options(&args) {
+            Some(options) => assemble(&options),
+            None => usage(),
+        },
+    }
+}
+
+fn usage() {
+    println!("Usage: hackassembler [--lint] [--cycles] [--stats] [--stats-json json_file] [--xref] [--xref-html html_file] [--define name=address]... [--symbols symbol_file]... [--export-symbols symbol_file] [--strict] [--platform platform_file] [asm_file]");
+    println!("       hackassembler fmt [--check] [asm_file...]");
+    println!("       hackassembler lsp");
+    println!("       hackassembler debug [--platform platform_file] asm_file");
+    println!("       hackassembler dap");
+    println!("       hackassembler gdb [--port port] [--platform platform_file] asm_or_hack_file");
+    println!("       hackassembler bench [--cycles count] [--platform platform_file] asm_or_hack_file");
+    println!("       hackassembler recompile [--output c_file] [--platform platform_file] asm_or_hack_file");
+    println!("       hackassembler tst [--coverage lcov_file] [--platform platform_file] [tst_file...]");
+    println!("       hackassembler test [--platform platform_file] [asm_file...]");
+    println!("       hackassembler play [--rate instructions_per_second] [--blocks] [--scale factor] [--hold ms] [--keys key_file] [--record key_file] [--platform platform_file] asm_or_hack_file");
+    println!("       hackassembler run [--cycles count] [--keys key_file] [--screen png_or_pbm_file] [--ascii] [--trace text_file] [--vcd vcd_file] [--checked] [--set name=value]... [--profile] [--listing listing_file] [--flamegraph stacks_file] [--coverage lcov_file] [--device kind[@address]]... [--define name=address]... [--symbols symbol_file]... [--strict] [--platform platform_file] asm_or_hack_file");
+}
+
+fn assemble(options: &Options) {
+    let now = SystemTime::now();
 
-        let filepath = &args[1];
-        println!("Assembling: {}", filepath);
+    let filepath = &options.filepath;
+    println!("Assembling: {}", filepath);
 
-        let mut a = Assembler::new();
-        match a.assemble_file(filepath) {
-            Ok(_) => {
-                if let Ok(elapsed) = now.elapsed() {
-                    println!("Compilation successful. Done in {} seconds!", elapsed.subsec_nanos() as f64 / 1000_000_000_f64);
+    let loaded = load_platform(options.platform.as_ref()).and_then(|platform| Ok((platform, load_symbols(&options.symbols)?)));
+    let (platform, imports) = match loaded {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        },
+    };
+    let mut a = Assembler::with_platform(&platform);
+    a.set_strict(options.strict);
+    if let Err(err) = a.import(&imports) {
+        eprintln!("{}", err);
+        return;
+    }
+    for (name, address) in &options.define {
+        a.define(name, *address);
+    }
+    match a.assemble_file(filepath) {
+        Ok(_) => {
+            if let Some(ref path) = options.export_symbols {
+                if let Err(err) = fs::write(path, symbol_file::export(a.symbols())) {
+                    eprintln!("{}: {}", path, err);
+                    return;
+                }
+            }
+            if options.lint {
+                for warning in a.lint() {
+                    eprintln!("{}", warning);
+                }
+            }
+            let graph = Graph::build(a.instructions(), a.symbols());
+            if options.cycles {
+                print!("{}", cycles::estimate(&graph));
+            }
+            if options.stats || options.stats_json.is_some() {
+                let stats = Stats::collect(a.instructions(), &graph.regions, a.symbols());
+                if options.stats {
+                    print!("{}", stats);
+                }
+                if let Some(ref path) = options.stats_json {
+                    if let Err(err) = fs::write(path, format!("{}\n", stats.to_json())) {
+                        eprintln!("{}", err);
+                        return;
+                    }
+                }
+            }
+            if options.xref || options.xref_html.is_some() {
+                let xref = CrossReference::build(a.instructions(), a.symbols(), &a.declarations());
+                if options.xref {
+                    print!("{}", xref);
+                }
+                if let Some(ref path) = options.xref_html {
+                    let written = fs::read_to_string(filepath)
+                        .and_then(|source| fs::write(path, xref.to_html(filepath, &source)));
+                    if let Err(err) = written {
+                        eprintln!("{}", err);
+                        return;
+                    }
+                }
+            }
+            if let Ok(elapsed) = now.elapsed() {
+                println!("Compilation successful. Done in {} seconds!", elapsed.subsec_nanos() as f64 / 1_000_000_000_f64);
+            }
+        },
+        Err(err) => {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+/// Rewrites each file in the canonical style, or with `--check` only reports
+/// the files that aren't. Returns false if any file failed or needs formatting.
+fn format_files(args: &[String]) -> bool {
+    if args.iter().any(|a| a.starts_with("--") && a != "--check") {
+        usage();
+        return false;
+    }
+    let check = args.iter().any(|a| a == "--check");
+    let mut ok = true;
+
+    for filepath in args.iter().filter(|a| !a.starts_with("--")) {
+        let result = fs::read_to_string(filepath)
+            .map_err(AssemblerError::from)
+            .and_then(|source| Ok((formatter::format_source(&source)?, source)));
+        match result {
+            Ok((formatted, source)) => {
+                if formatted == source {
+                    continue;
+                }
+                if check {
+                    println!("Not formatted: {}", filepath);
+                    ok = false;
+                } else if let Err(err) = fs::write(filepath, formatted) {
+                    eprintln!("{}: {}", filepath, err);
+                    ok = false;
+                } else {
+                    println!("Formatted: {}", filepath);
                 }
             },
             Err(err) => {
-                eprintln!("{}", err);
+                eprintln!("{}: {}", filepath, err);
+                ok = false;
+            },
+        }
+    }
+
+    ok
+}
+
+/// Assembles a file in memory and runs it under the step debugger
+fn debug(options: &ToolOptions) -> bool {
+    let filepath = options.filepath();
+    let result = load_platform(options.platform.as_ref())
+        .and_then(|platform| Ok((platform, fs::read_to_string(filepath).map_err(|e| format!("{}: {}", filepath, e))?)))
+        .and_then(|(platform, source)| {
+            let mut a = Assembler::with_platform(&platform);
+            a.assemble(source.as_bytes()).map_err(|e| e.to_string())?;
+            Ok(debugger::Session::new(&a, &source))
+        });
+    let mut session = match result {
+        Ok(session) => session,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        },
+    };
+
+    let stdin = io::stdin();
+    if let Err(err) = debugger::repl(&mut session, &mut stdin.lock(), &mut io::stdout()) {
+        eprintln!("{}", err);
+        return false;
+    }
+    true
+}
+
+/// Reads a platform profile, or gives the standard Hack computer without one
+fn load_platform(path: Option<&String>) -> Result<Platform, String> {
+    path.map_or_else(|| Ok(Platform::hack()), |path| Platform::load(path))
+}
+
+/// Reads and combines symbol files
+fn load_symbols(paths: &[String]) -> Result<Vec<symbol_file::Import>, String> {
+    let mut imports = Vec::new();
+    for path in paths {
+        imports.extend(symbol_file::load(path)?);
+    }
+    Ok(imports)
+}
+
+/// Loads the words of a program, assembling it unless it's already a `.hack` file
+fn load_program(filepath: &str, platform: &Platform) -> Result<Vec<u16>, String> {
+    load_assembly(filepath, &[], &[], false, platform).map(|(words, _)| words)
+}
+
+/// Loads the words of a program along with the assembler that produced them,
+/// when there was one
+fn load_assembly(filepath: &str, define: &[(String, u16)], imports: &[symbol_file::Import], strict: bool, platform: &Platform) -> Result<(Vec<u16>, Option<Assembler>), String> {
+    let source = fs::read_to_string(filepath).map_err(|e| format!("{}: {}", filepath, e))?;
+    if filepath.ends_with(".hack") {
+        let words = emulator::parse_hack(&source)?;
+        if words.len() > platform.rom_size {
+            return Err(format!("{}: The program is longer than the {} words of ROM", filepath, platform.rom_size));
+        }
+        return Ok((words, None));
+    }
+    let mut a = Assembler::with_platform(platform);
+    a.set_strict(strict);
+    a.import(imports)?;
+    for (name, address) in define {
+        a.define(name, *address);
+    }
+    a.assemble(source.as_bytes()).map_err(|e| e.to_string())?;
+    Ok((a.words().to_vec(), Some(a)))
+}
+
+/// Runs a program by stepping and by basic blocks, reporting how many
+/// million instructions per second each managed
+fn bench(options: &ToolOptions) -> bool {
+    let cycles = match options.value("--cycles").map(str::parse) {
+        None => 100_000_000,
+        Some(Ok(cycles)) => cycles,
+        Some(Err(_)) => {
+            usage();
+            return false;
+        },
+    };
+    let platform = match load_platform(options.platform.as_ref()) {
+        Ok(platform) => platform,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        },
+    };
+    let words = match load_program(options.filepath(), &platform) {
+        Ok(words) => words,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        },
+    };
+
+    let report = |name: &str, e: &emulator::Emulator, started: Instant| {
+        let seconds = started.elapsed().as_secs_f64();
+        println!("{:<9} {} cycles in {:.3} s, {:.1} MIPS", name, e.cycles, seconds, e.cycles as f64 / seconds / 1e6);
+    };
+    let mut stepped = emulator::Emulator::with_platform(&words, &platform);
+    let started = Instant::now();
+    while stepped.cycles < cycles && !stepped.halted() {
+        stepped.step();
+    }
+    report("Stepping:", &stepped, started);
+
+    let mut blocks = emulator::Emulator::with_platform(&words, &platform);
+    let started = Instant::now();
+    blocks.run(cycles);
+    report("Blocks:", &blocks, started);
+
+    if (blocks.pc, blocks.a, blocks.d, blocks.cycles) != (stepped.pc, stepped.a, stepped.d, stepped.cycles) || blocks.ram != stepped.ram {
+        eprintln!("Running by blocks ended in a different state than stepping");
+        return false;
+    }
+    true
+}
+
+/// Translates a program to C, written next to it unless given an output file
+fn recompile(options: &ToolOptions) -> bool {
+    let filepath = options.filepath();
+    let output = options.value("--output").map(String::from).unwrap_or_else(|| {
+        let stem = filepath.rsplit_once('.').map(|(stem, _)| stem).unwrap_or(filepath);
+        format!("{}.c", stem)
+    });
+
+    let result = load_platform(options.platform.as_ref()).and_then(|platform| {
+        let (words, assembler) = load_assembly(filepath, &[], &[], false, &platform)?;
+        let labels = assembler.as_ref().map(|a| a.symbols().labels());
+        let c = recompiler::translate(&words, labels.as_deref(), &platform);
+        fs::write(&output, c).map_err(|e| format!("{}: {}", output, e))
+    });
+    match result {
+        Ok(()) => {
+            println!("Recompiled: {} -> {}", filepath, output);
+            true
+        },
+        Err(err) => {
+            eprintln!("{}", err);
+            false
+        },
+    }
+}
+
+/// Serves the GDB remote serial protocol for a program on a local TCP port
+fn gdb_server(options: &ToolOptions) -> bool {
+    let port = match options.value("--port").map(str::parse) {
+        None => 1234,
+        Some(Ok(port)) => port,
+        Some(Err(_)) => {
+            usage();
+            return false;
+        },
+    };
+    let filepath = options.filepath();
+
+    let result = load_platform(options.platform.as_ref())
+        .and_then(|platform| Ok((load_program(filepath, &platform)?, platform)))
+        .and_then(|(words, platform)| gdb::Stub::new(emulator::Emulator::with_platform(&words, &platform)).listen(port).map_err(|e| e.to_string()));
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        return false;
+    }
+    true
+}
+
+/// Runs test scripts, writing each output file and reporting every line that
+/// differs from the compare file. Returns false if any script failed.
+fn run_scripts(options: &ToolOptions) -> bool {
+    let platform = match load_platform(options.platform.as_ref()) {
+        Ok(platform) => platform,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        },
+    };
+
+    let mut ok = true;
+    let mut coverages: Vec<coverage::Coverage> = Vec::new();
+    for filepath in &options.files {
+        let report = match tst::Runner::run_file(filepath, &platform) {
+            Ok(report) => report,
+            Err(err) => {
+                eprintln!("{}: {}", filepath, err);
+                ok = false;
+                continue;
+            },
+        };
+        if let Some(ref path) = report.output_file {
+            let mut output = report.output.join("\n");
+            output.push('\n');
+            if let Err(err) = fs::write(path, output) {
+                eprintln!("{}: {}", path.display(), err);
+                ok = false;
+            }
+        }
+        if !report.compared {
+            println!("Ran: {}", filepath);
+        } else if report.passed() {
+            println!("Passed: {}", filepath);
+        } else {
+            println!("Failed: {}", filepath);
+            print!("{}", report);
+            ok = false;
+        }
+        if let Some(coverage) = report.coverage {
+            match coverages.iter_mut().find(|c| c.same_program(&coverage)) {
+                Some(c) => c.merge(&coverage),
+                None => coverages.push(coverage),
             }
         }
     }
+
+    if let Some(path) = options.value("--coverage") {
+        for c in &coverages {
+            print!("{}", c.summary());
+        }
+        if let Err(err) = fs::write(path, coverage::to_lcov(&coverages)) {
+            eprintln!("{}: {}", path, err);
+            ok = false;
+        }
+    }
+    ok
+}
+
+/// Runs the `.test` blocks of each file on a fresh emulator, reporting every
+/// failed expectation. Returns false if any test failed.
+fn run_tests(options: &ToolOptions) -> bool {
+    let platform = match load_platform(options.platform.as_ref()) {
+        Ok(platform) => platform,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        },
+    };
+
+    let (mut passed, mut failed) = (0, 0);
+    for filepath in &options.files {
+        let mut a = Assembler::with_platform(&platform);
+        let tests = fs::read_to_string(filepath).map_err(|e| e.to_string())
+            .and_then(|source| a.assemble(source.as_bytes()).map_err(|e| e.to_string()))
+            .and_then(|_| unit::parse(&a).map_err(|e| e.to_string()));
+        let tests = match tests {
+            Ok(tests) => tests,
+            Err(err) => {
+                eprintln!("{}: {}", filepath, err);
+                failed += 1;
+                continue;
+            },
+        };
+        for test in &tests {
+            let outcome = test.run(a.words(), &platform);
+            if outcome.passed() {
+                println!("Passed: {}: {} ({} cycles)", filepath, test.name, outcome.cycles);
+                passed += 1;
+            } else {
+                println!("Failed: {}: {} [Line {}]", filepath, test.name, test.line);
+                for failure in &outcome.failures {
+                    println!("  {}", failure);
+                }
+                failed += 1;
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    failed == 0
+}
+
+/// Runs a program until it halts or reaches the cycle count, tracing every
+/// cycle when asked, then saves or draws the screen
+fn run(options: &RunOptions) -> bool {
+    let platform = match load_platform(options.platform.as_ref()) {
+        Ok(platform) => platform,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        },
+    };
+    let devices = match options.devices.iter().map(|spec| device::parse(spec, &platform)).collect::<Result<Vec<_>, String>>() {
+        Ok(devices) => devices,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        },
+    };
+    // Each device's register is a predefined symbol
+    let mut define: Vec<(String, u16)> = devices.iter().map(|d| (d.name().to_string(), d.address())).collect();
+    define.extend(options.define.iter().cloned());
+
+    let imports = match load_symbols(&options.symbols) {
+        Ok(imports) => imports,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        },
+    };
+    let loaded = options.keys.as_ref().map(|path| load_keys(path)).transpose()
+        .and_then(|keys| Ok((load_assembly(&options.filepath, &define, &imports, options.strict, &platform)?, keys)));
+    let ((words, assembler), mut keys) = match loaded {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        },
+    };
+
+    let names = assembler.as_ref().map(trace::symbol_names).unwrap_or_default();
+    let tracers = options.trace.as_ref().map(|path| create(path).map(|f| trace::TextTrace::new(f, names))).transpose()
+        .and_then(|text| Ok((text, options.vcd.as_ref().map(|path| create(path).and_then(trace::VcdTrace::new)).transpose()?)));
+    let (mut text, mut vcd) = match tracers {
+        Ok(tracers) => tracers,
+        Err(err) => {
+            eprintln!("{}", err);
+            return false;
+        },
+    };
+
+    if assembler.is_none() && options.needs_source() {
+        eprintln!("Profiling and coverage need the .asm source");
+        return false;
+    }
+    let mut profile = assembler.as_ref().filter(|_| options.profiling()).map(profile::Profile::new);
+    let mut coverage = assembler.as_ref().filter(|_| options.coverage.is_some())
+        .map(|a| coverage::Coverage::new(&options.filepath, a));
+
+    let mut e = emulator::Emulator::with_platform(&words, &platform);
+    for d in devices {
+        if let Err(err) = e.attach(d) {
+            eprintln!("{}", err);
+            return false;
+        }
+    }
+    let mut checker = if options.checked { Some(checked::Checker::new(&e, words.len())) } else { None };
+    // A .hack file still has the predefined symbols
+    let mut predefined = symbol_table::SymbolTable::with_platform(&platform);
+    if let Err(err) = predefined.import(&imports) {
+        eprintln!("{}", err);
+        return false;
+    }
+    for (name, address) in &define {
+        predefined.define(name, *address);
+    }
+    let symbols = assembler.as_ref().map(|a| a.symbols()).unwrap_or(&predefined);
+    for (name, value) in &options.set {
+        let address = match name.parse::<u16>().ok().or_else(|| symbols.get(name)) {
+            Some(address) => address,
+            None => {
+                eprintln!("No symbol {}", name);
+                return false;
+            },
+        };
+        e.write(address, *value);
+        if let Some(ref mut checker) = checker {
+            checker.initialize(address);
+        }
+    }
+    // Without anything watching each cycle, run a block at a time
+    if keys.is_none() && text.is_none() && vcd.is_none() && checker.is_none() && profile.is_none() && coverage.is_none() {
+        e.run(options.cycles);
+    }
+    let mut trap = None;
+    let mut result = Ok(());
+    while result.is_ok() && trap.is_none() && e.cycles < options.cycles && !e.halted() {
+        if let Some(ref mut keys) = keys {
+            keys.apply(&mut e);
+        }
+        let mut cycle = trace::Cycle::start(&e);
+        cycle.write = match checker {
+            Some(ref mut checker) => checker.step(&mut e).unwrap_or_else(|t| {
+                trap = Some(t);
+                None
+            }),
+            None => e.step(),
+        };
+        if let Some(ref mut text) = text {
+            result = text.record(&cycle, &e);
+        }
+        if let Some(ref mut vcd) = vcd {
+            result = result.and_then(|_| vcd.record(&cycle));
+        }
+        if trap.is_none() {
+            if let Some(ref mut profile) = profile {
+                profile.record(cycle.pc, cycle.instruction, e.pc);
+            }
+            if let Some(ref mut coverage) = coverage {
+                coverage.record(cycle.pc, e.pc);
+            }
+        }
+    }
+    let result = result
+        .and_then(|_| text.as_mut().map_or(Ok(()), |t| t.finish()))
+        .and_then(|_| vcd.as_mut().map_or(Ok(()), |v| v.finish(e.cycles)));
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        return false;
+    }
+    if let Some(trap) = trap {
+        // Each line of a .hack file holds the instruction at its address
+        let lines: Vec<u32> = match assembler {
+            Some(ref a) => a.source_lines().to_vec(),
+            None => (1..=words.len() as u32).collect(),
+        };
+        eprintln!("{}", checked::RuntimeError::new(&trap, &lines));
+        return false;
+    }
+    if e.halted() {
+        println!("Halted after {} cycles", e.cycles);
+    } else {
+        println!("Stopped after {} cycles", e.cycles);
+    }
+
+    if let Some(ref path) = options.screen {
+        if let Err(err) = e.platform().screen.save(path, &e.ram) {
+            eprintln!("{}", err);
+            return false;
+        }
+    }
+    if options.ascii {
+        print!("{}", e.platform().screen.to_ascii(&e.ram));
+    }
+
+    if let (Some(mut coverage), Some(path)) = (coverage, options.coverage.as_ref()) {
+        coverage.finish(&e);
+        print!("{}", coverage.summary());
+        if let Err(err) = fs::write(path, coverage.to_lcov()) {
+            eprintln!("{}: {}", path, err);
+            return false;
+        }
+    }
+    if let (Some(profile), Some(assembler)) = (profile, assembler) {
+        if options.profile {
+            print!("{}", profile.report());
+        }
+        let result = options.listing.as_ref().map_or(Ok(()), |path| {
+            fs::read_to_string(&options.filepath)
+                .and_then(|source| fs::write(path, profile.listing(&source, assembler.source_lines())))
+                .map_err(|e| format!("{}: {}", path, e))
+        }).and_then(|_| options.flamegraph.as_ref().map_or(Ok(()), |path| {
+            fs::write(path, profile.collapsed_stacks()).map_err(|e| format!("{}: {}", path, e))
+        }));
+        if let Err(err) = result {
+            eprintln!("{}", err);
+            return false;
+        }
+    }
+    true
+}
+
+/// Creates a buffered output file
+fn create(path: &str) -> io::Result<io::BufWriter<fs::File>> {
+    fs::File::create(path)
+        .map(io::BufWriter::new)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))
+}
+
+/// Reads a keyboard script file
+fn load_keys(filepath: &str) -> Result<keys::Script, String> {
+    let text = fs::read_to_string(filepath).map_err(|e| format!("{}: {}", filepath, e))?;
+    keys::Script::parse(&text).map_err(|e| format!("{}: {}", filepath, e))
+}
+
+/// Plays a program in the terminal until Ctrl-C, then saves the keys pressed
+/// when recording
+fn play_program(options: PlayOptions) -> bool {
+    let PlayOptions { filepath, keys, record, platform, mut options } = options;
+    let result = keys.as_ref().map(|path| load_keys(path)).transpose()
+        .and_then(|replay| {
+            options.replay = replay;
+            let platform = load_platform(platform.as_ref())?;
+            Ok((load_program(&filepath, &platform)?, platform))
+        })
+        .and_then(|(words, platform)| play::play(&mut emulator::Emulator::with_platform(&words, &platform), options).map_err(|e| e.to_string()))
+        .and_then(|recording| match record {
+            Some(ref path) => fs::write(path, recording.to_string()).map_err(|e| format!("{}: {}", path, e)),
+            None => Ok(()),
+        });
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        return false;
+    }
+    true
 }
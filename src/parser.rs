@@ -13,7 +13,7 @@ pub struct Parser {
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Parser {
         Parser{
-            tokens: tokens,
+            tokens,
             cursor: 0,
         }
     }
@@ -75,7 +75,7 @@ impl Parser {
             if dest.is_empty() {
                 return Err(self.error("Missing destination l-value"));
             }
-        } else if dest.len() > 0 {
+        } else if !dest.is_empty() {
             for _ in 0..dest.len() {
                 self.pop();
             }
@@ -90,8 +90,8 @@ impl Parser {
             let operator = self.previous();
             let right = self.literal()?;
             return Ok(Expression::Unary{
-                operator: operator,
-                right: right,
+                operator,
+                right,
             })
         }
 
@@ -101,9 +101,9 @@ impl Parser {
             let operator = self.previous();
             let right = self.literal()?;
             return Ok(Expression::Binary{
-                left: left,
-                operator: operator,
-                right: right,
+                left,
+                operator,
+                right,
             });
         }
 
@@ -216,10 +216,14 @@ pub struct ParserError {
 impl ParserError {
     pub fn new(token: Token, description: &str) -> ParserError {
         ParserError{
-            token: token,
+            token,
             description: String::from(description),
         }
     }
+
+    pub fn line(&self) -> u32 {
+        self.token.line
+    }
 }
 
 impl std::fmt::Display for ParserError {
@@ -0,0 +1,263 @@
+use std::fmt;
+use std::fs;
+
+use device::{self, Device, Keyboard, Screen};
+use emulator::{KBD, MAX_DEVICES, RAM_SIZE, ROM_SIZE};
+use screen::{self, Layout};
+
+type Result<T> = std::result::Result<T, PlatformError>;
+
+/// The memory map of a Hack board: its predefined symbols, where variables
+/// go, how much ROM it has and where its devices are
+#[derive(Debug, Clone, PartialEq)]
+pub struct Platform {
+    /// Predefined RAM symbols, besides SCREEN, KBD and the devices' own
+    pub symbols: Vec<(String, u16)>,
+    /// The first and last addresses variables are allocated at
+    pub variables: (u16, u16),
+    pub rom_size: usize,
+    pub screen: Layout,
+    pub keyboard: u16,
+    /// Devices besides the screen and keyboard, by kind and address
+    pub devices: Vec<(String, u16)>,
+}
+
+impl Platform {
+    /// The standard Hack computer
+    pub fn hack() -> Platform {
+        let mut symbols: Vec<(String, u16)> = ["SP", "LCL", "ARG", "THIS", "THAT"].iter().enumerate()
+            .map(|(i, s)| (s.to_string(), i as u16))
+            .collect();
+        symbols.extend((0..16).map(|i| (format!("R{}", i), i)));
+        Platform {
+            symbols,
+            variables: (0x10, screen::HACK.address - 1),
+            rom_size: ROM_SIZE,
+            screen: screen::HACK,
+            keyboard: KBD,
+            devices: Vec::new(),
+        }
+    }
+
+    /// Reads a platform profile from a file
+    pub fn load(path: &str) -> std::result::Result<Platform, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Platform::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Parses a platform profile: `key = value` lines changing the standard
+    /// Hack computer, with `#` starting a comment
+    ///
+    /// ```text
+    /// rom_size = 16384
+    /// variables = 16-2047
+    /// screen = 2048
+    /// screen_width = 640
+    /// screen_height = 480
+    /// keyboard = 21248
+    /// symbol.LED = 21249
+    /// device.timer = 21250
+    /// ```
+    pub fn parse(text: &str) -> Result<Platform> {
+        let mut platform = Platform::hack();
+        let mut last = 0;
+        // The line setting the variable range, if one does
+        let mut variables_line = None;
+        for (i, line) in text.lines().enumerate() {
+            let line_number = i as u32 + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            last = line_number;
+            let error = |description: &str| PlatformError::new(line_number, description);
+            let (key, value) = line.split_once('=').map(|(k, v)| (k.trim(), v.trim()))
+                .ok_or_else(|| error("Expected key = value"))?;
+            let number = |value: &str| value.parse::<u32>().ok().filter(|&n| n < RAM_SIZE as u32).map(|n| n as u16)
+                .ok_or_else(|| error(&format!("Bad address {}", value)));
+
+            if let Some(name) = key.strip_prefix("symbol.") {
+                platform.symbols.retain(|(s, _)| s != name);
+                platform.symbols.push((name.to_string(), number(value)?));
+                continue;
+            }
+            if let Some(kind) = key.strip_prefix("device.") {
+                let address = number(value)?;
+                device::parse(&format!("{}@{}", kind, address), &platform).map_err(|e| error(&e))?;
+                // The screen and keyboard are attached too
+                if platform.devices.len() + 2 == MAX_DEVICES {
+                    return Err(error(&format!("A platform can't have more than {} devices", MAX_DEVICES)));
+                }
+                platform.devices.push((kind.to_string(), address));
+                continue;
+            }
+            match key {
+                "rom_size" => platform.rom_size = value.parse::<usize>().ok().filter(|&n| n > 0 && n <= ROM_SIZE)
+                    .ok_or_else(|| error(&format!("ROM size must be from 1 to {}", ROM_SIZE)))?,
+                "variables" => {
+                    let (first, last) = value.split_once('-').ok_or_else(|| error("Expected FIRST-LAST"))?;
+                    platform.variables = (number(first.trim())?, number(last.trim())?);
+                    if platform.variables.0 > platform.variables.1 {
+                        return Err(error("The first variable address is after the last"));
+                    }
+                    variables_line = Some(line_number);
+                },
+                "screen" => platform.screen.address = number(value)?,
+                "screen_width" => platform.screen.width = value.parse::<usize>().ok().filter(|&n| n > 0 && n.is_multiple_of(16))
+                    .ok_or_else(|| error("Screen width must be a positive multiple of 16"))?,
+                "screen_height" => platform.screen.height = value.parse::<usize>().ok().filter(|&n| n > 0)
+                    .ok_or_else(|| error("Screen height must be positive"))?,
+                "keyboard" => platform.keyboard = number(value)?,
+                _ => return Err(error(&format!("Unknown key {}", key))),
+            }
+        }
+
+        let screen_end = platform.screen.address as usize + platform.screen.words();
+        if screen_end > RAM_SIZE {
+            return Err(PlatformError::new(last, "The screen runs past the end of RAM"));
+        }
+        if (platform.screen.address as usize..screen_end).contains(&(platform.keyboard as usize)) {
+            return Err(PlatformError::new(last, "The keyboard is inside the screen"));
+        }
+
+        let mapped: Vec<(String, u16, u16)> = platform.devices().iter()
+            .map(|d| (d.name().to_string(), d.address(), d.address() + d.words() - 1))
+            .collect();
+        match variables_line {
+            Some(line) => {
+                let (first, last) = platform.variables;
+                if let Some((name, _, _)) = mapped.iter().find(|&&(_, f, l)| f <= last && first <= l) {
+                    return Err(PlatformError::new(line, &format!("The variables overlap {}", name)));
+                }
+            },
+            None => platform.variables = free_range(&mapped).ok_or_else(|| PlatformError::new(last, "No RAM is left for variables"))?,
+        }
+        Ok(platform)
+    }
+
+    /// Every predefined symbol: the registers, SCREEN, KBD and each device's
+    pub fn predefined(&self) -> Vec<(String, u16)> {
+        let mut symbols = self.symbols.clone();
+        symbols.push((String::from("SCREEN"), self.screen.address));
+        symbols.push((String::from("KBD"), self.keyboard));
+        for device in self.devices() {
+            if !symbols.iter().any(|(s, _)| s == device.name()) {
+                symbols.push((device.name().to_string(), device.address()));
+            }
+        }
+        symbols
+    }
+
+    /// New instances of its devices, the screen and keyboard first
+    pub fn devices(&self) -> Vec<Box<dyn Device>> {
+        let mut devices: Vec<Box<dyn Device>> = vec![
+            Box::new(Screen::new(self.screen.address, self.screen.words() as u16)),
+            Box::new(Keyboard::new(self.keyboard)),
+        ];
+        // Kinds were checked when parsing
+        devices.extend(self.devices.iter().filter_map(|(kind, address)| device::parse(&format!("{}@{}", kind, address), self).ok()));
+        devices
+    }
+}
+
+/// The default variable range: from R15 up to whatever is mapped next,
+/// starting past anything mapped over the first address
+fn free_range(mapped: &[(String, u16, u16)]) -> Option<(u16, u16)> {
+    let mut first: usize = 16;
+    while let Some(&(_, _, l)) = mapped.iter().find(|&&(_, f, l)| f as usize <= first && first <= l as usize) {
+        first = l as usize + 1;
+    }
+    let last = mapped.iter().map(|&(_, f, _)| f as usize).filter(|&f| f > first).min().unwrap_or(RAM_SIZE) - 1;
+    if first > last {
+        return None;
+    }
+    Some((first as u16, last as u16))
+}
+
+#[derive(Debug)]
+pub struct PlatformError {
+    description: String,
+    line: u32,
+}
+
+impl PlatformError {
+    fn new(line: u32, description: &str) -> PlatformError {
+        PlatformError {
+            description: description.to_string(),
+            line,
+        }
+    }
+}
+
+impl fmt::Display for PlatformError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Platform error: [Line {}] {} ", self.line, self.description)
+    }
+}
+
+impl std::error::Error for PlatformError {}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+    use emulator::Emulator;
+    use platform::Platform;
+
+    const PROFILE: &str = "\
+# A board with a 640x256 screen
+rom_size = 64
+variables = 100-101
+screen = 16384
+screen_width = 640
+keyboard = 26624  # just past the screen
+symbol.LED = 26625
+device.timer = 26626
+";
+
+    #[test]
+    fn profile() {
+        let platform = Platform::parse(PROFILE).unwrap();
+        assert_eq!((platform.screen.width, platform.screen.height, platform.screen.words()), (640, 256, 10240));
+        let predefined = platform.predefined();
+        for symbol in &[("R15", 15), ("KBD", 26624), ("LED", 26625), ("TIMER", 26626)] {
+            assert!(predefined.contains(&(symbol.0.to_string(), symbol.1)));
+        }
+
+        let mut a = Assembler::with_platform(&platform);
+        a.assemble("@x\nM=1\n@y\nM=1\n@KBD\nD=M\n@TIMER\nD=M\n".as_bytes()).unwrap();
+        assert_eq!(a.words()[..8], [100, 0xEFC8, 101, 0xEFC8, 26624, 0xFC10, 26626, 0xFC10]);
+        assert_eq!(Assembler::with_platform(&platform).assemble("@x\n@y\n@z\n".as_bytes()).unwrap_err().to_string(),
+            "Instruction error: [Line 3] No RAM left for variable z, which go from 100 to 101 ");
+        let long = "D=0\n".repeat(65);
+        assert_eq!(Assembler::with_platform(&platform).assemble(long.as_bytes()).unwrap_err().to_string(),
+            "Instruction error: [Line 65] The program is longer than the 64 words of ROM ");
+
+        // Without a range of their own, variables go around what's mapped
+        let moved = Platform::parse("screen = 16\nscreen_height = 16\n").unwrap();
+        assert_eq!(moved.variables, (528, 24575));
+        let low_keyboard = Platform::parse("keyboard = 20\n").unwrap();
+        assert_eq!(low_keyboard.variables, (16, 19));
+        let mut a = Assembler::with_platform(&Platform::parse("variables = 16-20\nsymbol.LED = 17\n").unwrap());
+        a.assemble("@x\n@y\n".as_bytes()).unwrap();
+        assert_eq!(a.words(), [16, 18]);
+
+        let e = Emulator::with_platform(&[], &platform);
+        assert_eq!(e.platform().keyboard, 26624);
+        // A jump past the board's ROM stops the program rather than running blank words
+        let mut far = Emulator::with_platform(&[100, 0xEA87], &platform);
+        far.run(u64::MAX);
+        assert_eq!((far.pc, far.cycles, far.halted()), (100, 2, true));
+        assert!(e.mapped(26000) && e.mapped(26626) && !e.mapped(26627));
+
+        for (text, message) in &[
+            ("screen_width = 500\n", "Platform error: [Line 1] Screen width must be a positive multiple of 16 "),
+            ("\nkeyboard = 16400\n", "Platform error: [Line 2] The keyboard is inside the screen "),
+            ("device.lamp = 30000\n", "Platform error: [Line 1] Unknown device lamp "),
+            ("screen = 30000\n", "Platform error: [Line 1] The screen runs past the end of RAM "),
+            ("variables = 16-30\nkeyboard = 20\n", "Platform error: [Line 1] The variables overlap KBD "),
+            ("variables = 100-200\ndevice.timer = 150\n", "Platform error: [Line 1] The variables overlap TIMER "),
+        ] {
+            assert_eq!(Platform::parse(text).unwrap_err().to_string(), *message);
+        }
+    }
+}
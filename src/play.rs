@@ -0,0 +1,223 @@
+use std::io;
+use std::io::prelude::*;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use emulator::Emulator;
+use keys::{self, Script, BACKSPACE, DELETE, DOWN, END, ESCAPE, F1, HOME, INSERT, LEFT, NEWLINE, PAGE_DOWN, PAGE_UP, RIGHT, UP};
+use screen::Layout;
+
+/// How often the terminal is redrawn
+const FRAME: Duration = Duration::from_millis(33);
+
+/// Ctrl-C, which ends the session since raw mode doesn't deliver signals
+const QUIT: u8 = 0x03;
+
+/// How the screen is drawn with Unicode characters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    /// 2x4 pixels per character
+    Braille,
+    /// 1x2 pixels per character
+    HalfBlock,
+}
+
+pub struct Options {
+    /// Instructions executed per second
+    pub rate: u64,
+    pub style: Style,
+    /// Pixels of the screen per pixel drawn, across and down
+    pub scale: usize,
+    /// How long a key stays down after the terminal last reported it, since
+    /// terminals only report presses
+    pub hold: Duration,
+    /// Keys to replay instead of reading the keyboard
+    pub replay: Option<keys::Script>,
+}
+
+/// Puts the terminal in raw mode for as long as it lives
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    fn enter() -> io::Result<RawMode> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+        Ok(RawMode { saved: saved.trim().to_string() })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty").args(args).stdin(Stdio::inherit()).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Runs a program in real time, drawing the screen in the terminal and
+/// feeding it the keyboard until Ctrl-C. Returns every change to KBD, which
+/// replays the session exactly.
+pub fn play(emulator: &mut Emulator, options: Options) -> io::Result<Script> {
+    let (keys, input) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut buffer = [0u8; 64];
+        while let Ok(n) = stdin.lock().read(&mut buffer) {
+            if n == 0 || keys.send(buffer[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let _raw = RawMode::enter()?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    // Switch to the alternate screen and hide the cursor
+    write!(out, "\x1b[?1049h\x1b[?25l\x1b[2J")?;
+
+    let result = run(emulator, options, &input, &mut out);
+
+    write!(out, "\x1b[?25h\x1b[?1049l")?;
+    out.flush()?;
+    result
+}
+
+fn run<W: Write>(emulator: &mut Emulator, mut options: Options, input: &mpsc::Receiver<Vec<u8>>, out: &mut W) -> io::Result<Script> {
+    let per_frame = (options.rate as f64 * FRAME.as_secs_f64()).max(1.0) as u64;
+    let mut drawn: Vec<String> = Vec::new();
+    let mut released = Instant::now();
+    let mut recording = Script::new();
+    let keyboard = emulator.platform().keyboard;
+
+    loop {
+        let start = Instant::now();
+        while let Ok(bytes) = input.try_recv() {
+            if bytes.contains(&QUIT) {
+                return Ok(recording);
+            }
+            if options.replay.is_some() {
+                continue;
+            }
+            if let Some(&code) = key_codes(&bytes).last() {
+                emulator.write(keyboard, code);
+                recording.record(emulator.cycles, code);
+                released = start + options.hold;
+            }
+        }
+        if options.replay.is_none() && start >= released {
+            emulator.write(keyboard, 0);
+            recording.record(emulator.cycles, 0);
+        }
+
+        for _ in 0..per_frame {
+            if let Some(ref mut replay) = options.replay {
+                replay.apply(emulator);
+            }
+            emulator.step();
+        }
+
+        let lines = render(&emulator.platform().screen, &emulator.ram, options.style, options.scale);
+        for (i, line) in lines.iter().enumerate() {
+            if drawn.get(i) != Some(line) {
+                write!(out, "\x1b[{};1H{}", i + 1, line)?;
+            }
+        }
+        out.flush()?;
+        drawn = lines;
+
+        if let Some(rest) = FRAME.checked_sub(start.elapsed()) {
+            thread::sleep(rest);
+        }
+    }
+}
+
+pub fn render(screen: &Layout, ram: &[u16], style: Style, scale: usize) -> Vec<String> {
+    match style {
+        Style::Braille => screen.to_braille(ram, scale),
+        Style::HalfBlock => screen.to_half_blocks(ram, scale),
+    }
+}
+
+/// Translates terminal input to Hack key codes
+pub fn key_codes(bytes: &[u8]) -> Vec<u16> {
+    let mut codes = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let (code, len) = match bytes[i] {
+            0x1B => escape_sequence(&bytes[i + 1..]),
+            b'\r' | b'\n' => (Some(NEWLINE), 1),
+            0x7F | 0x08 => (Some(BACKSPACE), 1),
+            b if (0x20..0x7F).contains(&b) => (Some(b as u16), 1),
+            _ => (None, 1),
+        };
+        codes.extend(code);
+        i += len;
+    }
+    codes
+}
+
+/// Decodes the bytes after an escape, returning the key and the length of
+/// the whole sequence
+fn escape_sequence(rest: &[u8]) -> (Option<u16>, usize) {
+    match rest.first() {
+        Some(b'[') | Some(b'O') => (),
+        _ => return (Some(ESCAPE), 1),
+    }
+    // Parameters run until the final byte of the sequence
+    let end = match rest[1..].iter().position(|b| (0x40..0x7F).contains(b)) {
+        Some(end) => end + 1,
+        None => return (Some(ESCAPE), 1),
+    };
+    let parameters = String::from_utf8_lossy(&rest[1..end]);
+    let code = match (rest[end], parameters.as_ref()) {
+        (b'A', _) => Some(UP),
+        (b'B', _) => Some(DOWN),
+        (b'C', _) => Some(RIGHT),
+        (b'D', _) => Some(LEFT),
+        (b'H', _) => Some(HOME),
+        (b'F', _) => Some(END),
+        (b'P', _) => Some(F1),
+        (b'Q', _) => Some(F1 + 1),
+        (b'R', _) => Some(F1 + 2),
+        (b'S', _) => Some(F1 + 3),
+        (b'~', "1") | (b'~', "7") => Some(HOME),
+        (b'~', "2") => Some(INSERT),
+        (b'~', "3") => Some(DELETE),
+        (b'~', "4") | (b'~', "8") => Some(END),
+        (b'~', "5") => Some(PAGE_UP),
+        (b'~', "6") => Some(PAGE_DOWN),
+        (b'~', "15") => Some(F1 + 4),
+        (b'~', "17") => Some(F1 + 5),
+        (b'~', "18") => Some(F1 + 6),
+        (b'~', "19") => Some(F1 + 7),
+        (b'~', "20") => Some(F1 + 8),
+        (b'~', "21") => Some(F1 + 9),
+        (b'~', "23") => Some(F1 + 10),
+        (b'~', "24") => Some(F1 + 11),
+        _ => None,
+    };
+    (code, end + 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use play::key_codes;
+
+    #[test]
+    fn keys() {
+        assert_eq!(key_codes(b"a \r\x7f"), vec![97, 32, 128, 129]);
+        assert_eq!(key_codes(b"\x1b[D\x1b[A\x1b[C\x1b[B"), vec![130, 131, 132, 133]);
+        assert_eq!(key_codes(b"\x1b[5~\x1b[3~\x1bOP\x1b[24~"), vec![136, 139, 141, 152]);
+        assert_eq!(key_codes(b"\x1b"), vec![140]);
+    }
+}
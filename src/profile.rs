@@ -0,0 +1,202 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use assembler::Assembler;
+use cfg::{self, Graph, Region};
+
+/// A routine entered by a call, in the tree of every call stack seen
+#[derive(Clone, Copy)]
+struct Frame {
+    parent: usize,
+    /// The ROM address the call jumped to
+    address: u16,
+    /// Where the routine returns to
+    returns: u16,
+}
+
+/// Counts how many times each ROM address runs, and under which calls
+pub struct Profile {
+    words: Vec<u16>,
+    counts: Vec<u64>,
+    regions: Vec<Region>,
+    return_addresses: Vec<u16>,
+    /// Frame 0 is the program's entry, which never returns
+    frames: Vec<Frame>,
+    children: HashMap<(usize, u16, u16), usize>,
+    frame_counts: Vec<u64>,
+    current: usize,
+    /// The current frame was just called and hasn't jumped yet
+    fresh: bool,
+}
+
+impl Profile {
+    pub fn new(assembler: &Assembler) -> Profile {
+        let graph = Graph::build(assembler.instructions(), assembler.symbols());
+        Profile {
+            words: assembler.words().to_vec(),
+            counts: vec![0; assembler.words().len()],
+            regions: graph.regions,
+            return_addresses: cfg::return_addresses(assembler.words(), assembler.symbols()),
+            frames: vec![Frame { parent: 0, address: 0, returns: 0 }],
+            children: HashMap::new(),
+            frame_counts: vec![0],
+            current: 0,
+            fresh: false,
+        }
+    }
+
+    /// Counts an executed instruction, given the PC it ran at and the PC after
+    /// it. A jump followed by a return label is a call, and arriving at the
+    /// return label of a frame on the stack returns from it. Calls through a
+    /// shared routine, like those of the VM translator, are named after where
+    /// its first computed jump lands instead.
+    pub fn record(&mut self, pc: u16, instruction: u16, next: u16) {
+        if let Some(c) = self.counts.get_mut(pc as usize) {
+            *c += 1;
+        }
+        self.frame_counts[self.current] += 1;
+
+        if instruction & 0x8007 <= 0x8000 || next == pc.wrapping_add(1) {
+            return;
+        }
+        let returns = pc.wrapping_add(1);
+        if self.return_addresses.binary_search(&returns).is_ok() {
+            self.current = self.enter(self.current, next, returns);
+            self.fresh = true;
+            return;
+        }
+        let mut frame = self.current;
+        while frame != 0 {
+            if self.frames[frame].returns == next {
+                self.current = self.frames[frame].parent;
+                self.fresh = false;
+                return;
+            }
+            frame = self.frames[frame].parent;
+        }
+
+        // A jump through an address computed rather than loaded by the
+        // instruction before
+        let computed = pc > 0 && self.words.get(pc as usize - 1).is_some_and(|&w| w & 0x8000 != 0);
+        if self.fresh && computed && self.current != 0 {
+            let Frame { parent, returns, .. } = self.frames[self.current];
+            self.current = self.enter(parent, next, returns);
+        }
+        self.fresh = false;
+    }
+
+    /// The frame for a call from a frame, added the first time it's made
+    fn enter(&mut self, parent: usize, address: u16, returns: u16) -> usize {
+        let key = (parent, address, returns);
+        if let Some(&frame) = self.children.get(&key) {
+            return frame;
+        }
+        self.frames.push(Frame { parent, address, returns });
+        self.frame_counts.push(0);
+        self.children.insert(key, self.frames.len() - 1);
+        self.frames.len() - 1
+    }
+
+    pub fn total(&self) -> u64 {
+        self.frame_counts.iter().sum()
+    }
+
+    fn region_name(&self, address: u16) -> &str {
+        self.regions.iter().find(|r| r.contains(address)).map(|r| r.name.as_ref()).unwrap_or("(outside)")
+    }
+
+    /// Instructions executed under each label, most first
+    pub fn hot_spots(&self) -> Vec<(&str, u64)> {
+        let mut spots: Vec<(&str, u64)> = self.regions.iter()
+            .map(|r| (r.name.as_ref(), (r.start..r.end).map(|a| self.counts[a as usize]).sum()))
+            .filter(|&(_, n)| n > 0)
+            .collect();
+        spots.sort_by_key(|&(_, n)| Reverse(n));
+        spots
+    }
+
+    /// The hot spots with their share of every instruction executed
+    pub fn report(&self) -> String {
+        let total = self.total();
+        let mut out = format!("Instructions: {}\n", total);
+        for (name, n) in self.hot_spots() {
+            let _ = writeln!(out, "  {:>12} {:>6.2}%  {}", n, n as f64 * 100.0 / total as f64, name);
+        }
+        out
+    }
+
+    /// The source with the number of times each line's instructions ran in
+    /// the margin, blank for lines without instructions
+    pub fn listing(&self, source: &str, source_lines: &[u32]) -> String {
+        let mut line_counts: Vec<Option<u64>> = vec![None; source.lines().count() + 1];
+        for (&line, &n) in source_lines.iter().zip(&self.counts) {
+            if let Some(c) = line_counts.get_mut(line as usize) {
+                *c = Some(c.unwrap_or(0) + n);
+            }
+        }
+        let mut out = String::new();
+        for (i, text) in source.lines().enumerate() {
+            let _ = match line_counts[i + 1] {
+                Some(n) => writeln!(out, "{:>12} | {}", n, text),
+                None => writeln!(out, "{:>12} | {}", "", text),
+            };
+        }
+        out
+    }
+
+    /// Every call stack that ran, one per line as the frames' labels joined
+    /// by `;` and the instructions executed in it, for flamegraph tools
+    pub fn collapsed_stacks(&self) -> String {
+        // Calls from different places to the same routine share a stack
+        let mut stacks: BTreeMap<String, u64> = BTreeMap::new();
+        for (f, &n) in self.frame_counts.iter().enumerate().filter(|&(_, &n)| n > 0) {
+            let mut names = Vec::new();
+            let mut frame = f;
+            loop {
+                names.push(self.region_name(self.frames[frame].address));
+                if frame == 0 {
+                    break;
+                }
+                frame = self.frames[frame].parent;
+            }
+            names.reverse();
+            *stacks.entry(names.join(";")).or_insert(0) += n;
+        }
+        stacks.iter().map(|(stack, n)| format!("{} {}\n", stack, n)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+    use emulator::Emulator;
+    use profile::Profile;
+
+    #[test]
+    fn calls() {
+        // Calls DOUBLE twice, then halts
+        let source = "@RET1\nD=A\n@R15\nM=D\n@DOUBLE\n0;JMP\n(RET1)\n\
+                      @RET2\nD=A\n@R15\nM=D\n@DOUBLE\n0;JMP\n(RET2)\n\
+                      (END)\n@END\n0;JMP\n\
+                      (DOUBLE)\nD=D+A\n@R15\nA=M\n0;JMP\n";
+        let mut a = Assembler::new();
+        a.assemble(source.as_bytes()).unwrap();
+        let mut e = Emulator::new(a.words());
+        let mut profile = Profile::new(&a);
+        while !e.halted() {
+            let (pc, instruction) = (e.pc, e.instruction(e.pc));
+            e.step();
+            profile.record(pc, instruction, e.pc);
+        }
+
+        assert_eq!(profile.total(), 21);
+        assert_eq!(profile.hot_spots(), vec![("DOUBLE", 8), ("(start)", 6), ("RET1", 6), ("END", 1)]);
+        assert_eq!(profile.collapsed_stacks(), "(start) 13\n(start);DOUBLE 8\n");
+        let listing = profile.listing(source, a.source_lines());
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], "           1 | @RET1");
+        assert_eq!(lines[6], "             | (RET1)");
+        assert_eq!(lines[18], "           2 | D=D+A");
+    }
+}
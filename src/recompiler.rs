@@ -0,0 +1,236 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use instruction;
+use platform::Platform;
+
+const JUMP_CONDITIONS: [&str; 6] = ["> 0", "== 0", ">= 0", "< 0", "!= 0", "<= 0"];
+
+const PRELUDE: &str = r#"/*
+ * Usage: PROGRAM [MAX_CYCLES] [ADDRESS=VALUE...]
+ *
+ * Define HACK_HOOKS and link hack_screen() and hack_keyboard() to draw the
+ * screen and read the keyboard. Without them, screen writes only land in
+ * ram[] and the keyboard reads as whatever was stored in it.
+ */
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <time.h>
+
+uint16_t ram[0x8000];
+uint64_t cycles;
+
+#ifdef HACK_HOOKS
+void hack_screen(uint16_t address, uint16_t value);
+uint16_t hack_keyboard(void);
+#else
+static void hack_screen(uint16_t address, uint16_t value) { (void)address; (void)value; }
+static uint16_t hack_keyboard(void) { return ram[KBD]; }
+#endif
+
+static inline uint16_t rd(uint16_t address) {
+    address &= 0x7FFF;
+    return address == KBD ? hack_keyboard() : ram[address];
+}
+
+static inline void wr(uint16_t address, uint16_t value) {
+    address &= 0x7FFF;
+    ram[address] = value;
+    if (address >= SCREEN && address < SCREEN_END) hack_screen(address, value);
+}
+
+/* The comp bits the assembler has no mnemonic for */
+static inline uint16_t alu(uint16_t x, uint16_t y, uint16_t instruction) {
+    uint16_t out;
+    if (instruction & 0x800) x = 0;
+    if (instruction & 0x400) x = (uint16_t)~x;
+    if (instruction & 0x200) y = 0;
+    if (instruction & 0x100) y = (uint16_t)~y;
+    out = (instruction & 0x80) ? (uint16_t)(x + y) : (uint16_t)(x & y);
+    return (instruction & 0x40) ? (uint16_t)~out : out;
+}
+
+static void finish(const char *how, clock_t start) {
+    double seconds = (double)(clock() - start) / CLOCKS_PER_SEC;
+    printf("%s after %llu cycles\n", how, (unsigned long long)cycles);
+    if (seconds > 0) fprintf(stderr, "%.3f s, %.1f MIPS\n", seconds, cycles / seconds / 1e6);
+}
+
+int main(int argc, char **argv) {
+    uint64_t limit = UINT64_MAX;
+    uint16_t A = 0, D = 0, pc = 0, v = 0, t = 0;
+    clock_t start;
+    int i;
+    for (i = 1; i < argc; i++) {
+        unsigned address;
+        int value;
+        if (sscanf(argv[i], "%u=%d", &address, &value) == 2) ram[address & 0x7FFF] = (uint16_t)value;
+        else limit = strtoull(argv[i], NULL, 10);
+    }
+    (void)v;
+    (void)t;
+    start = clock();
+    for (;;) {
+        if (cycles >= limit) {
+            finish("Stopped", start);
+            return 0;
+        }
+        switch (pc) {
+"#;
+
+const EPILOGUE: &str = r#"            fprintf(stderr, "Ran past the end of the program\n");
+            return 1;
+        default:
+            fprintf(stderr, "Jump to ROM[%u], which starts no known block\n", pc);
+            return 1;
+        }
+    }
+"#;
+
+const HALT: &str = r#"halt:
+    finish("Halted", start);
+    return 0;
+"#;
+
+/// Determines if the instruction at an address is the jump of the
+/// `(END) @END 0;JMP` idiom, which the emulator halts on
+fn halts(words: &[u16], address: usize) -> bool {
+    address > 0 && words[address] & 0xE007 == 0xE007 && words[address - 1] as usize == address - 1
+}
+
+fn unconditional(word: u16) -> bool {
+    word & 0x8007 == 0x8007
+}
+
+/// The addresses control can arrive at other than by falling through: the
+/// start, everything after a jump, and the labels. Without labels, as for a
+/// `.hack` file, any address an A-instruction loads could be a jump target.
+fn block_starts(words: &[u16], labels: Option<&[(&str, u16)]>) -> BTreeSet<usize> {
+    let mut starts = BTreeSet::new();
+    starts.insert(0);
+    match labels {
+        Some(labels) => starts.extend(labels.iter().map(|&(_, a)| a as usize)),
+        None => starts.extend(words.iter().filter(|&&w| w & 0x8000 == 0).map(|&w| w as usize)),
+    }
+    for (address, &w) in words.iter().enumerate() {
+        if w & 0x8000 != 0 && w & 0x7 != 0 {
+            starts.insert(address + 1);
+        }
+    }
+    starts.retain(|&a| a < words.len());
+    starts
+}
+
+/// The C statements for an instruction
+fn translate_instruction(word: u16) -> String {
+    if word & 0x8000 == 0 {
+        return format!("A = {};", word);
+    }
+    let y = if word & 0x1000 != 0 { "rd(A)" } else { "A" };
+    let comp = match instruction::comp_mnemonic(word) {
+        Some(mnemonic) => format!("(uint16_t)({})", mnemonic.replace('!', "~").replace('A', y)),
+        None => format!("alu(D, {}, 0x{:04X})", y, word),
+    };
+
+    let jump = word & 0x7;
+    let mut statements = vec![format!("v = {};", comp)];
+    if word & 0x8 != 0 {
+        statements.push(String::from("wr(A, v);"));
+    }
+    // The jump target is A from before the instruction
+    let target = if jump != 0 && word & 0x20 != 0 {
+        statements.push(String::from("t = A;"));
+        "t"
+    } else {
+        "A"
+    };
+    if word & 0x10 != 0 {
+        statements.push(String::from("D = v;"));
+    }
+    if word & 0x20 != 0 {
+        statements.push(String::from("A = v;"));
+    }
+    match jump {
+        0 => (),
+        7 => statements.push(format!("pc = {} & 0x7FFF; continue;", target)),
+        _ => statements.push(format!("if ((int16_t)v {}) {{ pc = {} & 0x7FFF; continue; }}",
+            JUMP_CONDITIONS[jump as usize - 1], target)),
+    }
+    statements.join(" ")
+}
+
+/// Translates a program into a C program that runs it natively. Each basic
+/// block is a case of a switch on PC, which jumps dispatch through and the
+/// blocks after them fall into. It counts cycles like the emulator and stops
+/// at the same `(END) @END 0;JMP`, though it only checks the cycle limit
+/// between blocks. The screen and keyboard are where the platform has them.
+pub fn translate(words: &[u16], labels: Option<&[(&str, u16)]>, platform: &Platform) -> String {
+    let starts = block_starts(words, labels);
+    let screen = &platform.screen;
+    let mut out = format!("/* Recompiled from {} Hack instructions */\n#define SCREEN 0x{:04X}\n#define SCREEN_END 0x{:04X}\n#define KBD 0x{:04X}\n",
+        words.len(), screen.address, screen.address as usize + screen.words(), platform.keyboard);
+    out.push_str(PRELUDE);
+
+    for (address, &word) in words.iter().enumerate() {
+        if starts.contains(&address) {
+            if address > 0 && !unconditional(words[address - 1]) {
+                let _ = writeln!(out, "            /* fall through */");
+            }
+            let names: Vec<&str> = labels.unwrap_or(&[]).iter().filter(|&&(_, a)| a as usize == address).map(|&(n, _)| n).collect();
+            if names.is_empty() {
+                let _ = writeln!(out, "        case {}:", address);
+            } else {
+                let _ = writeln!(out, "        case {}: /* {} */", address, names.join(", "));
+            }
+            let end = starts.range(address + 1..).next().cloned().unwrap_or(words.len());
+            let len = (address..end).filter(|&a| !halts(words, a)).count();
+            let _ = writeln!(out, "            cycles += {};", len);
+        }
+        let text = instruction::disassemble(word, None);
+        if halts(words, address) {
+            let _ = writeln!(out, "            if (A == {}) goto halt;", address - 1);
+            let _ = writeln!(out, "            cycles++;");
+        }
+        let _ = writeln!(out, "            {} /* {}: {} */", translate_instruction(word), address, text);
+    }
+    if !unconditional(words.last().cloned().unwrap_or(0)) {
+        let _ = writeln!(out, "            /* fall through */");
+    }
+    out.push_str(EPILOGUE);
+    if (0..words.len()).any(|a| halts(words, a)) {
+        out.push_str(HALT);
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use assembler::Assembler;
+    use platform::Platform;
+    use recompiler::{translate, translate_instruction};
+
+    #[test]
+    fn instructions() {
+        assert_eq!(translate_instruction(0x0011), "A = 17;");
+        assert_eq!(translate_instruction(0xFC10), "v = (uint16_t)(rd(A)); D = v;");
+        assert_eq!(translate_instruction(0xE301), "v = (uint16_t)(D); if ((int16_t)v > 0) { pc = A & 0x7FFF; continue; }");
+        assert_eq!(translate_instruction(0xFDFF), "v = (uint16_t)(rd(A)+1); wr(A, v); t = A; D = v; A = v; pc = t & 0x7FFF; continue;");
+        assert_eq!(translate_instruction(0xEC50), "v = (uint16_t)(~A); D = v;");
+        assert_eq!(translate_instruction(0xE050), "v = alu(D, A, 0xE050); D = v;");
+    }
+
+    #[test]
+    fn max() {
+        let mut a = Assembler::new();
+        a.assemble(File::open("test/max/Max.asm").unwrap()).unwrap();
+        let c = translate(a.words(), Some(&a.symbols().labels()), &Platform::hack());
+        assert!(c.contains("        case 0:\n            cycles += 6;\n            A = 0; /* 0: @0 */\n"));
+        assert!(c.contains("        case 10: /* OUTPUT_FIRST */\n"));
+        assert!(c.contains("        case 14: /* INFINITE_LOOP */\n            cycles += 1;\n            A = 14; /* 14: @14 */\n\
+                            \x20           if (A == 14) goto halt;\n            cycles++;\n"));
+        assert!(c.ends_with("halt:\n    finish(\"Halted\", start);\n    return 0;\n}\n"));
+    }
+}
@@ -1,6 +1,9 @@
 use token::Token;
 use token::TokenKind;
 
+/// Names of the directives, which carry what isn't an instruction, like test blocks
+pub const DIRECTIVES: [&str; 5] = ["test", "set", "run", "expect", "var"];
+
 pub struct Scanner<'a> {
     iter: std::str::Chars<'a>,
     cursor: char,
@@ -32,15 +35,15 @@ impl<'a> Iterator for Scanner<'a> {
 }
 
 impl<'a> Scanner<'a> {
-    pub fn new(line: &str, line_num: u32) -> Scanner {
+    pub fn new(line: &str, line_num: u32) -> Scanner<'_> {
         let mut iter = line.chars();
         let peek = iter.next();
         Scanner {
-            iter: iter,
+            iter,
             cursor: ' ',
             did_error: false,
-            peek: match peek { Some(c) => c, None => '\0' },
-            line_num: line_num,
+            peek: peek.unwrap_or('\0'),
+            line_num,
         }
     }
 
@@ -61,7 +64,7 @@ impl<'a> Scanner<'a> {
                 }))
             },
             '(' => {
-                if self.peek.is_digit(10) {
+                if self.peek.is_ascii_digit() {
                     return Err(self.scanner_error("Symbol cannot start with a digit"));
                 }
                 let s = self.grab_while(|c| c != ')' && !c.is_whitespace());
@@ -71,6 +74,22 @@ impl<'a> Scanner<'a> {
                 let _ = self.push();
                 Ok(self.token(TokenKind::Label(s)))
             },
+            '.' => {
+                // Runs to the end of the line or a comment, though not one in quotes
+                let mut text = String::new();
+                let mut quoted = false;
+                while self.peek != '\0' && self.peek != '\n' && self.peek != '\r' && (quoted || self.peek != '/') {
+                    if self.peek == '"' {
+                        quoted = !quoted;
+                    }
+                    text.push(self.push());
+                }
+                let name = text.split_whitespace().next().unwrap_or("");
+                if !DIRECTIVES.contains(&name) {
+                    return Err(self.scanner_error(&format!("Unknown directive .{}", name)));
+                }
+                Ok(self.token(TokenKind::Directive(text.trim_end().to_string())))
+            },
             'A' => Ok(self.token(TokenKind::ARegister)),
             'D' => Ok(self.token(TokenKind::DRegister)),
             'M' => Ok(self.token(TokenKind::Memory)),
@@ -83,9 +102,9 @@ impl<'a> Scanner<'a> {
             ';' => Ok(self.token(TokenKind::Semicolon)),
             '/' => {
                 if self.peek == '/' {
-                    self.cursor = '\0';
-                    self.peek = '\0';
-                    Ok(self.token(TokenKind::NewLine))
+                    let _ = self.push();
+                    let comment = self.grab_while(|c| c != '\n' && c != '\r');
+                    Ok(self.token(TokenKind::Comment(comment.trim_end().to_string())))
                 } else {
                     Err(self.scanner_error("Unexpected slash character"))
                 }
@@ -106,8 +125,8 @@ impl<'a> Scanner<'a> {
                 }
             },
             _ => {
-                if cursor.is_digit(10) {
-                    let buf = self.grab_cursor_while(|c| c.is_digit(10));
+                if cursor.is_ascii_digit() {
+                    let buf = self.grab_cursor_while(|c| c.is_ascii_digit());
                     let num = buf.parse::<u32>().unwrap();
                     return Ok(self.token(TokenKind::Number(num)));
                 }
@@ -143,7 +162,7 @@ impl<'a> Scanner<'a> {
     }
 
     fn take_while<F>(&mut self, s: &mut String, predicate: F) where F: Fn(char) -> bool {
-        while predicate(self.peek) {
+        while self.peek != '\0' && predicate(self.peek) {
             s.push(self.push());
         }
     }
@@ -151,10 +170,7 @@ impl<'a> Scanner<'a> {
     /// Advance the cursor, returning the new cursor result
     fn push(&mut self) -> char {
         self.cursor = self.peek;
-        self.peek = match self.iter.next() {
-            Some(c) => c,
-            None => '\0',
-        };
+        self.peek = self.iter.next().unwrap_or('\0');
         self.cursor
     }
 
@@ -174,9 +190,13 @@ impl ScannerError {
     fn new(description: &str, line: u32) -> ScannerError {
         ScannerError{
             description: String::from(description),
-            line: line,
+            line,
         }
     }
+
+    pub fn line(&self) -> u32 {
+        self.line
+    }
 }
 
 impl std::fmt::Display for ScannerError {
@@ -205,4 +225,32 @@ mod tests {
             Token::new(TokenKind::NewLine, 0)
         ])
     }
+
+    #[test]
+    fn directive() {
+        use token::{Token, TokenKind};
+        use scanner::Scanner;
+        let tokens: Vec<Token> = Scanner::new(".test \"a // b\"  // max\n", 2).map(|t| t.unwrap()).collect();
+        assert_eq!(tokens, vec![
+            Token::new(TokenKind::Directive(String::from("test \"a // b\"")), 2),
+            Token::new(TokenKind::Comment(String::from(" max")), 2),
+            Token::new(TokenKind::NewLine, 2)
+        ]);
+        assert!(Scanner::new(".tset \"a\"\n", 2).any(|t| t.is_err()));
+    }
+
+    #[test]
+    fn comment() {
+        use token::{Token, TokenKind};
+        use scanner::Scanner;
+        let s = Scanner::new("M=D  // store the result \n", 3);
+        let tokens: Vec<Token> = s.map(|t| t.unwrap()).collect();
+        assert_eq!(tokens, vec![
+            Token::new(TokenKind::Memory, 3),
+            Token::new(TokenKind::Equal, 3),
+            Token::new(TokenKind::DRegister, 3),
+            Token::new(TokenKind::Comment(String::from(" store the result")), 3),
+            Token::new(TokenKind::NewLine, 3)
+        ])
+    }
 }
\ No newline at end of file
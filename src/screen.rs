@@ -0,0 +1,237 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use emulator::SCREEN;
+
+/// Where the screen is mapped and its size in pixels. Each row is a whole
+/// number of words.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub address: u16,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// The standard Hack screen, 512x256 pixels
+pub const HACK: Layout = Layout { address: SCREEN, width: 512, height: 256 };
+
+/// Pixels per ASCII character, across and down
+const ASCII_CELL: (usize, usize) = (4, 4);
+
+impl Layout {
+    /// Words of RAM the screen is mapped over
+    pub fn words(&self) -> usize {
+        self.width / 16 * self.height
+    }
+
+    /// Determines if a pixel is black. Bit 0 of each word is its leftmost pixel.
+    pub fn pixel(&self, ram: &[u16], x: usize, y: usize) -> bool {
+        let word = ram[self.address as usize + y * (self.width / 16) + x / 16];
+        word >> (x % 16) & 1 == 1
+    }
+
+    /// Writes the screen to a `.png` or `.pbm` file, chosen by its extension
+    pub fn save(&self, path: &str, ram: &[u16]) -> io::Result<()> {
+        let data = match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("png") => self.to_png(ram),
+            Some("pbm") => self.to_pbm(ram),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}: expected a .png or .pbm file", path))),
+        };
+        fs::write(path, data)
+    }
+
+    /// Packs rows of pixels into bytes, most significant bit first, with 1 for black
+    fn packed_rows(&self, ram: &[u16]) -> Vec<Vec<u8>> {
+        (0..self.height).map(|y| {
+            (0..self.width / 8).map(|b| {
+                (0..8).fold(0u8, |byte, i| byte << 1 | self.pixel(ram, b * 8 + i, y) as u8)
+            }).collect()
+        }).collect()
+    }
+
+    /// A binary portable bitmap
+    pub fn to_pbm(&self, ram: &[u16]) -> Vec<u8> {
+        let mut out = format!("P4\n{} {}\n", self.width, self.height).into_bytes();
+        for row in self.packed_rows(ram) {
+            out.extend(row);
+        }
+        out
+    }
+
+    /// A 1 bit grayscale PNG
+    pub fn to_png(&self, ram: &[u16]) -> Vec<u8> {
+        let mut raw = Vec::with_capacity(self.height * (1 + self.width / 8));
+        for row in self.packed_rows(ram) {
+            // Filter type none, then the row with 0 for black
+            raw.push(0);
+            raw.extend(row.iter().map(|b| !b));
+        }
+
+        let mut header = Vec::new();
+        header.extend(&(self.width as u32).to_be_bytes());
+        header.extend(&(self.height as u32).to_be_bytes());
+        header.extend(&[1, 0, 0, 0, 0]);
+
+        let mut out = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+        write_chunk(&mut out, b"IHDR", &header);
+        write_chunk(&mut out, b"IDAT", &zlib_stored(&raw));
+        write_chunk(&mut out, b"IEND", &[]);
+        out
+    }
+
+    /// Determines if any pixel of a square block is black, for drawing the
+    /// screen smaller
+    fn block(&self, ram: &[u16], x: usize, y: usize, size: usize) -> bool {
+        let (w, h) = (self.width, self.height);
+        x < w && y < h && (y..(y + size).min(h)).any(|y| (x..(x + size).min(w)).any(|x| self.pixel(ram, x, y)))
+    }
+
+    /// Draws the screen with a character per 4x4 block of pixels, `#` when any
+    /// of them is black
+    pub fn to_ascii(&self, ram: &[u16]) -> String {
+        let (w, h) = ASCII_CELL;
+        let mut out = String::with_capacity((self.width / w + 1) * self.height / h);
+        for cy in 0..self.height / h {
+            for cx in 0..self.width / w {
+                let black = (0..h).any(|y| (0..w).any(|x| self.pixel(ram, cx * w + x, cy * h + y)));
+                out.push(if black { '#' } else { '.' });
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Draws the screen with a braille character per 2x4 block of scaled pixels
+    pub fn to_braille(&self, ram: &[u16], scale: usize) -> Vec<String> {
+        // Bits of the braille dots, by column then row
+        const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+        let scale = scale.max(1);
+        (0..self.height.div_ceil(4 * scale)).map(|cy| {
+            (0..self.width.div_ceil(2 * scale)).map(|cx| {
+                let mut bits = 0;
+                for (dx, column) in DOTS.iter().enumerate() {
+                    for (dy, dot) in column.iter().enumerate() {
+                        if self.block(ram, (cx * 2 + dx) * scale, (cy * 4 + dy) * scale, scale) {
+                            bits |= dot;
+                        }
+                    }
+                }
+                std::char::from_u32(0x2800 + bits).unwrap_or(' ')
+            }).collect()
+        }).collect()
+    }
+
+    /// Draws the screen with a half-block character per 1x2 block of scaled pixels
+    pub fn to_half_blocks(&self, ram: &[u16], scale: usize) -> Vec<String> {
+        let scale = scale.max(1);
+        (0..self.height.div_ceil(2 * scale)).map(|cy| {
+            (0..self.width.div_ceil(scale)).map(|x| {
+                let top = self.block(ram, x * scale, cy * 2 * scale, scale);
+                let bottom = self.block(ram, x * scale, (cy * 2 + 1) * scale, scale);
+                match (top, bottom) {
+                    (true, true) => '\u{2588}',
+                    (true, false) => '\u{2580}',
+                    (false, true) => '\u{2584}',
+                    (false, false) => ' ',
+                }
+            }).collect()
+        }).collect()
+    }
+}
+
+fn write_chunk(out: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    out.extend(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend(kind);
+    out.extend(data);
+    let crc = crc32(&out[start..]);
+    out.extend(&crc.to_be_bytes());
+}
+
+/// Wraps data in a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xFFFF).peekable();
+    if blocks.peek().is_none() {
+        out.extend(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend(&len.to_le_bytes());
+        out.extend(&(!len).to_le_bytes());
+        out.extend(block);
+    }
+    out.extend(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { crc >> 1 ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use assembler::Assembler;
+    use emulator::Emulator;
+    use screen::{adler32, crc32, HACK};
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn rect() {
+        let mut a = Assembler::new();
+        a.assemble(File::open("test/rect/Rect.asm").unwrap()).unwrap();
+        let mut e = Emulator::new(a.words());
+        e.ram[0] = 8;
+        while !e.halted() {
+            e.step();
+        }
+
+        let ascii = HACK.to_ascii(&e.ram);
+        let lines: Vec<&str> = ascii.lines().collect();
+        assert_eq!(lines.len(), 64);
+        assert_eq!(&lines[0][..6], "####..");
+        assert_eq!(&lines[1][..6], "####..");
+        assert_eq!(&lines[2][..6], "......");
+
+        let pbm = HACK.to_pbm(&e.ram);
+        assert!(pbm.starts_with(b"P4\n512 256\n"));
+        assert_eq!(&pbm[11..14], &[0xFF, 0xFF, 0]);
+
+        let braille = HACK.to_braille(&e.ram, 1);
+        assert_eq!((braille.len(), braille[0].chars().count()), (64, 256));
+        assert_eq!(braille[1].chars().take(9).collect::<String>(), "\u{28FF}".repeat(8) + "\u{2800}");
+        assert_eq!(braille[2].chars().next(), Some('\u{2800}'));
+
+        let blocks = HACK.to_half_blocks(&e.ram, 2);
+        assert_eq!((blocks.len(), blocks[0].chars().count()), (64, 256));
+        assert_eq!(blocks[1].chars().take(9).collect::<String>(), "\u{2588}".repeat(8) + " ");
+
+        let png = HACK.to_png(&e.ram);
+        assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+        assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+    }
+}
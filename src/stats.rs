@@ -0,0 +1,155 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use instruction::{self, Instruction};
+use symbol_table::SymbolTable;
+use cfg::Region;
+use json::Value;
+
+/// How many of the largest routines are listed
+const LARGEST_ROUTINES: usize = 10;
+
+/// Counts describing the shape and size of an assembled program
+pub struct Stats {
+    pub labels: usize,
+    pub a_instructions: usize,
+    pub c_instructions: usize,
+    pub comp: BTreeMap<String, usize>,
+    pub dest: BTreeMap<String, usize>,
+    pub jump: BTreeMap<String, usize>,
+    /// The size in words of each label region, in program order
+    pub regions: Vec<(String, u16)>,
+    pub variables: usize,
+}
+
+impl Stats {
+    pub fn collect(instructions: &[Instruction], regions: &[Region], symbols: &SymbolTable) -> Stats {
+        let mut stats = Stats {
+            labels: 0,
+            a_instructions: 0,
+            c_instructions: 0,
+            comp: BTreeMap::new(),
+            dest: BTreeMap::new(),
+            jump: BTreeMap::new(),
+            regions: regions.iter().map(|r| (r.name.clone(), r.len())).collect(),
+            variables: symbols.variables().len(),
+        };
+
+        for i in instructions {
+            match i {
+                Instruction::Label(_) => stats.labels += 1,
+                Instruction::AInstruction(_) => stats.a_instructions += 1,
+                Instruction::CInstruction { dest, comp, jump } => {
+                    stats.c_instructions += 1;
+                    let dest = match instruction::dest_string(dest) {
+                        ref d if d.is_empty() => String::from("null"),
+                        d => d,
+                    };
+                    let jump = match jump {
+                        Some(j) => j.kind.to_string(),
+                        None => String::from("null"),
+                    };
+                    // By ALU operation rather than spelling, so D+A and A+D are one
+                    let comp = i.comp_code().map(instruction::comp_string).unwrap_or_else(|| comp.to_string());
+                    *stats.comp.entry(comp).or_insert(0) += 1;
+                    *stats.dest.entry(dest).or_insert(0) += 1;
+                    *stats.jump.entry(jump).or_insert(0) += 1;
+                },
+            }
+        }
+
+        stats
+    }
+
+    pub fn words(&self) -> usize {
+        self.a_instructions + self.c_instructions
+    }
+
+    /// The label regions ordered from largest to smallest
+    pub fn largest_routines(&self) -> Vec<&(String, u16)> {
+        let mut routines: Vec<&(String, u16)> = self.regions.iter().collect();
+        routines.sort_by_key(|r| Reverse(r.1));
+        routines.truncate(LARGEST_ROUTINES);
+        routines
+    }
+
+    pub fn to_json(&self) -> Value {
+        let histogram = |counts: &BTreeMap<String, usize>| {
+            Value::Object(counts.iter().map(|(k, v)| (k.clone(), Value::number(*v as u32))).collect())
+        };
+        let routines = |regions: Vec<&(String, u16)>| {
+            Value::Array(regions.iter().map(|(name, size)| Value::object(vec![
+                ("name", Value::string(name)),
+                ("words", Value::number(*size)),
+            ])).collect())
+        };
+
+        Value::object(vec![
+            ("words", Value::number(self.words() as u32)),
+            ("labels", Value::number(self.labels as u32)),
+            ("a_instructions", Value::number(self.a_instructions as u32)),
+            ("c_instructions", Value::number(self.c_instructions as u32)),
+            ("variables", Value::number(self.variables as u32)),
+            ("comp", histogram(&self.comp)),
+            ("dest", histogram(&self.dest)),
+            ("jump", histogram(&self.jump)),
+            ("regions", routines(self.regions.iter().collect())),
+            ("largest_routines", routines(self.largest_routines())),
+        ])
+    }
+}
+
+fn write_histogram(f: &mut fmt::Formatter, title: &str, counts: &BTreeMap<String, usize>) -> fmt::Result {
+    writeln!(f, "{}:", title)?;
+    let mut counts: Vec<(&String, &usize)> = counts.iter().collect();
+    counts.sort_by_key(|c| Reverse(*c.1));
+    let most = counts.first().map(|c| *c.1).unwrap_or(0);
+    for (k, v) in counts {
+        let bar = "#".repeat((v * 40).div_ceil(most.max(1)));
+        writeln!(f, "  {:6} {:>7}  {}", k, v, bar)?;
+    }
+    writeln!(f)
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Words: {} ({} A-instructions, {} C-instructions)", self.words(), self.a_instructions, self.c_instructions)?;
+        writeln!(f, "Labels: {}", self.labels)?;
+        writeln!(f, "Variables: {}", self.variables)?;
+        writeln!(f)?;
+        write_histogram(f, "Comp", &self.comp)?;
+        write_histogram(f, "Dest", &self.dest)?;
+        write_histogram(f, "Jump", &self.jump)?;
+        writeln!(f, "Largest routines:")?;
+        for (name, size) in self.largest_routines() {
+            writeln!(f, "  {:>7}  {}", size, name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+    use cfg::Graph;
+    use stats::Stats;
+
+    #[test]
+    fn counts() {
+        let mut a = Assembler::new();
+        a.assemble("@x\nM=0\n(LOOP)\n@x\nM=M+1\nD=M\n@LOOP\nD;JLT\n".as_bytes()).unwrap();
+        let graph = Graph::build(a.instructions(), a.symbols());
+        let stats = Stats::collect(a.instructions(), &graph.regions, a.symbols());
+
+        assert_eq!(stats.words(), 7);
+        assert_eq!(stats.labels, 1);
+        assert_eq!(stats.variables, 1);
+        assert_eq!(stats.comp["M+1"], 1);
+        assert_eq!(a.instructions()[4].comp_code(), Some(0x1DC0));
+        assert_eq!(stats.dest["null"], 1);
+        assert_eq!(stats.jump["JLT"], 1);
+        assert_eq!(stats.largest_routines()[0], &(String::from("LOOP"), 5));
+        assert!(stats.to_json().to_string().starts_with(r#"{"words":7,"labels":1,"a_instructions":3"#));
+    }
+}
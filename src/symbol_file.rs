@@ -0,0 +1,188 @@
+use std::fmt;
+use std::fs;
+
+use emulator::{RAM_SIZE, ROM_SIZE};
+use symbol_table::SymbolTable;
+
+type Result<T> = std::result::Result<T, SymbolFileError>;
+
+/// A named address from another build
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    pub name: String,
+    pub address: u16,
+    /// The RAM words it takes up from its address, none for a ROM label
+    pub words: u16,
+}
+
+/// Reads a symbol file
+pub fn load(path: &str) -> std::result::Result<Vec<Import>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    parse(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Parses a symbol file: `NAME = ADDRESS` lines for RAM words,
+/// `NAME = FIRST-LAST` for RAM ranges and `label.NAME = ADDRESS` for ROM
+/// addresses, with `#` starting a comment
+///
+/// ```text
+/// counter = 16
+/// buffer = 17-80
+/// label.Sys.init = 120
+/// ```
+pub fn parse(text: &str) -> Result<Vec<Import>> {
+    let mut imports: Vec<Import> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line_number = i as u32 + 1;
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |description: &str| SymbolFileError::new(line_number, description);
+        let (name, value) = line.split_once('=').map(|(k, v)| (k.trim(), v.trim()))
+            .ok_or_else(|| error("Expected NAME = ADDRESS"))?;
+        let number = |value: &str, size: usize| value.trim().parse::<u32>().ok().filter(|&n| n < size as u32).map(|n| n as u16)
+            .ok_or_else(|| error(&format!("Bad address {}", value.trim())));
+
+        let import = match name.strip_prefix("label.") {
+            Some(label) => Import { name: label.to_string(), address: number(value, ROM_SIZE)?, words: 0 },
+            None => match value.split_once('-') {
+                Some((first, last)) => {
+                    let (first, last) = (number(first, RAM_SIZE)?, number(last, RAM_SIZE)?);
+                    if first > last {
+                        return Err(error("The first address is after the last"));
+                    }
+                    Import { name: name.to_string(), address: first, words: last - first + 1 }
+                },
+                None => Import { name: name.to_string(), address: number(value, RAM_SIZE)?, words: 1 },
+            },
+        };
+        if import.name.is_empty() || import.name.starts_with(|c: char| c.is_ascii_digit()) || import.name.contains(char::is_whitespace) {
+            return Err(error(&format!("Bad symbol {}", import.name)));
+        }
+        if imports.iter().any(|i| i.name == import.name) {
+            return Err(error(&format!("{} is already defined", import.name)));
+        }
+        imports.push(import);
+    }
+    Ok(imports)
+}
+
+/// Writes the variables and labels of an assembled program as a symbol file
+/// for other builds to import, leaving out the labels it imported
+pub fn export(symbols: &SymbolTable) -> String {
+    let mut out = String::from("# Variables\n");
+    for name in symbols.variables() {
+        match (symbols.extent(name), symbols.get(name)) {
+            (Some((first, last)), _) if last > first => out.push_str(&format!("{} = {}-{}\n", name, first, last)),
+            (_, Some(address)) => out.push_str(&format!("{} = {}\n", name, address)),
+            _ => (),
+        }
+    }
+    out.push_str("# Labels\n");
+    for (name, address) in symbols.labels().into_iter().filter(|&(name, _)| !symbols.is_imported(name)) {
+        out.push_str(&format!("label.{} = {}\n", name, address));
+    }
+    out
+}
+
+#[derive(Debug)]
+pub struct SymbolFileError {
+    description: String,
+    line: u32,
+}
+
+impl SymbolFileError {
+    fn new(line: u32, description: &str) -> SymbolFileError {
+        SymbolFileError {
+            description: description.to_string(),
+            line,
+        }
+    }
+}
+
+impl fmt::Display for SymbolFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Symbol file error: [Line {}] {} ", self.line, self.description)
+    }
+}
+
+impl std::error::Error for SymbolFileError {}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+    use lint::lint;
+    use symbol_file::{export, parse, Import};
+
+    const LAYOUT: &str = "\
+# Shared by the game and its sound driver
+score = 16
+queue = 18-21  # four notes
+label.PLAY = 300
+";
+
+    #[test]
+    fn imports() {
+        let imports = parse(LAYOUT).unwrap();
+        assert_eq!(imports[1], Import { name: String::from("queue"), address: 18, words: 4 });
+        assert_eq!(imports[2].words, 0);
+
+        // Variables go around the imported words, but not the label
+        let mut a = Assembler::new();
+        a.import(&imports).unwrap();
+        a.assemble("@a\nM=0\n@score\nM=0\n@b\nM=0\n@c\nM=0\n@queue\nM=0\n@PLAY\n".as_bytes()).unwrap();
+        let addresses: Vec<u16> = a.words().iter().step_by(2).cloned().collect();
+        assert_eq!(addresses, [17, 16, 22, 23, 18, 300]);
+        assert_eq!(export(a.symbols()), "# Variables\na = 17\nb = 22\nc = 23\n# Labels\n");
+
+        let mut b = Assembler::new();
+        b.assemble("(LOOP)\n@x\n@LOOP\n".as_bytes()).unwrap();
+        assert_eq!(parse(&export(b.symbols())).unwrap(), vec![
+            Import { name: String::from("x"), address: 16, words: 1 },
+            Import { name: String::from("LOOP"), address: 0, words: 0 },
+        ]);
+    }
+
+    #[test]
+    fn imported_label() {
+        // An imported label is a ROM address, so jumping to it is fine
+        let mut a = Assembler::new();
+        a.import(&parse("label.ENTRY = 120\n").unwrap()).unwrap();
+        let instructions = a.load("@ENTRY\n0;JMP\n".as_bytes()).unwrap();
+        assert!(lint(&instructions, a.symbols()).is_empty());
+        assert_eq!(a.symbols().label_address("ENTRY"), Some(120));
+        assert_eq!(export(a.symbols()), "# Variables\n# Labels\n");
+    }
+
+    #[test]
+    fn conflicts() {
+        // A program's own label or variable can't take an imported name
+        for (source, message) in &[
+            ("(PLAY)\n@PLAY\n0;JMP\n", "Instruction error: [Line 1] PLAY is already defined by an import "),
+            (".var score\n", "Instruction error: [Line 1] score is already defined by an import "),
+        ] {
+            let mut a = Assembler::new();
+            a.import(&parse(LAYOUT).unwrap()).unwrap();
+            assert_eq!(a.assemble(source.as_bytes()).unwrap_err().to_string(), *message);
+        }
+
+        let mut a = Assembler::new();
+        assert_eq!(a.import(&parse("SCREEN = 100\n").unwrap()).unwrap_err(), "SCREEN is predefined and can't be imported");
+        a.import(&parse(LAYOUT).unwrap()).unwrap();
+        assert_eq!(a.import(&parse("score = 40\n").unwrap()).unwrap_err(), "score is already defined");
+    }
+
+    #[test]
+    fn errors() {
+        for (text, message) in &[
+            ("score 16\n", "Symbol file error: [Line 1] Expected NAME = ADDRESS "),
+            ("\nqueue = 21-18\n", "Symbol file error: [Line 2] The first address is after the last "),
+            ("score = 40000\n", "Symbol file error: [Line 1] Bad address 40000 "),
+            ("3d = 16\n", "Symbol file error: [Line 1] Bad symbol 3d "),
+            ("score = 16\nscore = 17\n", "Symbol file error: [Line 2] score is already defined "),
+        ] {
+            assert_eq!(parse(text).unwrap_err().to_string(), *message);
+        }
+    }
+}
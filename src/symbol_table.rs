@@ -1,62 +1,268 @@
 use std::collections::HashMap;
 
+use emulator::RAM_SIZE;
+use platform::Platform;
+use symbol_file::Import;
+
 pub struct SymbolTable {
     ram: HashMap<String, u16>,
     var_address: u16,
+    /// The first and last addresses variables can go at
+    var_range: (u16, u16),
+    variables: Vec<String>,
+    /// The symbols imported, declared or allocated with the first and last
+    /// addresses of RAM they take up, which variables aren't allocated in
+    reserved: Vec<(String, u16, u16)>,
+    /// The predefined symbols with the first and last addresses of RAM they
+    /// name, which variables aren't allocated or declared over either
+    predefined: Vec<(String, u16, u16)>,
+    /// Unknown symbols are errors rather than new variables
+    strict: bool,
     rom: HashMap<String, u16>,
+    /// The symbols imported from another build rather than defined here
+    imported: Vec<String>,
 }
 
 impl SymbolTable {
-    pub fn new() -> SymbolTable {
-        let mut ram: HashMap<String, u16> = HashMap::new();
-
-        // Define pre-defined symbols
-        ram.insert(String::from("SP"), 0x0);
-        ram.insert(String::from("LCL"), 0x1);
-        ram.insert(String::from("ARG"), 0x2);
-        ram.insert(String::from("THIS"), 0x3);
-        ram.insert(String::from("THAT"), 0x4);
-        ram.insert(String::from("R0"), 0x0);
-        ram.insert(String::from("R1"), 0x1);
-        ram.insert(String::from("R2"), 0x2);
-        ram.insert(String::from("R3"), 0x3);
-        ram.insert(String::from("R4"), 0x4);
-        ram.insert(String::from("R5"), 0x5);
-        ram.insert(String::from("R6"), 0x6);
-        ram.insert(String::from("R7"), 0x7);
-        ram.insert(String::from("R8"), 0x8);
-        ram.insert(String::from("R9"), 0x9);
-        ram.insert(String::from("R10"), 0xA);
-        ram.insert(String::from("R11"), 0xB);
-        ram.insert(String::from("R12"), 0xC);
-        ram.insert(String::from("R13"), 0xD);
-        ram.insert(String::from("R14"), 0xE);
-        ram.insert(String::from("R15"), 0xF);
-        ram.insert(String::from("SCREEN"), 0x4000);
-        ram.insert(String::from("KBD"), 0x6000);
-
+    /// A table of a platform's predefined symbols, allocating variables in
+    /// its range
+    pub fn with_platform(platform: &Platform) -> SymbolTable {
+        let mut predefined: Vec<(String, u16, u16)> = platform.symbols.iter().map(|(s, a)| (s.clone(), *a, *a)).collect();
+        predefined.extend(platform.devices().iter().map(|d| (d.name().to_string(), d.address(), d.address() + d.words() - 1)));
         SymbolTable {
-            ram: ram,
-            var_address: 0x10,
+            ram: platform.predefined().into_iter().collect(),
+            var_address: platform.variables.0,
+            var_range: platform.variables,
+            variables: Vec::new(),
+            reserved: Vec::new(),
+            predefined,
+            strict: false,
             rom: HashMap::new(),
+            imported: Vec::new(),
+        }
+    }
+
+    /// Adds a predefined RAM symbol, like a device's register
+    pub fn define(&mut self, symbol: &str, address: u16) {
+        self.ram.insert(symbol.to_string(), address);
+        self.predefined.push((symbol.to_string(), address, address));
+    }
+
+    /// Adds symbols from another build, labels to ROM and the rest to RAM,
+    /// reserving the RAM words they take up so variables aren't allocated
+    /// over them. Fails on a name that's predefined or already imported.
+    pub fn import(&mut self, imports: &[Import]) -> Result<(), String> {
+        for import in imports {
+            if self.predefined.iter().any(|(s, _, _)| *s == import.name) {
+                return Err(format!("{} is predefined and can't be imported", import.name));
+            }
+            if self.get(&import.name).is_some() {
+                return Err(format!("{} is already defined", import.name));
+            }
+            self.imported.push(import.name.clone());
+            if import.words == 0 {
+                self.add_symbol(&import.name, import.address);
+                continue;
+            }
+            self.ram.insert(import.name.clone(), import.address);
+            self.reserved.push((import.name.clone(), import.address, import.address + import.words - 1));
         }
+        Ok(())
+    }
+
+    /// Determines if the symbol was imported from another build
+    pub fn is_imported(&self, symbol: &str) -> bool {
+        self.imported.iter().any(|s| s == symbol)
+    }
+
+    /// Declares a variable taking up `words` contiguous words of RAM, at a
+    /// fixed address or the next free ones in the variable range. Fails if
+    /// the symbol is already defined or the words overlap another symbol's,
+    /// predefined ones included.
+    pub fn declare(&mut self, symbol: &str, address: Option<u16>, words: u16) -> Result<u16, String> {
+        if self.is_imported(symbol) {
+            return Err(format!("{} is already defined by an import", symbol));
+        }
+        if self.get(symbol).is_some() {
+            return Err(format!("{} is already defined", symbol));
+        }
+        let address = match address {
+            Some(address) => {
+                let last = address as usize + words as usize - 1;
+                if last >= RAM_SIZE {
+                    return Err(format!("{} runs past the end of RAM", symbol));
+                }
+                if let Some((other, _, _)) = self.overlapping(address, last as u16) {
+                    return Err(format!("{} overlaps {}", symbol, other));
+                }
+                address
+            },
+            None => self.allocate(words).ok_or_else(|| {
+                format!("No RAM left for {} words of {}, which go from {} to {}", words, symbol, self.var_range.0, self.var_range.1)
+            })?,
+        };
+        self.ram.insert(symbol.to_string(), address);
+        self.variables.push(symbol.to_string());
+        self.reserved.push((symbol.to_string(), address, address + words - 1));
+        Ok(address)
+    }
+
+    /// Makes unknown symbols errors rather than new variables
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
     }
 
     pub fn add_symbol(&mut self, symbol: &str, address: u16) {
-        if self.rom.get(symbol).is_none() {
+        if !self.rom.contains_key(symbol) {
             self.rom.insert(symbol.to_string(), address);
         }
     }
 
-    pub fn address_for(&mut self, symbol: &str) -> u16 {
-        let r = &mut self.ram;
-        let va = &mut self.var_address;
-        self.rom.get(symbol).unwrap_or_else(|| {
-            &*r.entry(symbol.to_string()).or_insert_with(|| {
-                let address = *va;
-                *va += 1;
-                address
-            })
-        }).clone()
+    /// Determines if the symbol names a label in ROM rather than a RAM address
+    pub fn is_label(&self, symbol: &str) -> bool {
+        self.rom.contains_key(symbol)
+    }
+
+    /// The ROM address of a label, if the symbol is one
+    pub fn label_address(&self, symbol: &str) -> Option<u16> {
+        self.rom.get(symbol).cloned()
+    }
+
+    /// The address of a symbol without allocating it as a variable
+    pub fn get(&self, symbol: &str) -> Option<u16> {
+        self.rom.get(symbol).or_else(|| self.ram.get(symbol)).cloned()
+    }
+
+    /// Every label with its ROM address, ordered by address
+    pub fn labels(&self) -> Vec<(&str, u16)> {
+        let mut labels: Vec<(&str, u16)> = self.rom.iter().map(|(k, v)| (k.as_ref(), *v)).collect();
+        labels.sort_by_key(|&(k, v)| (v, k));
+        labels
+    }
+
+    /// Every predefined symbol and variable with its RAM address, ordered by address
+    pub fn ram_symbols(&self) -> Vec<(&str, u16)> {
+        let mut symbols: Vec<(&str, u16)> = self.ram.iter().map(|(k, v)| (k.as_ref(), *v)).collect();
+        symbols.sort_by_key(|&(k, v)| (v, k));
+        symbols
     }
-}
\ No newline at end of file
+
+    /// The variables declared or allocated by `address_for`, in allocation order
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// The first and last addresses of an imported or declared symbol
+    pub fn extent(&self, symbol: &str) -> Option<(u16, u16)> {
+        self.reserved.iter().find(|(s, _, _)| s == symbol).map(|&(_, first, last)| (first, last))
+    }
+
+    /// The reserved or predefined symbol taking up any of the addresses
+    fn overlapping(&self, first: u16, last: u16) -> Option<&(String, u16, u16)> {
+        self.reserved.iter().chain(&self.predefined).find(|&&(_, f, l)| f <= last && first <= l)
+    }
+
+    /// The first of the next `words` free words in the variable range
+    fn allocate(&mut self, words: u16) -> Option<u16> {
+        let mut address = self.var_address as usize;
+        let last = |address: usize| (address + words as usize - 1).min(u16::MAX as usize) as u16;
+        while let Some(&(_, _, l)) = self.overlapping(address as u16, last(address)) {
+            address = l as usize + 1;
+        }
+        if address + words as usize - 1 > self.var_range.1 as usize {
+            return None;
+        }
+        self.var_address = address as u16 + words;
+        Some(address as u16)
+    }
+
+    /// The address of a symbol, allocating the next free RAM word as a
+    /// variable the first time an unknown one is seen, past any reserved
+    /// words. None if there are no words left, or in strict mode.
+    pub fn address_for(&mut self, symbol: &str) -> Option<u16> {
+        if let Some(&address) = self.rom.get(symbol).or_else(|| self.ram.get(symbol)) {
+            return Some(address);
+        }
+        if self.strict {
+            return None;
+        }
+        let address = self.allocate(1)?;
+        self.ram.insert(symbol.to_string(), address);
+        self.variables.push(symbol.to_string());
+        self.reserved.push((symbol.to_string(), address, address));
+        Some(address)
+    }
+
+    /// The first and last addresses variables can go at
+    pub fn variable_range(&self) -> (u16, u16) {
+        self.var_range
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+    use platform::Platform;
+    use symbol_file::{export, parse};
+    use symbol_table::SymbolTable;
+
+    const SOURCE: &str = "\
+.var status @ 17
+.var buffer[4]
+.var counter
+@counter
+M=0
+@buffer
+M=0
+@status
+M=0
+@x
+M=0
+";
+
+    #[test]
+    fn declarations() {
+        let mut a = Assembler::new();
+        a.assemble(SOURCE.as_bytes()).unwrap();
+        // The fixed one first, then the array around it
+        let addresses: Vec<u16> = a.words().iter().step_by(2).cloned().collect();
+        assert_eq!(addresses, [22, 18, 17, 23]);
+        assert_eq!(a.symbols().extent("buffer"), Some((18, 21)));
+        assert_eq!(export(a.symbols()), "# Variables\nstatus = 17\nbuffer = 18-21\ncounter = 22\nx = 23\n# Labels\n");
+
+        for (source, message) in &[
+            (".var a @ 20\n.var b[4] @ 18\n", "Instruction error: [Line 2] b overlaps a "),
+            (".var buf[4] @ 0\n", "Instruction error: [Line 1] buf overlaps SP "),
+            (".var buf[4] @ 16382\n", "Instruction error: [Line 1] buf overlaps SCREEN "),
+            (".var key @ 24576\n", "Instruction error: [Line 1] key overlaps KBD "),
+            ("(LOOP)\n.var LOOP\n", "Instruction error: [Line 2] LOOP is already defined "),
+            (".var R0\n", "Instruction error: [Line 1] R0 is already defined "),
+            (".var big[20000]\n", "Instruction error: [Line 1] No RAM left for 20000 words of big, which go from 16 to 16383 "),
+            (".var buffer[0]\n", "Syntax error: [Line 1] Usage: .var NAME[WORDS] [@ ADDRESS] "),
+            (".var 2x\n", "Syntax error: [Line 1] Usage: .var NAME[WORDS] [@ ADDRESS] "),
+        ] {
+            assert_eq!(Assembler::new().assemble(source.as_bytes()).unwrap_err().to_string(), *message);
+        }
+
+        // Imported and implicit variables are taken too
+        let mut a = Assembler::new();
+        a.import(&parse("score = 30\n").unwrap()).unwrap();
+        assert_eq!(a.assemble(".var s[2] @ 29\n".as_bytes()).unwrap_err().to_string(), "Instruction error: [Line 1] s overlaps score ");
+        let mut symbols = SymbolTable::with_platform(&Platform::hack());
+        assert_eq!(symbols.address_for("x"), Some(16));
+        assert_eq!(symbols.declare("y", Some(16), 1), Err(String::from("y overlaps x")));
+    }
+
+    #[test]
+    fn strict() {
+        let source = ".var x\n(LOOP)\n@x\n@SCREEN\n@LOOP\n@y\n";
+        let mut a = Assembler::new();
+        a.set_strict(true);
+        assert_eq!(a.assemble(source.as_bytes()).unwrap_err().to_string(), "Instruction error: [Line 6] Undeclared symbol y ");
+        assert!(Assembler::new().assemble(source.as_bytes()).is_ok());
+    }
+}
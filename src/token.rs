@@ -21,10 +21,47 @@ pub enum TokenKind {
     JumpLessThan,
     JumpNotEqual,
     JumpLessThanEqual,
+    Comment(String),
+    /// A line starting with `.`, holding the rest of it
+    Directive(String),
     NewLine,
     EOF
 }
 
+impl std::fmt::Display for TokenKind {
+    /// Writes the token as it appears in source
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        use token::TokenKind::*;
+        match self {
+            Label(s) => write!(f, "({})", s),
+            Symbol(s) => write!(f, "@{}", s),
+            Address(n) => write!(f, "@{}", n),
+            Number(n) => write!(f, "{}", n),
+            Equal => write!(f, "="),
+            Plus => write!(f, "+"),
+            Minus => write!(f, "-"),
+            Not => write!(f, "!"),
+            And => write!(f, "&"),
+            Or => write!(f, "|"),
+            Memory => write!(f, "M"),
+            DRegister => write!(f, "D"),
+            ARegister => write!(f, "A"),
+            Semicolon => write!(f, ";"),
+            Jump => write!(f, "JMP"),
+            JumpGreaterThan => write!(f, "JGT"),
+            JumpEqual => write!(f, "JEQ"),
+            JumpGreaterThanEqual => write!(f, "JGE"),
+            JumpLessThan => write!(f, "JLT"),
+            JumpNotEqual => write!(f, "JNE"),
+            JumpLessThanEqual => write!(f, "JLE"),
+            Comment(s) => write!(f, "//{}", s),
+            Directive(s) => write!(f, ".{}", s),
+            NewLine => writeln!(f),
+            EOF => Ok(()),
+        }
+    }
+}
+
 #[derive(Debug,Clone,PartialEq)]
 pub struct Token {
     pub kind: TokenKind,
@@ -33,9 +70,9 @@ pub struct Token {
 
 impl Token {
     pub fn new(kind: TokenKind, line: u32) -> Token {
-        return Token {
-            kind: kind,
-            line: line,
+        Token {
+            kind,
+            line,
         }
     }
 }
@@ -0,0 +1,188 @@
+use std::io;
+use std::io::prelude::*;
+
+use token::TokenKind;
+use instruction::{self, Instruction};
+use assembler::Assembler;
+use emulator::{Emulator, MemoryWrite};
+
+/// Everything that happened in a single cycle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cycle {
+    pub cycle: u64,
+    pub pc: u16,
+    pub instruction: u16,
+    /// A and D before the instruction executed
+    pub a: u16,
+    pub d: u16,
+    pub write: Option<MemoryWrite>,
+}
+
+impl Cycle {
+    /// Describes the emulator's next instruction before it executes, leaving
+    /// the write to fill in afterwards
+    pub fn start(emulator: &Emulator) -> Cycle {
+        Cycle {
+            cycle: emulator.cycles,
+            pc: emulator.pc,
+            instruction: emulator.instruction(emulator.pc),
+            a: emulator.a,
+            d: emulator.d,
+            write: None,
+        }
+    }
+}
+
+/// The symbol each A-instruction was written with, by ROM address, so traces
+/// can show names instead of addresses
+pub fn symbol_names(assembler: &Assembler) -> Vec<Option<String>> {
+    assembler.instructions().iter()
+        .filter(|i| !matches!(i, Instruction::Label(_)))
+        .map(|i| match i {
+            Instruction::AInstruction(t) => match t.kind {
+                TokenKind::Symbol(ref s) => Some(s.clone()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+/// Writes a line per cycle: the cycle, PC, disassembled instruction, A and D
+/// after it executed, and the RAM write it made
+pub struct TextTrace<W: Write> {
+    output: W,
+    names: Vec<Option<String>>,
+}
+
+impl<W: Write> TextTrace<W> {
+    pub fn new(output: W, names: Vec<Option<String>>) -> TextTrace<W> {
+        TextTrace { output, names }
+    }
+
+    pub fn record(&mut self, c: &Cycle, emulator: &Emulator) -> io::Result<()> {
+        let name = self.names.get(c.pc as usize).and_then(|n| n.as_ref()).map(|n| n.as_ref());
+        let mut line = format!("{:>8} {:>5}  {:<18} A={:<5} D={:<6}",
+            c.cycle, c.pc, instruction::disassemble(c.instruction, name), emulator.a, emulator.d as i16);
+        if let Some(w) = c.write {
+            line += &format!(" RAM[{}]={}", w.address, w.new as i16);
+        }
+        writeln!(self.output, "{}", line.trim_end())
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// A Value Change Dump of the CPU's signals, one time unit per cycle, named
+/// like the nand2tetris CPU chip's pins. Registers hold their values from
+/// before the clock edge, as they would in the HDL simulation.
+pub struct VcdTrace<W: Write> {
+    output: W,
+    previous: Option<[u16; 7]>,
+}
+
+/// Name, width and identifier of each signal
+const SIGNALS: [(&str, u32, char); 7] = [
+    ("pc", 15, '!'),
+    ("instruction", 16, '"'),
+    ("A", 16, '#'),
+    ("D", 16, '$'),
+    ("writeM", 1, '%'),
+    ("addressM", 15, '&'),
+    ("outM", 16, '\''),
+];
+
+impl<W: Write> VcdTrace<W> {
+    pub fn new(mut output: W) -> io::Result<VcdTrace<W>> {
+        writeln!(output, "$version hackassembler $end")?;
+        writeln!(output, "$timescale 1 ns $end")?;
+        writeln!(output, "$scope module CPU $end")?;
+        for &(name, width, id) in &SIGNALS {
+            writeln!(output, "$var wire {} {} {} $end", width, id, name)?;
+        }
+        writeln!(output, "$upscope $end")?;
+        writeln!(output, "$enddefinitions $end")?;
+        Ok(VcdTrace { output, previous: None })
+    }
+
+    pub fn record(&mut self, c: &Cycle) -> io::Result<()> {
+        let (write_m, address_m, out_m) = match c.write {
+            Some(w) => (1, w.address, w.new),
+            None => (0, c.a & 0x7FFF, 0),
+        };
+        let values = [c.pc, c.instruction, c.a, c.d, write_m, address_m, out_m];
+
+        writeln!(self.output, "#{}", c.cycle)?;
+        for (i, &(_, width, id)) in SIGNALS.iter().enumerate() {
+            if self.previous.is_some_and(|p| p[i] == values[i]) {
+                continue;
+            }
+            if width == 1 {
+                writeln!(self.output, "{}{}", values[i], id)?;
+            } else {
+                writeln!(self.output, "b{:b} {}", values[i], id)?;
+            }
+        }
+        self.previous = Some(values);
+        Ok(())
+    }
+
+    /// Ends the dump at the time after the last cycle
+    pub fn finish(&mut self, cycles: u64) -> io::Result<()> {
+        writeln!(self.output, "#{}", cycles)?;
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use assembler::Assembler;
+    use emulator::Emulator;
+    use instruction;
+    use trace::{symbol_names, Cycle, TextTrace, VcdTrace};
+
+    fn assemble(source: &str) -> Assembler {
+        let mut a = Assembler::new();
+        a.assemble(source.as_bytes()).unwrap();
+        a
+    }
+
+    #[test]
+    fn disassembles_every_instruction() {
+        let mut a = Assembler::new();
+        a.assemble(File::open("test/pong/Pong.asm").unwrap()).unwrap();
+        let source: String = a.words().iter().map(|&w| instruction::disassemble(w, None) + "\n").collect();
+        let mut b = Assembler::new();
+        b.assemble(source.as_bytes()).unwrap();
+        assert_eq!(a.words(), b.words());
+    }
+
+    #[test]
+    fn traces() {
+        let a = assemble("@x\nM=1\nD=M+1\n@x\nM=D;JGT\n");
+        let mut e = Emulator::new(a.words());
+        let mut text = TextTrace::new(Vec::new(), symbol_names(&a));
+        let mut vcd = VcdTrace::new(Vec::new()).unwrap();
+        for _ in 0..5 {
+            let mut c = Cycle::start(&e);
+            c.write = e.step();
+            text.record(&c, &e).unwrap();
+            vcd.record(&c).unwrap();
+        }
+        vcd.finish(e.cycles).unwrap();
+
+        let text = String::from_utf8(text.output).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "       0     0  @x                 A=16    D=0");
+        assert_eq!(lines[1], "       1     1  M=1                A=16    D=0      RAM[16]=1");
+        assert_eq!(lines[4], "       4     4  M=D;JGT            A=16    D=2      RAM[16]=2");
+
+        let vcd = String::from_utf8(vcd.output).unwrap();
+        assert!(vcd.contains("$var wire 16 \" instruction $end"));
+        assert!(vcd.contains("#1\nb1 !\nb1110111111001000 \"\nb10000 #\n1%\nb10000 &\nb1 '\n#2\n"));
+        assert!(vcd.ends_with("#5\n"));
+    }
+}
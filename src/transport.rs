@@ -0,0 +1,84 @@
+use std::io;
+use std::io::prelude::*;
+
+use json::Value;
+
+/// The largest message body accepted, so a bogus `Content-Length` can't
+/// exhaust memory
+const MAX_MESSAGE: usize = 16 * 1024 * 1024;
+
+/// Reads a message framed by a `Content-Length` header, as used by the
+/// language server and debug adapter protocols. Returns `None` at end of input.
+pub fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+    let mut length: Option<usize> = None;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let line = header.trim_end();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("Content-Length") {
+            length = parts.next().and_then(|v| v.trim().parse().ok());
+        }
+    }
+
+    let length = length.unwrap_or(0);
+    if length > MAX_MESSAGE {
+        // Skip the body so the next message is still read from its header
+        io::copy(&mut reader.take(length as u64), &mut io::sink())?;
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Message is too large"));
+    }
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Value::parse(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use json::Value;
+    use transport::{read_message, write_message, MAX_MESSAGE};
+
+    #[test]
+    fn round_trip() {
+        let mut out = Vec::new();
+        let message = Value::object(vec![("method", Value::string("initialized"))]);
+        write_message(&mut out, &message).unwrap();
+        write_message(&mut out, &message).unwrap();
+
+        let mut input = Cursor::new(out);
+        assert_eq!(read_message(&mut input).unwrap(), Some(message.clone()));
+        assert_eq!(read_message(&mut input).unwrap(), Some(message));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn oversized() {
+        let mut out = format!("Content-Length: {}\r\n\r\n", MAX_MESSAGE + 1).into_bytes();
+        out.resize(out.len() + MAX_MESSAGE + 1, b' ');
+        let message = Value::object(vec![("method", Value::string("initialized"))]);
+        write_message(&mut out, &message).unwrap();
+
+        let mut input = Cursor::new(out);
+        assert_eq!(read_message(&mut input).unwrap_err().kind(), ::std::io::ErrorKind::InvalidData);
+        assert_eq!(read_message(&mut input).unwrap(), Some(message));
+    }
+}
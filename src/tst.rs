@@ -0,0 +1,554 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use assembler::Assembler;
+use coverage::Coverage;
+use emulator::{self, Emulator};
+use platform::Platform;
+
+type Result<T> = std::result::Result<T, ScriptError>;
+
+/// Passes through a `repeat` without a count, or a `while`, before the script fails
+pub const REPEAT_LIMIT: u64 = 10_000_000;
+
+/// A statement of a nand2tetris test script
+#[derive(Debug, PartialEq)]
+enum Statement {
+    Command { line: u32, words: Vec<String> },
+    Repeat { line: u32, count: Option<u64>, body: Vec<Statement> },
+    While { line: u32, condition: Condition, body: Vec<Statement> },
+}
+
+#[derive(Debug, PartialEq)]
+struct Condition {
+    left: String,
+    operator: String,
+    right: String,
+}
+
+/// A value written by `output`, from a spec like `RAM[0]%D2.6.2`
+#[derive(Debug, Clone, PartialEq)]
+struct Column {
+    name: String,
+    format: char,
+    left: usize,
+    width: usize,
+    right: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+    Separator,
+    Open,
+    Close,
+}
+
+/// A line of output that doesn't match the compare file
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+    pub line: usize,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+/// The result of running a test script
+pub struct Report {
+    pub output: Vec<String>,
+    pub output_file: Option<PathBuf>,
+    pub compared: bool,
+    pub mismatches: Vec<Mismatch>,
+    /// What ran of the last program loaded from `.asm` source
+    pub coverage: Option<Coverage>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for m in &self.mismatches {
+            writeln!(f, "Line {}:", m.line)?;
+            match m.expected {
+                Some(ref e) => writeln!(f, "- {}", e)?,
+                None => writeln!(f, "- (no line)")?,
+            }
+            match m.actual {
+                Some(ref a) => writeln!(f, "+ {}", a)?,
+                None => writeln!(f, "+ (no line)")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs CPU emulator test scripts against the built-in emulator
+pub struct Runner {
+    directory: PathBuf,
+    emulator: Emulator,
+    columns: Vec<Column>,
+    output: Vec<String>,
+    output_file: Option<PathBuf>,
+    compare_file: Option<PathBuf>,
+    coverage: Option<Coverage>,
+    platform: Platform,
+}
+
+impl Runner {
+    /// Runs a script file. Files named by the script are relative to its directory.
+    pub fn run_file(path: &str, platform: &Platform) -> Result<Report> {
+        let script = fs::read_to_string(path).map_err(|e| ScriptError::new(0, &format!("{}: {}", path, e)))?;
+        let directory = Path::new(path).parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        Runner::run(&script, &directory, platform)
+    }
+
+    pub fn run(script: &str, directory: &Path, platform: &Platform) -> Result<Report> {
+        let tokens = tokenize(script)?;
+        let mut position = 0;
+        let statements = parse_block(&tokens, &mut position, false)?;
+
+        let mut runner = Runner {
+            directory: directory.to_path_buf(),
+            emulator: Emulator::with_platform(&[], platform),
+            columns: Vec::new(),
+            output: Vec::new(),
+            output_file: None,
+            compare_file: None,
+            coverage: None,
+            platform: platform.clone(),
+        };
+        runner.execute(&statements)?;
+        if let Some(ref mut coverage) = runner.coverage {
+            coverage.finish(&runner.emulator);
+        }
+
+        let mut report = Report {
+            output: runner.output,
+            output_file: runner.output_file,
+            compared: false,
+            mismatches: Vec::new(),
+            coverage: runner.coverage,
+        };
+        if let Some(path) = runner.compare_file {
+            let expected = fs::read_to_string(&path)
+                .map_err(|e| ScriptError::new(0, &format!("{}: {}", path.display(), e)))?;
+            report.mismatches = compare(&expected, &report.output);
+            report.compared = true;
+        }
+        Ok(report)
+    }
+
+    fn execute(&mut self, statements: &[Statement]) -> Result<()> {
+        for statement in statements {
+            match statement {
+                Statement::Command { line, words } => self.command(*line, words)?,
+                Statement::Repeat { count: Some(count), body, .. } => {
+                    for _ in 0..*count {
+                        self.execute(body)?;
+                    }
+                },
+                Statement::Repeat { line, count: None, body } => {
+                    for _ in 0..REPEAT_LIMIT {
+                        self.execute(body)?;
+                    }
+                    return Err(ScriptError::new(*line, &format!("Repeat ran {} times without stopping", REPEAT_LIMIT)));
+                },
+                Statement::While { line, condition, body } => {
+                    let mut passes = 0;
+                    while self.holds(*line, condition)? {
+                        if passes == REPEAT_LIMIT {
+                            return Err(ScriptError::new(*line, &format!("While ran {} times without stopping", REPEAT_LIMIT)));
+                        }
+                        self.execute(body)?;
+                        passes += 1;
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, line: u32, words: &[String]) -> Result<()> {
+        let argument = |i: usize| words.get(i).ok_or_else(|| ScriptError::new(line, &format!("Missing argument to {}", words[0])));
+        match words[0].as_ref() {
+            "load" => {
+                let (program, coverage) = self.load(line, argument(1)?)?;
+                if let Some(ref mut c) = self.coverage {
+                    c.finish(&self.emulator);
+                }
+                self.emulator = Emulator::with_platform(&program, &self.platform);
+                // Reloading the same program adds to its coverage
+                match (&self.coverage, coverage) {
+                    (Some(old), Some(new)) if old.same_program(&new) => (),
+                    (_, new) => self.coverage = new,
+                }
+            },
+            "output-file" => self.output_file = Some(self.directory.join(argument(1)?)),
+            "compare-to" => self.compare_file = Some(self.directory.join(argument(1)?)),
+            "output-list" => {
+                self.columns = words[1..].iter().map(|w| parse_column(line, w)).collect::<Result<Vec<Column>>>()?;
+                let header = self.columns.iter().map(header).collect::<Vec<String>>();
+                self.output.push(format!("|{}|", header.join("|")));
+            },
+            "output" => {
+                let values = self.columns.iter()
+                    .map(|c| Ok(format_value(c, self.get(line, &c.name)?)))
+                    .collect::<Result<Vec<String>>>()?;
+                self.output.push(format!("|{}|", values.join("|")));
+            },
+            "set" => {
+                let value = parse_value(line, argument(2)?)?;
+                self.set(line, argument(1)?, value)?;
+            },
+            "ticktock" | "tock" => {
+                let pc = self.emulator.pc;
+                self.emulator.step();
+                if let Some(ref mut coverage) = self.coverage {
+                    coverage.record(pc, self.emulator.pc);
+                }
+            },
+            "tick" | "echo" | "clear-echo" | "breakpoint" | "clear-breakpoints" => (),
+            other => return Err(ScriptError::new(line, &format!("Unknown command {}", other))),
+        }
+        Ok(())
+    }
+
+    /// Loads a program, assembling the `.asm` source next to a `.hack` file
+    /// when there is one, in which case its coverage is tracked
+    fn load(&self, line: u32, name: &str) -> Result<(Vec<u16>, Option<Coverage>)> {
+        let path = self.directory.join(name);
+        let source = path.with_extension("asm");
+        let error = |e: &dyn fmt::Display| ScriptError::new(line, &format!("{}: {}", name, e));
+        if source.exists() {
+            let text = fs::read_to_string(&source).map_err(|e| error(&e))?;
+            let mut a = Assembler::with_platform(&self.platform);
+            a.assemble(text.as_bytes()).map_err(|e| error(&e))?;
+            return Ok((a.words().to_vec(), Some(Coverage::new(&source.display().to_string(), &a))));
+        }
+        let text = fs::read_to_string(&path).map_err(|e| error(&e))?;
+        Ok((emulator::parse_hack(&text).map_err(|e| error(&e))?, None))
+    }
+
+    fn get(&self, line: u32, name: &str) -> Result<u16> {
+        match name {
+            "A" => Ok(self.emulator.a),
+            "D" => Ok(self.emulator.d),
+            "PC" => Ok(self.emulator.pc),
+            "time" => Ok(self.emulator.cycles as u16),
+            _ => Ok(self.emulator.read(ram_address(line, name)?)),
+        }
+    }
+
+    fn set(&mut self, line: u32, name: &str, value: u16) -> Result<()> {
+        match name {
+            "A" => self.emulator.a = value,
+            "D" => self.emulator.d = value,
+            "PC" => self.emulator.pc = value,
+            _ => {
+                let address = ram_address(line, name)?;
+                self.emulator.write(address, value);
+            },
+        }
+        Ok(())
+    }
+
+    fn holds(&self, line: u32, condition: &Condition) -> Result<bool> {
+        let value = |s: &str| -> Result<i16> {
+            match parse_value(line, s) {
+                Ok(v) => Ok(v as i16),
+                Err(_) => Ok(self.get(line, s)? as i16),
+            }
+        };
+        let (left, right) = (value(&condition.left)?, value(&condition.right)?);
+        Ok(match condition.operator.as_ref() {
+            "=" => left == right,
+            "<>" => left != right,
+            "<" => left < right,
+            "<=" => left <= right,
+            ">" => left > right,
+            ">=" => left >= right,
+            other => return Err(ScriptError::new(line, &format!("Unknown operator {}", other))),
+        })
+    }
+}
+
+fn ram_address(line: u32, name: &str) -> Result<u16> {
+    name.strip_prefix("RAM[")
+        .and_then(|s| s.strip_suffix(']'))
+        .and_then(|s| s.parse::<u16>().ok())
+        .filter(|&a| (a as usize) < emulator::RAM_SIZE)
+        .ok_or_else(|| ScriptError::new(line, &format!("Unknown variable {}", name)))
+}
+
+/// Parses a decimal number, or one prefixed by `%X`, `%B` or `%D`
+fn parse_value(line: u32, s: &str) -> Result<u16> {
+    let parsed = if let Some(hex) = s.strip_prefix("%X") {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = s.strip_prefix("%B") {
+        u16::from_str_radix(binary, 2).ok()
+    } else {
+        let decimal = s.strip_prefix("%D").unwrap_or(s);
+        decimal.parse::<i16>().map(|v| v as u16).or_else(|_| decimal.parse::<u16>()).ok()
+    };
+    parsed.ok_or_else(|| ScriptError::new(line, &format!("Bad value {}", s)))
+}
+
+fn parse_column(line: u32, spec: &str) -> Result<Column> {
+    let mut parts = spec.splitn(2, '%');
+    let name = parts.next().unwrap_or("").to_string();
+    let format = match parts.next() {
+        Some(f) => f,
+        None => return Ok(Column { name, format: 'D', left: 1, width: 6, right: 1 }),
+    };
+    let bad = || ScriptError::new(line, &format!("Bad output format {}", spec));
+    let kind = format.chars().next().filter(|c| "BDXS".contains(*c)).ok_or_else(bad)?;
+    let sizes = format[1..].split('.').map(|n| n.parse::<usize>()).collect::<std::result::Result<Vec<usize>, _>>().map_err(|_| bad())?;
+    if sizes.len() != 3 {
+        return Err(bad());
+    }
+    Ok(Column { name, format: kind, left: sizes[0], width: sizes[1], right: sizes[2] })
+}
+
+/// The column's name centered over its value
+fn header(c: &Column) -> String {
+    let total = c.left + c.width + c.right;
+    let name: String = c.name.chars().take(total).collect();
+    let left = (total - name.len()) / 2;
+    format!("{}{}{}", " ".repeat(left), name, " ".repeat(total - left - name.len()))
+}
+
+fn format_value(c: &Column, value: u16) -> String {
+    let text = match c.format {
+        'X' => format!("{:0w$X}", value, w = c.width),
+        'B' => format!("{:0w$b}", value, w = c.width),
+        _ => (value as i16).to_string(),
+    };
+    // Numbers too wide for the column keep their low digits
+    let text = if text.len() > c.width { text[text.len() - c.width..].to_string() } else { text };
+    format!("{}{:>w$}{}", " ".repeat(c.left), text, " ".repeat(c.right), w = c.width)
+}
+
+/// Compares output to the expected lines, where `*` in the compare file
+/// matches any character
+fn compare(expected: &str, actual: &[String]) -> Vec<Mismatch> {
+    let expected: Vec<&str> = expected.lines().map(|l| l.trim_end()).filter(|l| !l.is_empty()).collect();
+    let matches = |e: &str, a: &str| {
+        e.len() == a.len() && e.chars().zip(a.chars()).all(|(e, a)| e == '*' || e == a)
+    };
+    (0..expected.len().max(actual.len()))
+        .filter_map(|i| {
+            let e = expected.get(i).cloned();
+            let a = actual.get(i).map(|s| s.as_ref());
+            match (e, a) {
+                (Some(e), Some(a)) if matches(e, a) => None,
+                _ => Some(Mismatch { line: i + 1, expected: e.map(String::from), actual: a.map(String::from) }),
+            }
+        })
+        .collect()
+}
+
+fn tokenize(script: &str) -> Result<Vec<(Token, u32)>> {
+    let mut tokens = Vec::new();
+    let mut chars = script.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            },
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if previous == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            previous = c;
+                        },
+                        None => return Err(ScriptError::new(line, "Unterminated comment")),
+                    }
+                }
+            },
+            ',' | ';' | '!' => tokens.push((Token::Separator, line)),
+            '{' => tokens.push((Token::Open, line)),
+            '}' => tokens.push((Token::Close, line)),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\n') | None => return Err(ScriptError::new(line, "Unterminated string")),
+                        Some(c) => text.push(c),
+                    }
+                }
+                tokens.push((Token::Text(text), line));
+            },
+            c if c.is_whitespace() => (),
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || ",;!{}\"".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push((Token::Word(word), line));
+            },
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_block(tokens: &[(Token, u32)], position: &mut usize, nested: bool) -> Result<Vec<Statement>> {
+    let mut statements = Vec::new();
+    let mut words: Vec<String> = Vec::new();
+    let mut line = 0;
+    while *position < tokens.len() {
+        let (ref token, token_line) = tokens[*position];
+        *position += 1;
+        match token {
+            Token::Word(w) | Token::Text(w) => {
+                if words.is_empty() {
+                    line = token_line;
+                }
+                words.push(w.clone());
+            },
+            Token::Separator => {
+                if !words.is_empty() {
+                    statements.push(Statement::Command { line, words: words.split_off(0) });
+                }
+            },
+            Token::Open => {
+                let body = parse_block(tokens, position, true)?;
+                statements.push(loop_statement(line, words.split_off(0), body)?);
+            },
+            Token::Close if nested => {
+                if !words.is_empty() {
+                    return Err(ScriptError::new(token_line, "Expected ; before }"));
+                }
+                return Ok(statements);
+            },
+            Token::Close => return Err(ScriptError::new(token_line, "Unexpected }")),
+        }
+    }
+    if nested {
+        return Err(ScriptError::new(line, "Missing }"));
+    }
+    if !words.is_empty() {
+        statements.push(Statement::Command { line, words });
+    }
+    Ok(statements)
+}
+
+fn loop_statement(line: u32, words: Vec<String>, body: Vec<Statement>) -> Result<Statement> {
+    match words.first().map(|w| w.as_ref()) {
+        Some("repeat") => {
+            let count = match words.get(1) {
+                Some(n) => Some(n.parse().map_err(|_| ScriptError::new(line, &format!("Bad repeat count {}", n)))?),
+                None => None,
+            };
+            Ok(Statement::Repeat { line, count, body })
+        },
+        Some("while") => {
+            let condition = words[1..].join(" ");
+            let condition = parse_condition(&condition)
+                .ok_or_else(|| ScriptError::new(line, &format!("Bad condition {}", condition)))?;
+            Ok(Statement::While { line, condition, body })
+        },
+        _ => Err(ScriptError::new(line, "Expected repeat or while before {")),
+    }
+}
+
+fn parse_condition(s: &str) -> Option<Condition> {
+    for operator in &["<>", "<=", ">=", "=", "<", ">"] {
+        if let Some(i) = s.find(operator) {
+            return Some(Condition {
+                left: s[..i].trim().to_string(),
+                operator: operator.to_string(),
+                right: s[i + operator.len()..].trim().to_string(),
+            });
+        }
+    }
+    None
+}
+
+#[derive(Debug)]
+pub struct ScriptError {
+    description: String,
+    line: u32,
+}
+
+impl ScriptError {
+    fn new(line: u32, description: &str) -> ScriptError {
+        ScriptError {
+            description: description.to_string(),
+            line,
+        }
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Script error: [Line {}] {} ", self.line, self.description)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use platform::Platform;
+    use tst::Runner;
+
+    #[test]
+    fn scripts() {
+        for path in &["test/add/Add.tst", "test/max/Max.tst", "test/rect/Rect.tst"] {
+            let report = Runner::run_file(path, &Platform::hack()).unwrap();
+            assert!(report.compared);
+            assert!(report.passed(), "{}\n{}", path, report);
+        }
+
+        // Both runs of Max add up to every line and both arms of its jump
+        let lcov = Runner::run_file("test/max/Max.tst", &Platform::hack()).unwrap().coverage.unwrap().to_lcov();
+        assert!(lcov.contains("SF:test/max/Max.asm\n"));
+        assert!(lcov.contains("BRH:2\n"));
+        assert!(lcov.contains("LF:16\nLH:16\n"));
+    }
+
+    #[test]
+    fn diff() {
+        let script = "load Max.asm, compare-to Max.cmp, output-list RAM[2]%D2.6.2 RAM[1];\n\
+                      set RAM[0] 3, set RAM[1] 5;\n\
+                      while PC <> 14 { ticktock; }\n\
+                      output;";
+        let report = Runner::run(script, Path::new("test/max"), &Platform::hack()).unwrap();
+        assert_eq!(report.output, vec!["|  RAM[2]  | RAM[1] |", "|       5  |      5 |"]);
+        assert_eq!(report.mismatches.len(), 3);
+        assert_eq!(report.to_string().lines().take(3).collect::<Vec<&str>>(),
+            vec!["Line 1:", "- |  RAM[0]  |  RAM[1]  |  RAM[2]  |", "+ |  RAM[2]  | RAM[1] |"]);
+    }
+
+    #[test]
+    fn endless_repeat() {
+        let script = "load Max.asm;\nrepeat {\n  ticktock;\n}";
+        let error = Runner::run(script, Path::new("test/max"), &Platform::hack()).err().unwrap();
+        assert_eq!(error.to_string(), "Script error: [Line 2] Repeat ran 10000000 times without stopping ");
+
+        let script = "load Max.asm;\nwhile PC <> 100 {\n  ticktock;\n}";
+        let error = Runner::run(script, Path::new("test/max"), &Platform::hack()).err().unwrap();
+        assert_eq!(error.to_string(), "Script error: [Line 2] While ran 10000000 times without stopping ");
+    }
+}
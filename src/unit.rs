@@ -0,0 +1,284 @@
+use std::fmt;
+
+use assembler::Assembler;
+use emulator::Emulator;
+use platform::Platform;
+use token::TokenKind;
+
+type Result<T> = std::result::Result<T, TestError>;
+
+/// Instructions a `.run` executes before the test fails, unless it gives its own limit
+pub const DEFAULT_LIMIT: u64 = 1_000_000;
+
+/// A register or RAM word a test sets or checks
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Location {
+    A,
+    D,
+    Ram(u16),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Set { location: Location, value: u16 },
+    /// Runs from an address, or where execution is, until the program halts
+    Run { start: Option<u16>, limit: u64 },
+    Expect { name: String, location: Location, value: u16 },
+}
+
+/// A test block: a `.test "name"` directive and the `.set`, `.run` and
+/// `.expect` directives after it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Test {
+    pub name: String,
+    pub line: u32,
+    steps: Vec<(u32, Step)>,
+}
+
+/// A failed expectation or run, at the line of its directive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Failure {
+    pub line: u32,
+    pub description: String,
+}
+
+/// The result of running a test
+pub struct Outcome {
+    pub failures: Vec<Failure>,
+    pub cycles: u64,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Reads the test blocks of an assembled program from its directives
+pub fn parse(assembler: &Assembler) -> Result<Vec<Test>> {
+    let mut tests: Vec<Test> = Vec::new();
+    for token in assembler.directives() {
+        let text = match token.kind {
+            TokenKind::Directive(ref text) => text,
+            _ => continue,
+        };
+        let line = token.line;
+        let mut words = text.splitn(2, char::is_whitespace);
+        let directive = words.next().unwrap_or("");
+        let rest = words.next().unwrap_or("").trim();
+
+        // Variable declarations are the assembler's
+        if directive == "var" {
+            continue;
+        }
+        if directive == "test" {
+            let name = rest.strip_prefix('"').and_then(|r| r.strip_suffix('"'))
+                .ok_or_else(|| TestError::new(line, "Expected a quoted test name"))?;
+            tests.push(Test { name: name.to_string(), line, steps: Vec::new() });
+            continue;
+        }
+
+        let test = tests.last_mut()
+            .ok_or_else(|| TestError::new(line, &format!(".{} outside a .test block", directive)))?;
+        let arguments: Vec<&str> = rest.split_whitespace().collect();
+        let step = match (directive, arguments.as_slice()) {
+            ("set", [location, value]) => Step::Set {
+                location: parse_location(assembler, line, location)?,
+                value: parse_value(assembler, line, value)?,
+            },
+            ("expect", [location, value]) => Step::Expect {
+                name: location.to_string(),
+                location: parse_location(assembler, line, location)?,
+                value: parse_value(assembler, line, value)?,
+            },
+            ("run", arguments) if arguments.len() <= 2 => {
+                let mut start = None;
+                let mut limit = DEFAULT_LIMIT;
+                for argument in arguments {
+                    match argument.parse::<u64>() {
+                        Ok(n) => limit = n,
+                        Err(_) => start = Some(assembler.symbols().label_address(argument)
+                            .ok_or_else(|| TestError::new(line, &format!("No label {}", argument)))?),
+                    }
+                }
+                Step::Run { start, limit }
+            },
+            ("set", _) => return Err(TestError::new(line, "Usage: .set LOCATION VALUE")),
+            ("expect", _) => return Err(TestError::new(line, "Usage: .expect LOCATION VALUE")),
+            ("run", _) => return Err(TestError::new(line, "Usage: .run [LABEL] [MAX_CYCLES]")),
+            (other, _) => return Err(TestError::new(line, &format!("Unknown directive .{}", other))),
+        };
+        test.steps.push((line, step));
+    }
+    Ok(tests)
+}
+
+/// Parses `A`, `D`, `RAM[n]` or a RAM symbol
+fn parse_location(assembler: &Assembler, line: u32, text: &str) -> Result<Location> {
+    match text {
+        "A" => return Ok(Location::A),
+        "D" => return Ok(Location::D),
+        _ => (),
+    }
+    if let Some(address) = text.strip_prefix("RAM[").and_then(|t| t.strip_suffix(']')) {
+        return address.parse::<u16>().map(Location::Ram).map_err(|_| TestError::new(line, &format!("Bad address {}", text)));
+    }
+    let symbols = assembler.symbols();
+    if symbols.is_label(text) {
+        return Err(TestError::new(line, &format!("{} is a label, not RAM", text)));
+    }
+    symbols.get(text).map(Location::Ram).ok_or_else(|| TestError::new(line, &format!("No symbol {}", text)))
+}
+
+/// Parses a number, which may be negative, or a symbol's address
+fn parse_value(assembler: &Assembler, line: u32, text: &str) -> Result<u16> {
+    text.parse::<i16>().map(|v| v as u16).or_else(|_| text.parse::<u16>())
+        .ok().or_else(|| assembler.symbols().get(text))
+        .ok_or_else(|| TestError::new(line, &format!("Bad value {}", text)))
+}
+
+impl Test {
+    /// Runs the test on a fresh emulator for the platform
+    pub fn run(&self, program: &[u16], platform: &Platform) -> Outcome {
+        let mut e = Emulator::with_platform(program, platform);
+        let mut failures = Vec::new();
+        for (line, step) in &self.steps {
+            match *step {
+                Step::Set { location, value } => match location {
+                    Location::A => e.a = value,
+                    Location::D => e.d = value,
+                    Location::Ram(address) => e.write(address, value),
+                },
+                Step::Run { start, limit } => {
+                    if let Some(start) = start {
+                        e.pc = start;
+                    }
+                    e.run(e.cycles + limit);
+                    if !e.halted() {
+                        failures.push(Failure { line: *line, description: format!("Didn't halt within {} cycles", limit) });
+                        break;
+                    }
+                },
+                Step::Expect { ref name, location, value } => {
+                    let actual = match location {
+                        Location::A => e.a,
+                        Location::D => e.d,
+                        Location::Ram(address) => e.read(address),
+                    };
+                    if actual != value {
+                        failures.push(Failure {
+                            line: *line,
+                            description: format!("Expected {} = {}, got {}", name, value as i16, actual as i16),
+                        });
+                    }
+                },
+            }
+        }
+        Outcome { failures, cycles: e.cycles }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[Line {}] {}", self.line, self.description)
+    }
+}
+
+#[derive(Debug)]
+pub struct TestError {
+    description: String,
+    line: u32,
+}
+
+impl TestError {
+    fn new(line: u32, description: &str) -> TestError {
+        TestError {
+            description: description.to_string(),
+            line,
+        }
+    }
+}
+
+impl fmt::Display for TestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Test error: [Line {}] {} ", self.line, self.description)
+    }
+}
+
+impl std::error::Error for TestError {}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+    use unit::{parse, Failure};
+
+    const SOURCE: &str = "\
+(MAX)
+    @R0
+    D=M
+    @R1
+    D=D-M
+    @FIRST
+    D;JGT
+    @R1
+    D=M
+    @STORE
+    0;JMP
+(FIRST)
+    @R0
+    D=M
+(STORE)
+    @R2
+    M=D
+(END)
+    @END
+    0;JMP
+
+.test \"max picks larger\"
+.set R0 3
+.set R1 7
+.run MAX
+.expect R2 7
+
+.test \"max of negatives\"  // two's complement
+.set R0 -4
+.set R1 -9
+.run
+.expect R2 -9
+.expect D -4
+
+.test \"limit\"
+.set R0 1
+.run STORE 2
+";
+
+    #[test]
+    fn blocks() {
+        let mut a = Assembler::new();
+        a.assemble(SOURCE.as_bytes()).unwrap();
+        assert_eq!(a.words().len(), 16);
+
+        let tests = parse(&a).unwrap();
+        assert_eq!(tests.iter().map(|t| (t.name.as_ref(), t.line)).collect::<Vec<(&str, u32)>>(),
+            vec![("max picks larger", 22), ("max of negatives", 28), ("limit", 35)]);
+
+        let outcomes: Vec<Vec<Failure>> = tests.iter().map(|t| t.run(a.words(), a.platform()).failures).collect();
+        assert_eq!(outcomes[0], vec![]);
+        assert_eq!(outcomes[1], vec![Failure { line: 32, description: String::from("Expected R2 = -9, got -4") }]);
+        assert_eq!(outcomes[2], vec![Failure { line: 37, description: String::from("Didn't halt within 2 cycles") }]);
+    }
+
+    #[test]
+    fn errors() {
+        for (source, message) in &[
+            (".set R0 1\n", "Test error: [Line 1] .set outside a .test block "),
+            (".test max\n", "Test error: [Line 1] Expected a quoted test name "),
+            (".test \"a\"\n.run NOWHERE\n", "Test error: [Line 2] No label NOWHERE "),
+            (".test \"a\"\n.expect R0\n", "Test error: [Line 2] Usage: .expect LOCATION VALUE "),
+        ] {
+            let mut a = Assembler::new();
+            a.assemble(source.as_bytes()).unwrap();
+            assert_eq!(parse(&a).unwrap_err().to_string(), *message);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use token::TokenKind;
+use instruction::Instruction;
+use symbol_table::SymbolTable;
+use lint::{self, AValue};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Label,
+    Variable,
+    Predefined,
+    /// A RAM symbol from another build
+    Imported,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Label => write!(f, "label"),
+            Kind::Variable => write!(f, "variable"),
+            Kind::Predefined => write!(f, "predefined"),
+            Kind::Imported => write!(f, "imported"),
+        }
+    }
+}
+
+/// Everything known about how a single symbol is used
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub kind: Kind,
+    pub address: u16,
+    /// The line a label or `.var` is declared on, or an undeclared variable
+    /// is first allocated on
+    pub definition: Option<u32>,
+    /// Every line loading the symbol into A
+    pub references: Vec<u32>,
+    /// How many times M is read while A holds the symbol
+    pub reads: usize,
+    /// How many times M is written while A holds the symbol
+    pub writes: usize,
+}
+
+impl Entry {
+    fn ram(&self) -> bool {
+        self.kind != Kind::Label
+    }
+}
+
+/// A cross-reference of every symbol in a program's symbol table, used or
+/// not, ordered by name
+pub struct CrossReference {
+    pub entries: Vec<Entry>,
+}
+
+impl CrossReference {
+    pub fn build(instructions: &[Instruction], symbols: &SymbolTable, declarations: &[(String, u32)]) -> CrossReference {
+        let mut entries: BTreeMap<String, Entry> = BTreeMap::new();
+        for (name, _) in symbols.ram_symbols().into_iter().chain(symbols.labels()) {
+            entry(&mut entries, symbols, name);
+        }
+        for (name, line) in declarations {
+            if let Some(e) = entry(&mut entries, symbols, name) {
+                e.definition = Some(*line);
+            }
+        }
+
+        for (i, a) in instructions.iter().zip(lint::track_a(instructions, symbols)) {
+            match i {
+                Instruction::Label(_) => {
+                    if let Some(e) = i.symbol_string().and_then(|s| entry(&mut entries, symbols, s)) {
+                        e.definition = Some(i.line());
+                    }
+                },
+                Instruction::AInstruction(t) => {
+                    if let TokenKind::Symbol(ref s) = t.kind {
+                        if let Some(e) = entry(&mut entries, symbols, s) {
+                            if e.kind == Kind::Variable && e.definition.is_none() {
+                                e.definition = Some(t.line);
+                            }
+                            e.references.push(t.line);
+                        }
+                    }
+                },
+                Instruction::CInstruction { dest, comp, jump: _ } => {
+                    let name = match a {
+                        AValue::Label(ref s) | AValue::Variable(ref s) => s,
+                        _ => continue,
+                    };
+                    if let Some(e) = entry(&mut entries, symbols, name) {
+                        if comp.reads_memory() {
+                            e.reads += 1;
+                        }
+                        if dest.iter().any(|t| t.kind == TokenKind::Memory) {
+                            e.writes += 1;
+                        }
+                    }
+                },
+            }
+        }
+
+        CrossReference { entries: entries.into_values().collect() }
+    }
+
+    /// Renders the cross-reference and the annotated source as a single HTML
+    /// page, linking every use of a symbol to its entry and every entry back
+    /// to the lines that use it
+    pub fn to_html(&self, title: &str, source: &str) -> String {
+        let mut line_symbols: BTreeMap<u32, &str> = BTreeMap::new();
+        for e in &self.entries {
+            for &line in e.references.iter().chain(e.definition.iter()) {
+                line_symbols.insert(line, &e.name);
+            }
+        }
+
+        let mut out = String::new();
+        out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        out.push_str(&format!("<title>{}</title>\n", escape(title)));
+        out.push_str("<style>\nbody { font-family: monospace; }\n:target { background: #ffa; }\n");
+        out.push_str("td, th { padding: 0 1em 0 0; text-align: left; vertical-align: top; }\n");
+        out.push_str(".line { color: #999; display: inline-block; width: 6em; }\n</style>\n</head>\n<body>\n");
+        out.push_str(&format!("<h1>{}</h1>\n", escape(title)));
+
+        out.push_str("<h2>Symbols</h2>\n<table>\n");
+        out.push_str("<tr><th>Symbol</th><th>Kind</th><th>Address</th><th>Defined</th><th>Reads</th><th>Writes</th><th>References</th></tr>\n");
+        for e in &self.entries {
+            let line_link = |l: &u32| format!("<a href=\"#L{0}\">{0}</a>", l);
+            out.push_str(&format!(
+                "<tr id=\"sym-{name}\"><td>{name}</td><td>{kind}</td><td>{space} {address}</td><td>{definition}</td><td>{reads}</td><td>{writes}</td><td>{references}</td></tr>\n",
+                name = escape(&e.name),
+                kind = e.kind,
+                space = if e.ram() { "RAM" } else { "ROM" },
+                address = e.address,
+                definition = e.definition.as_ref().map(line_link).unwrap_or_default(),
+                reads = e.reads,
+                writes = e.writes,
+                references = e.references.iter().map(line_link).collect::<Vec<String>>().join(", "),
+            ));
+        }
+        out.push_str("</table>\n");
+
+        out.push_str("<h2>Source</h2>\n<pre>\n");
+        for (i, text) in source.lines().enumerate() {
+            let line = i as u32 + 1;
+            let mut text = escape(text);
+            if let Some(name) = line_symbols.get(&line) {
+                let name = escape(name);
+                for prefix in &["@", "("] {
+                    let from = format!("{}{}", prefix, name);
+                    if let Some(start) = text.find(&from) {
+                        let end = start + from.len();
+                        text = format!("{}{}<a href=\"#sym-{}\">{}</a>{}", &text[..start], prefix, name, name, &text[end..]);
+                        break;
+                    }
+                }
+            }
+            out.push_str(&format!("<span id=\"L{0}\"><span class=\"line\">{0}</span>{1}</span>\n", line, text));
+        }
+        out.push_str("</pre>\n</body>\n</html>\n");
+        out
+    }
+}
+
+fn entry<'a>(entries: &'a mut BTreeMap<String, Entry>, symbols: &SymbolTable, name: &str) -> Option<&'a mut Entry> {
+    let address = symbols.get(name)?;
+    Some(entries.entry(name.to_string()).or_insert_with(|| Entry {
+        name: name.to_string(),
+        kind: if symbols.is_label(name) {
+            Kind::Label
+        } else if symbols.variables().iter().any(|v| v == name) {
+            Kind::Variable
+        } else if symbols.is_imported(name) {
+            Kind::Imported
+        } else {
+            Kind::Predefined
+        },
+        address,
+        definition: None,
+        references: Vec::new(),
+        reads: 0,
+        writes: 0,
+    }))
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+impl fmt::Display for CrossReference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.entries.iter().map(|e| e.name.len()).max().unwrap_or(0).max(6);
+        writeln!(f, "{:w$}  {:10}  {:>9}  {:>7}  {:>5}  {:>6}  References", "Symbol", "Kind", "Address", "Defined", "Reads", "Writes", w = width)?;
+        for e in &self.entries {
+            let address = format!("{} {}", if e.ram() { "RAM" } else { "ROM" }, e.address);
+            let definition = e.definition.map(|l| l.to_string()).unwrap_or_default();
+            let references: Vec<String> = e.references.iter().map(|l| l.to_string()).collect();
+            writeln!(f, "{:w$}  {:10}  {:>9}  {:>7}  {:>5}  {:>6}  {}",
+                e.name, e.kind.to_string(), address, definition, e.reads, e.writes, references.join(", "), w = width)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Assembler;
+    use symbol_file::parse;
+    use xref::{CrossReference, Entry, Kind};
+
+    const SOURCE: &str = "@ball_x\nM=0\n(LOOP)\n@ball_x\nD=M\nM=D+1\n@SCREEN\nM=D\n@LOOP\n0;JMP\n";
+
+    fn cross_reference() -> CrossReference {
+        let mut a = Assembler::new();
+        a.assemble(SOURCE.as_bytes()).unwrap();
+        CrossReference::build(a.instructions(), a.symbols(), &a.declarations())
+    }
+
+    fn find<'a>(xref: &'a CrossReference, name: &str) -> &'a Entry {
+        xref.entries.iter().find(|e| e.name == name).unwrap()
+    }
+
+    #[test]
+    fn entries() {
+        let xref = cross_reference();
+        let names: Vec<&str> = xref.entries.iter().map(|e| e.name.as_ref()).collect();
+        assert_eq!(names[..3], ["ARG", "KBD", "LCL"]);
+        assert_eq!(names.len(), 25);
+
+        let ball_x = find(&xref, "ball_x");
+        assert_eq!(ball_x.kind, Kind::Variable);
+        assert_eq!(ball_x.address, 16);
+        assert_eq!(ball_x.definition, Some(1));
+        assert_eq!(ball_x.references, vec![1, 4]);
+        assert_eq!((ball_x.reads, ball_x.writes), (1, 2));
+
+        let screen = find(&xref, "SCREEN");
+        assert_eq!(screen.kind, Kind::Predefined);
+        assert_eq!((screen.definition, screen.writes), (None, 1));
+        assert!(find(&xref, "R13").references.is_empty());
+
+        assert_eq!(find(&xref, "LOOP").definition, Some(3));
+    }
+
+    #[test]
+    fn unused_symbols() {
+        // Declared and imported symbols are listed whether used or not
+        let mut a = Assembler::new();
+        a.import(&parse("score = 100\nlabel.PLAY = 300\n").unwrap()).unwrap();
+        a.assemble("@counter\nM=0\n.var counter\n.var spare\n".as_bytes()).unwrap();
+        let xref = CrossReference::build(a.instructions(), a.symbols(), &a.declarations());
+        assert_eq!(find(&xref, "counter").definition, Some(3));
+        assert_eq!(find(&xref, "spare").definition, Some(4));
+        assert_eq!(find(&xref, "score").kind, Kind::Imported);
+        assert_eq!(find(&xref, "PLAY").kind, Kind::Label);
+    }
+
+    #[test]
+    fn html_links() {
+        let html = cross_reference().to_html("test.asm", SOURCE);
+        assert!(html.contains("<tr id=\"sym-ball_x\">"));
+        assert!(html.contains("<a href=\"#L4\">4</a>"));
+        assert!(html.contains("<span id=\"L4\"><span class=\"line\">4</span>@<a href=\"#sym-ball_x\">ball_x</a></span>"));
+    }
+}
@@ -0,0 +1,11 @@
+// Tests Add.asm, which computes RAM[0] = 2 + 3
+
+load Add.hack,
+output-file Add.out,
+compare-to Add.cmp,
+output-list RAM[0]%D2.6.2;
+
+repeat 6 {
+  ticktock;
+}
+output;
@@ -0,0 +1,23 @@
+// Tests Max.asm, which computes RAM[2] = max(RAM[0], RAM[1])
+
+load Max.hack,
+output-file Max.out,
+compare-to Max.cmp,
+output-list RAM[0]%D2.6.2 RAM[1]%D2.6.2 RAM[2]%D2.6.2;
+
+set RAM[0] 3,   // Set test arguments
+set RAM[1] 5,
+set RAM[2] 0;
+repeat 14 {
+  ticktock;
+}
+output;
+
+set PC 0,
+set RAM[0] 23456,
+set RAM[1] 12345,
+set RAM[2] 0;
+repeat 14 {
+  ticktock;
+}
+output;
@@ -0,0 +1,12 @@
+// Tests Rect.asm, which draws a 16 pixel wide rectangle RAM[0] rows high
+
+load Rect.hack,
+output-file Rect.out,
+compare-to Rect.cmp,
+output-list RAM[0]%D2.6.2 RAM[16384]%D2.6.2 RAM[16416]%D2.6.2 RAM[16448]%D2.6.2 RAM[16480]%D2.6.2 RAM[16512]%D2.6.2;
+
+set RAM[0] 4;
+while PC <> 23 {
+  ticktock;
+}
+output;